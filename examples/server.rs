@@ -42,7 +42,7 @@ fn main() {
     // Start the server.
     let mut server = Server::new(addr, parts, Config::default()).expect("Failed to create server.");
 
-    let blacklisted_users = vec!["john", "jane"];
+    let blacklisted_users = ["john", "jane"];
 
     // This represents the game loop in your favorite game engine.
     loop {
@@ -0,0 +1,327 @@
+//! Filtering of incoming connections.
+//!
+//! Before a new TCP connection becomes a pending connection (and before it is given a [`CId`]),
+//! it is checked against the [`AcceptPolicy`] of the server. Connections that are not accepted are
+//! closed right away, without a response, so that they never take up one of the
+//! [`Config::max_con_handle`](crate::net::Config::max_con_handle) slots.
+//!
+//! The policy can:
+//! - Ban or allow ranges of addresses with [`IpNet`]s.
+//! - Limit the number of pending connections from one IP address.
+//! - Throttle the connection attempts from one IP address.
+//! - Run a custom filter.
+//!
+//! The policy is set with [`Server::set_accept_policy()`](crate::Server::set_accept_policy).
+//!
+//! [`CId`]: crate::CId
+
+use crate::bandwidth::TokenBucket;
+use crate::rate_limit::RateLimit;
+use hashbrown::HashMap;
+use log::debug;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// The number of throttled addresses after which the idle ones are forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// A range of IP addresses, in CIDR notation.
+///
+/// Can be parsed from a string like `"10.0.0.0/8"`, or `"::1"` for a single address.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Creates a new [`IpNet`] containing all addresses that share the first `prefix_len` bits with
+    /// `addr`.
+    ///
+    /// Returns an error if `prefix_len` is bigger than the number of bits in the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self, IpNetError> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return Err(IpNetError::PrefixTooLong { prefix_len, max });
+        }
+        Ok(IpNet { addr, prefix_len })
+    }
+
+    /// Gets the address of this range.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Gets the number of leading bits that are shared by all the addresses in this range.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `addr` is in this range.
+    ///
+    /// An IPv4 address is never in an IPv6 range, and the other way around.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => Self::prefix_eq(
+                u32::from(net) as u128,
+                u32::from(addr) as u128,
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                Self::prefix_eq(u128::from(net), u128::from(addr), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+
+    /// Compares the first `prefix_len` bits of two addresses that are `bits` long.
+    fn prefix_eq(a: u128, b: u128, bits: u8, prefix_len: u8) -> bool {
+        let shift = (bits - prefix_len) as u32;
+        a.checked_shr(shift).unwrap_or(0) == b.checked_shr(shift).unwrap_or(0)
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        IpNet { addr, prefix_len }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpNetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IpNetError::Invalid(s.to_owned());
+        match s.split_once('/') {
+            Some((addr, prefix_len)) => {
+                let addr = addr.parse().map_err(|_| invalid())?;
+                let prefix_len = prefix_len.parse().map_err(|_| invalid())?;
+                IpNet::new(addr, prefix_len)
+            }
+            None => Ok(IpNet::from(s.parse::<IpAddr>().map_err(|_| invalid())?)),
+        }
+    }
+}
+
+impl Display for IpNet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// The possible errors when creating or parsing an [`IpNet`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum IpNetError {
+    /// The string is not an IP address or range.
+    Invalid(String),
+    /// The prefix length is bigger than the number of bits in the address.
+    PrefixTooLong {
+        /// The prefix length that was given.
+        prefix_len: u8,
+        /// The number of bits in the address.
+        max: u8,
+    },
+}
+
+impl Display for IpNetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpNetError::Invalid(s) => write!(f, "Invalid IP range \"{}\".", s),
+            IpNetError::PrefixTooLong { prefix_len, max } => write!(
+                f,
+                "Prefix length {} is too long. The maximum is {}.",
+                prefix_len, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IpNetError {}
+
+/// A custom filter for incoming connections. Returns whether the connection should be accepted.
+pub type AcceptFilter = Box<dyn FnMut(SocketAddr) -> bool + Send + Sync>;
+
+/// The rules for accepting incoming connections.
+///
+/// See the [module level docs](self) for more.
+#[derive(Default)]
+pub struct AcceptPolicy {
+    /// Connections from these ranges are always rejected.
+    pub banned: Vec<IpNet>,
+    /// If this is not empty, only connections from these ranges are accepted.
+    pub allowed: Vec<IpNet>,
+    /// The maximum number of pending connections from one IP address. `None` means unlimited.
+    pub max_pending_per_ip: Option<usize>,
+    /// The maximum rate of connection attempts from one IP address. `None` means unlimited.
+    ///
+    /// Rejected attempts count towards the limit.
+    pub attempt_limit: Option<RateLimit>,
+    /// A custom filter, that is run after all the other rules.
+    pub filter: Option<AcceptFilter>,
+}
+
+impl Debug for AcceptPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptPolicy")
+            .field("banned", &self.banned)
+            .field("allowed", &self.allowed)
+            .field("max_pending_per_ip", &self.max_pending_per_ip)
+            .field("attempt_limit", &self.attempt_limit)
+            .field("filter", &self.filter.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl AcceptPolicy {
+    /// Creates a new [`AcceptPolicy`] that accepts everything.
+    pub fn new() -> Self {
+        AcceptPolicy::default()
+    }
+}
+
+/// The reason that an incoming connection was rejected by the [`AcceptPolicy`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum Rejection {
+    /// The address is in a banned range.
+    Banned,
+    /// The address is not in an allowed range.
+    NotAllowed,
+    /// The address has too many pending connections.
+    TooManyPending,
+    /// The address made too many connection attempts.
+    Throttled,
+    /// The custom filter rejected the connection.
+    Filtered,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Banned => write!(f, "the address is banned"),
+            Self::NotAllowed => write!(f, "the address is not allowed"),
+            Self::TooManyPending => write!(f, "too many pending connections"),
+            Self::Throttled => write!(f, "too many connection attempts"),
+            Self::Filtered => write!(f, "rejected by the filter"),
+        }
+    }
+}
+
+/// Applies the [`AcceptPolicy`] and keeps track of the connection attempts.
+#[derive(Debug, Default)]
+pub(crate) struct AcceptGuard {
+    pub(crate) policy: AcceptPolicy,
+    /// The connection attempt bucket of each address.
+    attempts: HashMap<IpAddr, TokenBucket>,
+    /// The number of rejected connection attempts.
+    rejected: u64,
+}
+
+impl AcceptGuard {
+    /// Sets the policy, and forgets the connection attempts.
+    pub(crate) fn set_policy(&mut self, policy: AcceptPolicy) {
+        self.policy = policy;
+        self.attempts.clear();
+    }
+
+    /// Gets the number of rejected connection attempts.
+    pub(crate) fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Checks a connection attempt from `addr`, which already has `pending` pending connections
+    /// from the same IP address.
+    pub(crate) fn check(&mut self, addr: SocketAddr, pending: usize) -> Result<(), Rejection> {
+        let result = self.check_inner(addr, pending);
+        if let Err(rejection) = result {
+            self.rejected += 1;
+            debug!("Rejected connection attempt from {}: {}.", addr, rejection);
+        }
+        result
+    }
+
+    /// The checking logic.
+    fn check_inner(&mut self, addr: SocketAddr, pending: usize) -> Result<(), Rejection> {
+        let ip = addr.ip();
+        let policy = &mut self.policy;
+
+        if policy.banned.iter().any(|net| net.contains(ip)) {
+            return Err(Rejection::Banned);
+        }
+        if !policy.allowed.is_empty() && !policy.allowed.iter().any(|net| net.contains(ip)) {
+            return Err(Rejection::NotAllowed);
+        }
+        if let Some(limit) = policy.attempt_limit {
+            if self.attempts.len() > PRUNE_THRESHOLD {
+                self.attempts.retain(|_, bucket| {
+                    bucket.refill();
+                    !bucket.is_full()
+                });
+            }
+            let bucket = self
+                .attempts
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::new(limit.msgs_per_sec, limit.burst));
+            bucket.refill();
+            if !bucket.try_take(1) {
+                return Err(Rejection::Throttled);
+            }
+        }
+        if policy.max_pending_per_ip.is_some_and(|max| pending >= max) {
+            return Err(Rejection::TooManyPending);
+        }
+        if let Some(filter) = &mut policy.filter {
+            if !filter(addr) {
+                return Err(Rejection::Filtered);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accept::{IpNet, IpNetError};
+    use std::net::IpAddr;
+
+    #[test]
+    fn ip_net() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+
+        let all: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("255.255.255.255".parse().unwrap()));
+
+        let single: IpNet = "::1".parse().unwrap();
+        assert_eq!(single.prefix_len(), 128);
+        assert!(single.contains(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])));
+        assert!(!single.contains(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 2])));
+
+        let v6: IpNet = "fe80::/10".parse().unwrap();
+        assert!(v6.contains("fe80::1234".parse().unwrap()));
+        assert!(!v6.contains("fec0::1".parse().unwrap()));
+
+        assert_eq!(
+            "10.0.0.0/33".parse::<IpNet>(),
+            Err(IpNetError::PrefixTooLong {
+                prefix_len: 33,
+                max: 32
+            })
+        );
+        assert_eq!(
+            "10.0.0/8".parse::<IpNet>(),
+            Err(IpNetError::Invalid("10.0.0/8".to_owned()))
+        );
+        assert_eq!(net.to_string(), "10.1.0.0/16");
+    }
+}
@@ -0,0 +1,508 @@
+//! Bandwidth limiting for outgoing messages.
+//!
+//! Every connection has a token bucket that is refilled at a constant rate of
+//! [`BandwidthLimit::bytes_per_sec`], up to a maximum of [`BandwidthLimit::burst`] bytes. Sending
+//! a message takes as many tokens as there are bytes in the message (including the header).
+//!
+//! TCP messages are always sent, but they still take tokens from the bucket. UDP messages that do
+//! not fit in the budget are either dropped or queued, depending on [`BandwidthLimit::over_budget`].
+//! Queued messages are sent by calling `send_queued()` on the client or server.
+//!
+//! When queued messages are sent, the ones with the highest priority go first, and the ones whose
+//! time-to-live has run out are discarded (see [`MsgOptions`]).
+//!
+//! A server can also have a total limit, set with
+//! [`Config::total_bandwidth`](crate::net::Config::total_bandwidth). It is a token bucket that is
+//! shared by all connections, so a message is only sent when both its connection's bucket and the
+//! shared bucket have enough tokens. UDP messages that are over the total budget are dropped or
+//! queued depending on the [`OverBudget`] of the total limit.
+
+use crate::message_table::MsgOptions;
+use crate::{MId, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A send rate limit for a connection.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BandwidthLimit {
+    /// The sustained send rate in bytes per second.
+    pub bytes_per_sec: u32,
+    /// The maximum number of bytes that can be sent at once, after the connection was idle.
+    pub burst: u32,
+    /// What to do with UDP messages that are over the budget.
+    pub over_budget: OverBudget,
+}
+
+impl BandwidthLimit {
+    /// Creates a new [`BandwidthLimit`].
+    pub fn new(bytes_per_sec: u32, burst: u32, over_budget: OverBudget) -> Self {
+        BandwidthLimit {
+            bytes_per_sec,
+            burst,
+            over_budget,
+        }
+    }
+}
+
+/// What to do with a UDP message that is sent while the connection is over its budget.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum OverBudget {
+    /// Discard the message.
+    Drop,
+    /// Keep the message until there is enough budget to send it.
+    Queue,
+}
+
+/// The state of a connection's bandwidth limiter.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct BandwidthStats {
+    /// The number of bytes that can currently be sent without going over the budget.
+    ///
+    /// This is `None` if the connection is not limited.
+    pub available: Option<u32>,
+    /// The total number of bytes sent.
+    pub sent_bytes: u64,
+    /// The total number of messages sent.
+    pub sent_msgs: u64,
+    /// The total number of UDP messages dropped for being over the budget.
+    pub dropped_msgs: u64,
+    /// The total number of queued UDP messages discarded because their time-to-live ran out.
+    pub expired_msgs: u64,
+    /// The number of UDP messages currently waiting to be sent.
+    pub queued_msgs: usize,
+}
+
+/// A token bucket.
+///
+/// Refilled with `rate` tokens per second, up to a maximum of `burst` tokens.
+#[derive(Clone, Debug)]
+pub(crate) struct TokenBucket {
+    rate: u32,
+    burst: u32,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a new, full, [`TokenBucket`].
+    pub(crate) fn new(rate: u32, burst: u32) -> Self {
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Adds the tokens that accumulated since the last refill.
+    pub(crate) fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.burst as f64);
+    }
+
+    /// Returns whether the bucket is full, as of the last refill.
+    pub(crate) fn is_full(&self) -> bool {
+        self.tokens >= self.burst as f64
+    }
+
+    /// Takes `n` tokens if there are enough available. Returns whether the tokens were taken.
+    pub(crate) fn try_take(&mut self, n: usize) -> bool {
+        if self.can_take(n) {
+            self.take_saturating(n);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns whether `n` tokens can be taken.
+    ///
+    /// Messages bigger than the burst size are let through when the bucket is full, so that they
+    /// don't get stuck forever.
+    fn can_take(&self, n: usize) -> bool {
+        self.tokens >= n as f64 || self.tokens >= self.burst as f64
+    }
+
+    /// Takes `n` tokens, or as many as are available.
+    fn take_saturating(&mut self, n: usize) {
+        self.tokens = (self.tokens - n as f64).max(0.0);
+    }
+}
+
+/// A limit with its token bucket.
+#[derive(Clone, Debug)]
+pub(crate) struct Budget {
+    limit: BandwidthLimit,
+    bucket: TokenBucket,
+}
+
+impl Budget {
+    /// Creates a new [`Budget`], with a full bucket.
+    fn new(limit: BandwidthLimit) -> Self {
+        Budget {
+            limit,
+            bucket: TokenBucket::new(limit.bytes_per_sec, limit.burst),
+        }
+    }
+}
+
+/// The [`Budget`] that is shared by all connections of a server, if it has a total limit.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedBudget(Arc<Mutex<Option<Budget>>>);
+
+impl SharedBudget {
+    /// Creates a new [`SharedBudget`] with the limit `limit`.
+    pub(crate) fn new(limit: Option<BandwidthLimit>) -> Self {
+        SharedBudget(Arc::new(Mutex::new(limit.map(Budget::new))))
+    }
+
+    /// Changes the limit. This is seen by all connections that share the budget.
+    pub(crate) fn set_limit(&self, limit: Option<BandwidthLimit>) {
+        *self.0.lock().unwrap() = limit.map(Budget::new);
+    }
+
+    /// Gets the current limit.
+    pub(crate) fn limit(&self) -> Option<BandwidthLimit> {
+        self.0.lock().unwrap().as_ref().map(|b| b.limit)
+    }
+
+    /// Gets the number of bytes that can currently be sent, or `None` if there is no limit.
+    pub(crate) fn available(&self) -> Option<u32> {
+        let mut budget = self.0.lock().unwrap();
+        let budget = budget.as_mut()?;
+        budget.bucket.refill();
+        Some(budget.bucket.tokens as u32)
+    }
+}
+
+/// A UDP message waiting to be sent.
+#[derive(Clone, Debug)]
+struct Queued {
+    mid: MId,
+    payload: Vec<u8>,
+    priority: u8,
+    expires: Option<Instant>,
+}
+
+/// The outgoing side of a connection.
+///
+/// Keeps track of the bandwidth budget, and schedules the UDP messages waiting to be sent.
+#[derive(Clone, Debug)]
+pub(crate) struct Limiter {
+    limit: Option<BandwidthLimit>,
+    bucket: Option<TokenBucket>,
+    /// Whether all UDP messages are queued until the next flush.
+    schedule: bool,
+    queue: VecDeque<Queued>,
+    stats: BandwidthStats,
+    /// The budget shared with the other connections.
+    shared: SharedBudget,
+}
+
+impl Limiter {
+    /// Creates a new [`Limiter`] with the given limit, that also takes from the budget `shared`.
+    ///
+    /// If `schedule` is set, all UDP messages are queued until the next flush.
+    pub(crate) fn new(limit: Option<BandwidthLimit>, schedule: bool, shared: SharedBudget) -> Self {
+        Limiter {
+            limit,
+            bucket: limit.map(|l| TokenBucket::new(l.bytes_per_sec, l.burst)),
+            schedule,
+            queue: VecDeque::new(),
+            stats: BandwidthStats::default(),
+            shared,
+        }
+    }
+
+    /// Changes the limit of this connection.
+    ///
+    /// If the limit is removed, all queued messages will be sent on the next flush.
+    pub(crate) fn set_limit(&mut self, limit: Option<BandwidthLimit>) {
+        self.limit = limit;
+        self.bucket = limit.map(|l| TokenBucket::new(l.bytes_per_sec, l.burst));
+    }
+
+    /// Gets the current limit of this connection.
+    pub(crate) fn limit(&self) -> Option<BandwidthLimit> {
+        self.limit
+    }
+
+    /// Gets the current stats.
+    pub(crate) fn stats(&mut self) -> BandwidthStats {
+        if let Some(bucket) = &mut self.bucket {
+            bucket.refill();
+        }
+        BandwidthStats {
+            available: self.bucket.as_ref().map(|b| b.tokens as u32),
+            queued_msgs: self.queue.len(),
+            ..self.stats
+        }
+    }
+
+    /// Records a TCP message of `len` bytes (including the header) being sent.
+    pub(crate) fn record_tcp(&mut self, len: usize) {
+        if let Some(bucket) = &mut self.bucket {
+            bucket.refill();
+            bucket.take_saturating(len);
+        }
+        if let Some(shared) = self.shared.0.lock().unwrap().as_mut() {
+            shared.bucket.refill();
+            shared.bucket.take_saturating(len);
+        }
+        self.stats.sent_bytes += len as u64;
+        self.stats.sent_msgs += 1;
+    }
+
+    /// Takes `n` tokens from both this connection's bucket and the shared one, if both have
+    /// enough.
+    ///
+    /// Otherwise, nothing is taken, and the limit that is over its budget is returned.
+    fn try_take(
+        bucket: &mut Option<TokenBucket>,
+        limit: Option<BandwidthLimit>,
+        shared: &mut Option<Budget>,
+        n: usize,
+    ) -> std::result::Result<(), BandwidthLimit> {
+        if let (Some(bucket), Some(limit)) = (bucket.as_mut(), limit) {
+            bucket.refill();
+            if !bucket.can_take(n) {
+                return Err(limit);
+            }
+        }
+        if let Some(shared) = shared {
+            shared.bucket.refill();
+            if !shared.bucket.can_take(n) {
+                return Err(shared.limit);
+            }
+        }
+        if let Some(bucket) = bucket {
+            bucket.take_saturating(n);
+        }
+        if let Some(shared) = shared {
+            shared.bucket.take_saturating(n);
+        }
+        Ok(())
+    }
+
+    /// Sends a UDP message through `send` if it is within budget. Otherwise it is dropped or
+    /// queued.
+    ///
+    /// `len` is the size of the message on the wire (including the header).
+    pub(crate) fn send_udp(
+        &mut self,
+        mid: MId,
+        payload: &[u8],
+        len: usize,
+        options: MsgOptions,
+        send: impl FnOnce(MId, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        if self.schedule {
+            self.enqueue(mid, payload, options);
+            return Ok(());
+        }
+
+        // Don't let new messages skip the queue.
+        let over = match self.queue.front() {
+            Some(_) => self.limit.or(self.shared.limit()),
+            None => {
+                let mut shared = self.shared.0.lock().unwrap();
+                Self::try_take(&mut self.bucket, self.limit, &mut shared, len).err()
+            }
+        };
+
+        match over.map(|l| l.over_budget) {
+            None => {
+                send(mid, payload)?;
+                self.stats.sent_bytes += len as u64;
+                self.stats.sent_msgs += 1;
+            }
+            Some(OverBudget::Drop) => self.stats.dropped_msgs += 1,
+            Some(OverBudget::Queue) => self.enqueue(mid, payload, options),
+        }
+        Ok(())
+    }
+
+    /// Adds a message to the back of the queue.
+    fn enqueue(&mut self, mid: MId, payload: &[u8], options: MsgOptions) {
+        self.queue.push_back(Queued {
+            mid,
+            payload: payload.to_vec(),
+            priority: options.priority,
+            expires: options.ttl.map(|ttl| Instant::now() + ttl),
+        });
+    }
+
+    /// Sends as many of the queued UDP messages as the budget allows, by passing them all to
+    /// `send` at once.
+    ///
+    /// Expired messages are discarded, then the rest are sent from the highest to the lowest
+    /// priority. Messages with the same priority are sent in the order they were queued.
+    /// If the limit that ran out uses [`OverBudget::Drop`], the messages that did not fit are
+    /// discarded.
+    ///
+    /// `header_len` is the size of the header that is added to every message.
+    ///
+    /// Returns the number of messages sent.
+    pub(crate) fn flush(
+        &mut self,
+        header_len: usize,
+        send: impl FnOnce(&[(MId, Vec<u8>)]) -> Result<()>,
+    ) -> Result<u32> {
+        let now = Instant::now();
+        let before = self.queue.len();
+        self.queue
+            .retain(|q| q.expires.is_none_or(|expires| expires > now));
+        self.stats.expired_msgs += (before - self.queue.len()) as u64;
+
+        // The sort is stable, so equal priorities keep their order.
+        self.queue
+            .make_contiguous()
+            .sort_by_key(|q| Reverse(q.priority));
+
+        let mut msgs = vec![];
+        let mut len = 0;
+        let mut over = None;
+        let mut shared = self.shared.0.lock().unwrap();
+        while let Some(queued) = self.queue.front() {
+            let msg_len = queued.payload.len() + header_len;
+            if let Err(limit) = Self::try_take(&mut self.bucket, self.limit, &mut shared, msg_len) {
+                over = Some(limit);
+                break;
+            }
+            let queued = self.queue.pop_front().unwrap();
+            msgs.push((queued.mid, queued.payload));
+            len += msg_len;
+        }
+        drop(shared);
+
+        if over.map(|l| l.over_budget) == Some(OverBudget::Drop) {
+            self.stats.dropped_msgs += self.queue.len() as u64;
+            self.queue.clear();
+        }
+
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+        send(&msgs)?;
+        self.stats.sent_bytes += len as u64;
+        self.stats.sent_msgs += msgs.len() as u64;
+
+        Ok(msgs.len() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bandwidth::{BandwidthLimit, Limiter, OverBudget, SharedBudget};
+    use crate::message_table::MsgOptions;
+    use std::time::Duration;
+
+    #[test]
+    fn drop_over_budget() {
+        let mut limiter = Limiter::new(
+            Some(BandwidthLimit::new(10, 100, OverBudget::Drop)),
+            false,
+            SharedBudget::default(),
+        );
+
+        let mut sent = 0;
+        for _ in 0..4 {
+            limiter
+                .send_udp(3, &[0; 36], 40, MsgOptions::default(), |_, _| {
+                    sent += 1;
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        let stats = limiter.stats();
+        assert_eq!(sent, 2);
+        assert_eq!(stats.sent_msgs, 2);
+        assert_eq!(stats.sent_bytes, 80);
+        assert_eq!(stats.dropped_msgs, 2);
+        assert_eq!(stats.queued_msgs, 0);
+    }
+
+    #[test]
+    fn queue_over_budget() {
+        let mut limiter = Limiter::new(
+            Some(BandwidthLimit::new(10, 100, OverBudget::Queue)),
+            false,
+            SharedBudget::default(),
+        );
+
+        for _ in 0..4 {
+            limiter
+                .send_udp(3, &[0; 36], 40, MsgOptions::default(), |_, _| Ok(()))
+                .unwrap();
+        }
+        assert_eq!(limiter.stats().queued_msgs, 2);
+
+        // Still no budget.
+        assert_eq!(limiter.flush(4, |_| Ok(())).unwrap(), 0);
+
+        // Removing the limit lets everything through.
+        limiter.set_limit(None);
+        assert_eq!(limiter.flush(4, |_| Ok(())).unwrap(), 2);
+        assert_eq!(limiter.stats().sent_msgs, 4);
+    }
+
+    #[test]
+    fn priority_and_ttl() {
+        let mut limiter = Limiter::new(None, true, SharedBudget::default());
+
+        let low = MsgOptions::new(0, None);
+        let high = MsgOptions::new(10, None);
+        let expired = MsgOptions::new(20, Some(Duration::ZERO));
+        for (mid, options) in [(3, low), (4, high), (5, expired), (6, low), (7, high)] {
+            limiter
+                .send_udp(mid, &[0; 4], 8, options, |_, _| panic!("Sent before flush"))
+                .unwrap();
+        }
+
+        let mut sent = vec![];
+        limiter
+            .flush(4, |msgs| {
+                sent.extend(msgs.iter().map(|(mid, _)| *mid));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(sent, vec![4, 7, 3, 6]);
+        assert_eq!(limiter.stats().expired_msgs, 1);
+    }
+
+    #[test]
+    fn shared_budget() {
+        let shared = SharedBudget::new(Some(BandwidthLimit::new(10, 100, OverBudget::Drop)));
+        let mut a = Limiter::new(None, false, shared.clone());
+        let mut b = Limiter::new(None, true, shared.clone());
+
+        for _ in 0..2 {
+            a.send_udp(3, &[0; 36], 40, MsgOptions::default(), |_, _| Ok(()))
+                .unwrap();
+        }
+        assert_eq!(shared.available(), Some(20));
+
+        // The other connection is over the total budget, so its messages are dropped.
+        b.send_udp(3, &[0; 36], 40, MsgOptions::default(), |_, _| Ok(()))
+            .unwrap();
+        assert_eq!(b.flush(4, |_| Ok(())).unwrap(), 0);
+        assert_eq!(b.stats().dropped_msgs, 1);
+
+        // TCP messages take from the shared budget too.
+        a.record_tcp(20);
+        assert_eq!(shared.available(), Some(0));
+
+        // Removing the limit lets everything through.
+        shared.set_limit(None);
+        a.send_udp(3, &[0; 36], 40, MsgOptions::default(), |_, _| Ok(()))
+            .unwrap();
+        assert_eq!(a.stats().sent_msgs, 4);
+    }
+}
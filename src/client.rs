@@ -1,17 +1,34 @@
-use crate::message_table::{MsgTableParts, DISCONNECT_TYPE_MID, RESPONSE_TYPE_MID};
-use crate::net::{Config, ErasedNetMsg, NetMsg, Status, Transport};
+use crate::bandwidth::{BandwidthLimit, BandwidthStats, Limiter, SharedBudget};
+use crate::compression::{compress, decompress};
+use crate::header::{check_size, prepend_tick, split_tick, TCP_HEADER_LEN, UDP_HEADER_LEN};
+use crate::message_table::{
+    MsgHandle, MsgTableParts, TypedParts, CONNECTION_TYPE_MID, DISCONNECT_TYPE_MID,
+    RESPONSE_TYPE_MID,
+};
+use crate::negotiate::{prepend_manifest, split_response, Negotiated};
+use crate::net::{
+    ordered_msgs, AnyNetMsg, Config, ErasedNetMsg, NetMsg, OwnedNetMsg, Status, Transport,
+    TypedStatus,
+};
+use crate::rpc::{call_id, CallHandle, CallIdFn, Request, RpcRequest, RpcResponse};
 use crate::tcp::TcpCon;
 use crate::udp::UdpCon;
-use crate::MId;
+use crate::version::Version;
+use crate::{Error, MId, Result};
 use crossbeam_channel::internal::SelectHandle;
 use crossbeam_channel::Receiver;
+use hashbrown::HashMap;
 use log::{debug, error, trace};
 use std::any::{type_name, Any, TypeId};
+use std::collections::VecDeque;
 use std::fmt::{Debug, Display, Formatter};
-use std::io;
-use std::io::ErrorKind::InvalidData;
-use std::io::{Error, ErrorKind};
+use std::io::ErrorKind;
+use std::marker::PhantomData;
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
 
 /// A Client connection.
 ///
@@ -22,17 +39,33 @@ use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 pub struct Client {
     /// The configuration of the client.
     config: Config,
+    /// The current simulation tick. Sent with every message if [`Config::tick_header`] is set.
+    tick: u32,
     /// The status of the client. Whether it is connected/disconnected etc.
     status: Status,
     /// The received message buffer.
     ///
     /// Each [`MId`] has its own vector.
-    msg_buff: Vec<Vec<ErasedNetMsg>>,
+    msg_buff: Vec<VecDeque<ErasedNetMsg>>,
+    /// The sequence number to give the next received message.
+    next_seq: u64,
 
     /// The TCP connection for this client.
     tcp: TcpCon,
     /// The UDP connection for this client.
     udp: UdpCon,
+    /// The bandwidth limiter for this client.
+    limiter: Mutex<Limiter>,
+    /// The id of the next remote procedure call.
+    next_call_id: AtomicU32,
+    /// The functions that get the call id of a response, for the [`MId`] of each response type
+    /// that was called.
+    call_ids: Mutex<HashMap<MId, CallIdFn>>,
+    /// The responses that were not polled yet, by call id, with the time they arrived.
+    #[allow(clippy::type_complexity)]
+    responses: Mutex<HashMap<u32, (Instant, Box<dyn Any + Send + Sync>)>>,
+    /// The message types that were matched with the server.
+    negotiated: Negotiated,
 
     /// The [`MsgTableParts`] to use for sending messages.
     parts: MsgTableParts,
@@ -54,19 +87,41 @@ impl Client {
         let (client_tx, client_rx) = crossbeam_channel::bounded(1);
 
         std::thread::spawn(move || {
-            client_tx.send(Self::new_blocking(peer, parts, config, con_msg))
+            let _ = client_tx.send(Self::new_blocking(peer, parts, config, con_msg));
         });
 
         PendingClient { channel: client_rx }
     }
 
+    /// Creates a new [`Client`] with [`TypedParts`], connecting to the server at `peer`.
+    ///
+    /// Unlike [`new()`](Self::new), the types of the connection message and the response are
+    /// checked by the compiler. See [`new()`](Self::new) for more.
+    pub fn connect<C, R, D, A>(
+        peer: A,
+        parts: TypedParts<C, R, D>,
+        config: Config,
+        con_msg: C,
+    ) -> TypedPendingClient<R, D>
+    where
+        C: Any + Send + Sync,
+        R: Any + Send + Sync,
+        D: Any + Send + Sync,
+        A: ToSocketAddrs + Send + 'static,
+    {
+        TypedPendingClient {
+            pending: Self::new(peer, parts.erase(), config, con_msg),
+            _types: PhantomData,
+        }
+    }
+
     /// Creates a new [`Client`] by blocking.
     fn new_blocking<C: Any + Send + Sync, A: ToSocketAddrs>(
         peer: A,
         parts: MsgTableParts,
         config: Config,
         con_msg: C,
-    ) -> io::Result<(Self, Box<dyn Any + Send + Sync>)> {
+    ) -> Result<(Self, Box<dyn Any + Send + Sync>)> {
         debug!("Attempting to create a client connection.");
         // TCP & UDP Connections.
         let tcp = TcpStream::connect(peer)?;
@@ -88,34 +143,50 @@ impl Client {
 
         let mut msg_buff = Vec::with_capacity(parts.mid_count());
         for _ in 0..parts.mid_count() {
-            msg_buff.push(vec![]);
+            msg_buff.push(VecDeque::new());
         }
 
         let mut client = Client {
             config,
+            tick: 0,
             status: Status::Connected,
             msg_buff,
+            next_seq: 0,
             tcp,
             udp,
+            limiter: Mutex::new(Limiter::new(
+                config.bandwidth,
+                config.schedule_udp || config.batch_udp,
+                SharedBudget::default(),
+            )),
+            next_call_id: AtomicU32::new(0),
+            call_ids: Mutex::new(HashMap::new()),
+            responses: Mutex::new(HashMap::new()),
+            negotiated: Negotiated::local(&parts),
             parts,
         };
 
-        // Send connection message
-        client.send(&con_msg)?;
+        // Send connection message, along with the message types.
+        if !client.parts.is_type::<C>(CONNECTION_TYPE_MID) {
+            return Err(Error::WrongType(type_name::<C>()));
+        }
+        let payload = (client.parts.ser[CONNECTION_TYPE_MID])(&con_msg)?;
+        let payload = prepend_manifest(&client.parts.manifest(), &payload)?;
+        client.send_tcp(CONNECTION_TYPE_MID, &payload)?;
         trace!("Client connection message sent. Awaiting response...");
 
         // Get response message.
-        let (mid, net_msg) = client.recv_tcp()?;
+        let resp = client.recv_response().map_err(|e| match e.io_kind() {
+            Some(ErrorKind::WouldBlock | ErrorKind::TimedOut) => Error::Timeout,
+            Some(
+                ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset
+                | ErrorKind::UnexpectedEof,
+            ) => Error::HandshakeRejected,
+            _ => e,
+        })?;
         trace!("Got response message from the server.");
 
-        if mid != RESPONSE_TYPE_MID {
-            let msg = format!(
-                "Client: First received message was MId: {} not MId: {} (Response message)",
-                mid, RESPONSE_TYPE_MID
-            );
-            return Err(Error::new(ErrorKind::InvalidData, msg));
-        }
-
         debug!(
             "New Client created at {}, to {}.",
             client.tcp.local_addr().unwrap(),
@@ -125,42 +196,138 @@ impl Client {
         client.tcp.set_nonblocking(true)?;
         client.udp.set_nonblocking(true)?;
 
-        Ok((client, net_msg.msg))
+        Ok((client, resp))
+    }
+
+    /// Receives the response message, along with the message types of the server.
+    fn recv_response(&mut self) -> Result<Box<dyn Any + Send + Sync>> {
+        let (mid, bytes) = self.tcp.recv().map_err(|e| e.with_name(&self.parts))?;
+        let (_tick, bytes) = split_tick(self.config.tick_header, bytes)?;
+
+        if mid != RESPONSE_TYPE_MID {
+            error!(
+                "Client: First received message was MId: {} not MId: {} (Response message)",
+                mid, RESPONSE_TYPE_MID
+            );
+            return Err(Error::InvalidMId {
+                mid,
+                name: self.parts.name(mid),
+            });
+        }
+
+        let (manifest, resp) = split_response(bytes)?;
+        // If the server could not match the message types, this finds out why.
+        let negotiated = Negotiated::new(&self.parts, &manifest)?;
+        let resp = resp.ok_or(Error::HandshakeRejected)?;
+        let resp = (self.parts.deser[RESPONSE_TYPE_MID])(resp)?;
+        self.negotiated = negotiated;
+        Ok(resp)
     }
 
     /// A function that encapsulates the sending logic for the TCP transport.
-    fn send_tcp(&self, mid: MId, payload: &[u8]) -> io::Result<()> {
-        self.tcp.send(mid, payload)
+    fn send_tcp(&self, mid: MId, payload: &[u8]) -> Result<()> {
+        let options = self.parts.options[mid];
+        let wire_mid = self.negotiated.wire_mid(&self.parts, mid)?;
+        let (header_mid, payload) =
+            compress(wire_mid, payload, options, self.config.compress_threshold);
+        let payload = prepend_tick(self.header_tick(), &payload);
+        check_size(
+            mid,
+            self.parts.name(mid),
+            &payload,
+            self.config.max_msg_size,
+        )?;
+        self.tcp.send(header_mid, &payload)?;
+        self.limiter
+            .lock()
+            .unwrap()
+            .record_tcp(payload.len() + TCP_HEADER_LEN);
+        Ok(())
     }
 
     /// A function that encapsulates the sending logic for the UDP transport.
-    fn send_udp(&self, mid: MId, payload: &[u8]) -> io::Result<()> {
-        self.udp.send(mid, payload)
+    fn send_udp(&self, mid: MId, payload: &[u8]) -> Result<()> {
+        let options = self.parts.options[mid];
+        let wire_mid = self.negotiated.wire_mid(&self.parts, mid)?;
+        let (header_mid, payload) =
+            compress(wire_mid, payload, options, self.config.compress_threshold);
+        let payload = prepend_tick(self.header_tick(), &payload);
+        check_size(
+            mid,
+            self.parts.name(mid),
+            &payload,
+            self.config.max_msg_size,
+        )?;
+        self.limiter.lock().unwrap().send_udp(
+            header_mid,
+            &payload,
+            payload.len() + UDP_HEADER_LEN,
+            options,
+            |mid, payload| self.udp.send(mid, payload),
+        )
+    }
+
+    /// Sends the queued UDP messages, as far as the bandwidth limit allows.
+    ///
+    /// UDP messages are queued if [`Config::schedule_udp`] is set, or if the [`BandwidthLimit`]
+    /// uses [`OverBudget::Queue`](crate::bandwidth::OverBudget::Queue). Queued messages are sent
+    /// from the highest to the lowest priority, and expired messages are discarded. If
+    /// [`Config::batch_udp`] is set, the messages are packed together into as few datagrams as
+    /// possible. This should be called once every frame.
+    ///
+    /// Returns the number of messages sent.
+    pub fn send_queued(&self) -> Result<u32> {
+        self.limiter.lock().unwrap().flush(UDP_HEADER_LEN, |msgs| {
+            if self.config.batch_udp {
+                self.udp.send_batch(msgs)
+            } else {
+                msgs.iter()
+                    .try_for_each(|(mid, payload)| self.udp.send(*mid, payload))
+            }
+        })
+    }
+
+    /// Sets the bandwidth limit, overriding the limit from the [`Config`].
+    /// `None` removes the limit.
+    pub fn set_bandwidth_limit(&mut self, limit: Option<BandwidthLimit>) {
+        self.limiter.get_mut().unwrap().set_limit(limit);
+    }
+
+    /// Gets the bandwidth limit. Returns `None` if the client is not limited.
+    pub fn bandwidth_limit(&self) -> Option<BandwidthLimit> {
+        self.limiter.lock().unwrap().limit()
+    }
+
+    /// Gets the state of the bandwidth limiter.
+    pub fn bandwidth_stats(&self) -> BandwidthStats {
+        self.limiter.lock().unwrap().stats()
     }
 
     /// A function that encapsulates the receiving logic for the TCP transport.
     ///
-    /// Any errors in receiving are returned. An error of type [`WouldBlock`] means
-    /// no more messages can be yielded without blocking. [`InvalidData`] likely means
-    /// carrier-pigeon got bad data.
-    fn recv_tcp(&mut self) -> io::Result<(MId, ErasedNetMsg)> {
+    /// Any errors in receiving are returned. An error that [`is_would_block()`](Error::is_would_block)
+    /// means no more messages can be yielded without blocking.
+    fn recv_tcp(&mut self) -> Result<(MId, ErasedNetMsg)> {
         let (mid, bytes) = self.tcp.recv()?;
+        let (tick, bytes) = split_tick(self.config.tick_header, bytes)?;
+        let (mid, bytes) = decompress(mid, bytes, self.config.max_msg_size)?;
 
-        if !self.parts.valid_mid(mid) {
-            let e_msg = format!(
-                "TCP: Got a message specifying MId {}, but the maximum MId is {}.",
+        if !self.negotiated.is_enabled(mid) {
+            return Err(Error::InvalidMId {
                 mid,
-                self.parts.mid_count()
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+                name: self.parts.name(mid),
+            });
         }
 
-        let deser_fn = self.parts.deser[mid];
-        let msg = deser_fn(bytes)?;
+        let msg = self
+            .parts
+            .deser_version(mid, self.negotiated.versions()[mid], &bytes)?;
 
         let net_msg = ErasedNetMsg {
             cid: 0,
             time: None,
+            tick,
+            seq: 0,
             msg,
         };
 
@@ -169,27 +336,29 @@ impl Client {
 
     /// A function that encapsulates the receiving logic for the UDP transport.
     ///
-    /// Any errors in receiving are returned. An error of type [`WouldBlock`] means
-    /// no more messages can be yielded without blocking. [`InvalidData`] likely means
-    /// carrier-pigeon got bad data.
-    fn recv_udp(&mut self) -> io::Result<(MId, ErasedNetMsg)> {
+    /// Any errors in receiving are returned. An error that [`is_would_block()`](Error::is_would_block)
+    /// means no more messages can be yielded without blocking.
+    fn recv_udp(&mut self) -> Result<(MId, ErasedNetMsg)> {
         let (mid, time, bytes) = self.udp.recv()?;
+        let (tick, bytes) = split_tick(self.config.tick_header, bytes)?;
+        let (mid, bytes) = decompress(mid, bytes, self.config.max_msg_size)?;
 
-        if !self.parts.valid_mid(mid) {
-            let e_msg = format!(
-                "TCP: Got a message specifying MId {}, but the maximum MId is {}.",
+        if !self.negotiated.is_enabled(mid) {
+            return Err(Error::InvalidMId {
                 mid,
-                self.parts.mid_count()
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+                name: self.parts.name(mid),
+            });
         }
 
-        let deser_fn = self.parts.deser[mid];
-        let msg = deser_fn(bytes)?;
+        let msg = self
+            .parts
+            .deser_version(mid, self.negotiated.versions()[mid], &bytes)?;
 
         let net_msg = ErasedNetMsg {
             cid: 0,
             time: Some(time),
+            tick,
+            seq: 0,
             msg,
         };
 
@@ -201,14 +370,33 @@ impl Client {
         &self.config
     }
 
+    /// Sets the current simulation tick.
+    ///
+    /// If [`Config::tick_header`] is set, this is sent with every message.
+    pub fn set_tick(&mut self, tick: u32) {
+        self.tick = tick;
+    }
+
+    /// Gets the current simulation tick.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// Gets the tick to put in the header of outgoing messages.
+    fn header_tick(&self) -> Option<u32> {
+        self.config.tick_header.then_some(self.tick)
+    }
+
     /// Disconnects from the server. You should call this
     /// method before dropping the client to let the server know that
     /// you intentionally disconnected. The `discon_msg` allows you to
     /// give a reason for the disconnect.
-    pub fn disconnect<D: Any + Send + Sync>(&mut self, discon_msg: &D) -> io::Result<()> {
+    pub fn disconnect<D: Any + Send + Sync>(&mut self, discon_msg: &D) -> Result<()> {
         let tid = TypeId::of::<D>();
-        if self.parts.tid_map[&tid] != DISCONNECT_TYPE_MID {
-            return Err(Error::new(InvalidData, "The generic parameter `D` must be the disconnection message type (the same `D` that you passed into `MsgTable::build`)."));
+        if self.parts.tid_map.get(&tid) != Some(&DISCONNECT_TYPE_MID) {
+            // The generic parameter `D` must be the disconnection message type (the same `D` that
+            // you passed into `MsgTable::build`).
+            return Err(Error::WrongType(type_name::<D>()));
         }
         debug!("Disconnecting client.");
         self.send(discon_msg)?;
@@ -218,6 +406,20 @@ impl Client {
         Ok(())
     }
 
+    /// Gets the version of each message type that was picked with the server, in [`MId`] order.
+    ///
+    /// See the [`version`](crate::version) module.
+    pub fn versions(&self) -> &[Version] {
+        self.negotiated.versions()
+    }
+
+    /// Gets the message types that were matched with the server.
+    ///
+    /// See the [`negotiate`](crate::negotiate) module.
+    pub fn negotiated(&self) -> &Negotiated {
+        &self.negotiated
+    }
+
     /// Gets the status of the connection.
     pub fn status(&self) -> &Status {
         &self.status
@@ -230,33 +432,82 @@ impl Client {
 
     /// Sends a message to the peer.
     ///
-    /// `T` must be registered in the [`MsgTable`].
-    pub fn send<T: Any + Send + Sync>(&self, msg: &T) -> io::Result<()> {
+    /// `T` must be registered in the [`MsgTable`](crate::MsgTable). Returns an [`Error::DisabledType`] if the
+    /// server doesn't have `T`.
+    pub fn send<T: Any + Send + Sync>(&self, msg: &T) -> Result<()> {
         let tid = TypeId::of::<T>();
         if !self.parts.valid_tid(tid) {
-            return Err(io::Error::new(
-                ErrorKind::InvalidData,
-                "Type not registered.",
-            ));
+            return Err(Error::UnregisteredType(type_name::<T>()));
         }
         let mid = self.parts.tid_map[&tid];
+        self.send_mid(mid, msg)
+    }
+
+    /// Sends a message to the peer, using the [`MsgHandle`] of `T`.
+    ///
+    /// This skips looking up the [`MId`] of `T`. Returns an [`Error::WrongType`] if `handle` is
+    /// not from this client's [`MsgTableParts`].
+    pub fn send_with<T: Any + Send + Sync>(&self, handle: MsgHandle<T>, msg: &T) -> Result<()> {
+        if !self.parts.is_type::<T>(handle.mid()) {
+            return Err(Error::WrongType(type_name::<T>()));
+        }
+        self.send_mid(handle.mid(), msg)
+    }
+
+    /// Sends the message `msg` with [`MId`] `mid` to the peer.
+    fn send_mid(&self, mid: MId, msg: &(dyn Any + Send + Sync)) -> Result<()> {
         let transport = self.parts.transports[mid];
-        let ser_fn = self.parts.ser[mid];
-        let b = ser_fn(msg)
-            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Serialization Error."))?;
+        let b = self
+            .parts
+            .ser_version(mid, self.negotiated.versions()[mid], msg)?;
 
+        trace!(
+            "Sending message of type {}, len {}",
+            self.parts.names[mid],
+            b.len()
+        );
         match transport {
             Transport::TCP => self.send_tcp(mid, &b),
             Transport::UDP => self.send_udp(mid, &b),
         }
     }
 
+    /// Calls a remote procedure on the server, by sending the request `req`.
+    ///
+    /// Returns a [`CallHandle`] that resolves when the matching response arrives, or times out
+    /// after [`Config::call_timeout`]. `Req` must be registered with `register_rpc()`.
+    pub fn call<Req: Request>(&self, req: Req) -> Result<CallHandle<Req>> {
+        let resp_mid = *self
+            .parts
+            .tid_map
+            .get(&TypeId::of::<RpcResponse<Req>>())
+            .ok_or(Error::UnregisteredType(type_name::<RpcResponse<Req>>()))?;
+        self.call_ids
+            .lock()
+            .unwrap()
+            .insert(resp_mid, call_id::<Req>);
+
+        let id = self.next_call_id.fetch_add(1, Ordering::Relaxed);
+        self.send(&RpcRequest { id, req })?;
+        Ok(CallHandle::new(
+            id,
+            Instant::now() + self.config.call_timeout,
+        ))
+    }
+
+    /// Removes the response to the call with id `id`, if it arrived.
+    pub(crate) fn take_response<Req: Request>(&self, id: u32) -> Option<Req::Response> {
+        let (_, resp) = self.responses.lock().unwrap().remove(&id)?;
+        let resp = resp.downcast::<RpcResponse<Req>>().ok()?;
+        Some(resp.resp)
+    }
+
     /// Gets an iterator for the messages of type `T`.
     ///
     /// ### Panics
     /// Panics if the type `T` was not registered.
     /// For a non-panicking version, see [try_recv()](Self::try_recv).
-    pub fn recv<T: Any + Send + Sync>(&self) -> impl Iterator<Item = NetMsg<T>> + '_ {
+    pub fn recv<T: Any + Send + Sync>(&self) -> impl Iterator<Item = NetMsg<'_, T>> + '_ {
         let tid = TypeId::of::<T>();
         if !self.parts.valid_tid(tid) {
             panic!("Type ({}) not registered.", type_name::<T>());
@@ -266,16 +517,87 @@ impl Client {
         self.msg_buff[mid].iter().map(|m| m.to_typed().unwrap())
     }
 
+    /// Gets an iterator for the messages of type `T`, using the [`MsgHandle`] of `T`.
+    ///
+    /// This skips looking up the [`MId`] of `T`.
+    ///
+    /// Like [`send_with()`](Self::send_with) returns an [`Error::WrongType`], this yields no
+    /// messages if `handle` is not from this client's [`MsgTableParts`].
+    pub fn recv_with<T: Any + Send + Sync>(
+        &self,
+        handle: MsgHandle<T>,
+    ) -> impl Iterator<Item = NetMsg<'_, T>> + '_ {
+        let is_type = self.parts.is_type::<T>(handle.mid());
+        self.msg_buff
+            .get(handle.mid())
+            .filter(|_| is_type)
+            .into_iter()
+            .flatten()
+            .map(|m| m.to_typed().unwrap())
+    }
+
     /// Gets an iterator for the messages of type `T`.
     ///
     /// Returns `None` if the type `T` was not registered.
-    pub fn try_recv<T: Any + Send + Sync>(&self) -> Option<impl Iterator<Item = NetMsg<T>> + '_> {
+    pub fn try_recv<T: Any + Send + Sync>(
+        &self,
+    ) -> Option<impl Iterator<Item = NetMsg<'_, T>> + '_> {
         let tid = TypeId::of::<T>();
         let mid = *self.parts.tid_map.get(&tid)?;
 
         Some(self.msg_buff[mid].iter().map(|m| m.to_typed().unwrap()))
     }
 
+    /// Removes all the messages of type `T`, and gets an iterator for them.
+    ///
+    /// Unlike [`recv()`](Self::recv), the messages are owned, so they can be moved elsewhere
+    /// without cloning them. Messages that are not consumed from the iterator are still removed.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// ### Panics
+    /// Panics if the type `T` was not registered.
+    pub fn drain<T: Any + Send + Sync>(&mut self) -> impl Iterator<Item = OwnedNetMsg<T>> + '_ {
+        let tid = TypeId::of::<T>();
+        if !self.parts.valid_tid(tid) {
+            panic!("Type ({}) not registered.", type_name::<T>());
+        }
+        let mid = self.parts.tid_map[&tid];
+
+        self.msg_buff[mid]
+            .drain(..)
+            .map(|m| m.into_typed::<T>().unwrap())
+    }
+
+    /// Removes the oldest message of type `T`, and returns it.
+    ///
+    /// Returns `None` if there are no messages of type `T`.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// ### Panics
+    /// Panics if the type `T` was not registered.
+    pub fn take<T: Any + Send + Sync>(&mut self) -> Option<OwnedNetMsg<T>> {
+        let tid = TypeId::of::<T>();
+        if !self.parts.valid_tid(tid) {
+            panic!("Type ({}) not registered.", type_name::<T>());
+        }
+        let mid = self.parts.tid_map[&tid];
+
+        let net_msg = self.msg_buff[mid].pop_front()?;
+        Some(net_msg.into_typed::<T>().unwrap())
+    }
+
+    /// Gets an iterator for all the received messages, in the order they arrived.
+    ///
+    /// Unlike [`recv()`](Self::recv), this keeps the relative order of messages of different
+    /// types. Use [`AnyNetMsg::downcast()`] to get the typed messages.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    pub fn recv_all_ordered(&self) -> impl Iterator<Item = AnyNetMsg<'_>> {
+        ordered_msgs(&self.msg_buff).into_iter()
+    }
+
     /// Receives the messages from the connections.
     /// This should be done before calling `recv<T>()`.
     ///
@@ -284,16 +606,23 @@ impl Client {
     pub fn recv_msgs(&mut self) -> u32 {
         let mut i = 0;
 
+        // Forget the responses to calls that already timed out.
+        let call_timeout = self.config.call_timeout;
+        self.responses
+            .get_mut()
+            .unwrap()
+            .retain(|_, (arrived, _)| arrived.elapsed() <= call_timeout);
+
         // TCP
         loop {
             if !self.status.connected() {
                 break;
             }
 
-            let recv = self.recv_tcp();
+            let recv = self.recv_tcp().map_err(|e| e.with_name(&self.parts));
             match recv {
                 // No more data.
-                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.is_would_block() => break,
                 // IO Error occurred.
                 Err(e) => {
                     error!("TCP: IO error occurred while receiving data. {}", e);
@@ -305,7 +634,7 @@ impl Client {
                     if mid == DISCONNECT_TYPE_MID {
                         self.status = Status::Disconnected(net_msg.msg);
                     } else {
-                        self.msg_buff[mid].push(net_msg);
+                        self.push_msg(mid, net_msg);
                     }
                 }
             }
@@ -317,10 +646,10 @@ impl Client {
                 break;
             }
 
-            let recv = self.recv_udp();
+            let recv = self.recv_udp().map_err(|e| e.with_name(&self.parts));
             match recv {
                 // No more data.
-                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.is_would_block() => break,
                 // IO Error occurred.
                 Err(e) => {
                     error!("UDP: IO error occurred while receiving data. {}", e);
@@ -328,7 +657,7 @@ impl Client {
                 // Successfully got a message.
                 Ok((mid, net_msg)) => {
                     i += 1;
-                    self.msg_buff[mid].push(net_msg);
+                    self.push_msg(mid, net_msg);
                 }
             }
         }
@@ -336,6 +665,24 @@ impl Client {
         i
     }
 
+    /// Adds a received message to the buffer, giving it the next sequence number.
+    ///
+    /// Responses to calls are kept by their call id instead, until they are polled.
+    fn push_msg(&mut self, mid: MId, mut net_msg: ErasedNetMsg) {
+        trace!("Received message of type {}", self.parts.names[mid]);
+        if let Some(call_id) = self.call_ids.get_mut().unwrap().get(&mid) {
+            let id = call_id(&*net_msg.msg);
+            self.responses
+                .get_mut()
+                .unwrap()
+                .insert(id, (Instant::now(), net_msg.msg));
+            return;
+        }
+        net_msg.seq = self.next_seq;
+        self.next_seq += 1;
+        self.msg_buff[mid].push_back(net_msg);
+    }
+
     /// Clears messages from the buffer.
     pub fn clear_msgs(&mut self) {
         for buff in self.msg_buff.iter_mut() {
@@ -344,12 +691,12 @@ impl Client {
     }
 
     /// Gets the local address.
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    pub fn local_addr(&self) -> Result<SocketAddr> {
         self.tcp.local_addr()
     }
 
     /// Gets the address of the peer.
-    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
         self.tcp.peer_addr()
     }
 }
@@ -371,7 +718,7 @@ impl Debug for Client {
 #[derive(Debug)]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Resource))]
 pub struct PendingClient {
-    channel: Receiver<io::Result<(Client, Box<dyn Any + Send + Sync>)>>,
+    channel: Receiver<Result<(Client, Box<dyn Any + Send + Sync>)>>,
 }
 
 // Impl display so that `get()` can be unwrapped.
@@ -391,14 +738,14 @@ impl PendingClient {
         self.channel.is_ready()
     }
 
-    /// Takes the [`io::Result<Client>`] from the [`PendingClient`].
+    /// Takes the [`Result<Client>`] from the [`PendingClient`].
     /// This **will** yield a value if [`done()`](Self::done) returned `true`.
     ///
-    /// ### Panics
-    /// Panics if the generic parameter `R` isn't the response message type (the same `R` that you passed into `MsgTable::build`).
-    pub fn take<R: Any + Send + Sync>(self) -> Result<io::Result<(Client, R)>, Self> {
+    /// Yields an [`Error::WrongType`] if the generic parameter `R` isn't the response message
+    /// type (the same `R` that you passed into `MsgTable::build`).
+    pub fn take<R: Any + Send + Sync>(self) -> std::result::Result<Result<(Client, R)>, Self> {
         if self.done() {
-            Ok(self.channel.recv().unwrap().map(|(client, m)| (client, *m.downcast::<R>().expect("The generic parameter `R` must be the response message type (the same `R` that you passed into `MsgTable::build`)."))))
+            Ok(downcast_response(self.channel.recv().unwrap()))
         } else {
             Err(self)
         }
@@ -406,10 +753,10 @@ impl PendingClient {
 
     /// Blocks until the client is ready.
     ///
-    /// ### Panics
-    /// Panics if the generic parameter `R` isn't the response message type (the same `R` that you passed into `MsgTable::build`).
-    pub fn block<R: Any + Send + Sync>(self) -> io::Result<(Client, R)> {
-        self.channel.recv().unwrap().map(|(client, m)| (client, *m.downcast::<R>().expect("The generic parameter `R` must be the response message type (the same `R` that you passed into `MsgTable::build`).")))
+    /// Yields an [`Error::WrongType`] if the generic parameter `R` isn't the response message
+    /// type (the same `R` that you passed into `MsgTable::build`).
+    pub fn block<R: Any + Send + Sync>(self) -> Result<(Client, R)> {
+        downcast_response(self.channel.recv().unwrap())
     }
 
     /// Converts this into a [`OptionPendingClient`].
@@ -440,7 +787,7 @@ impl From<PendingClient> for OptionPendingClient {
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Resource))]
 pub struct OptionPendingClient {
     #[allow(clippy::type_complexity)]
-    channel: Option<Receiver<io::Result<(Client, Box<dyn Any + Send + Sync>)>>>,
+    channel: Option<Receiver<Result<(Client, Box<dyn Any + Send + Sync>)>>>,
 }
 
 // Impl display so that `get()` can be unwrapped.
@@ -464,14 +811,16 @@ impl OptionPendingClient {
         Some(self.channel.as_ref()?.is_ready())
     }
 
-    /// Takes the [`io::Result<Client>`] from the [`PendingClient`].
+    /// Takes the [`Result<Client>`] from the [`PendingClient`].
     /// This **will** yield a value if [`done()`](Self::done) returned `true`.
     ///
-    /// ### Panics
-    /// Panics if `R` is not the response message type.
-    pub fn take<R: Any + Send + Sync>(&mut self) -> Option<io::Result<(Client, R)>> {
+    /// Yields an [`Error::WrongType`] if the generic parameter `R` isn't the response message
+    /// type (the same `R` that you passed into `MsgTable::build`).
+    pub fn take<R: Any + Send + Sync>(&mut self) -> Option<Result<(Client, R)>> {
         if self.done()? {
-            Some(self.channel.as_ref().unwrap().recv().unwrap().map(|(client, m)| (client, *m.downcast::<R>().expect("The generic parameter `R` must be the response message type (the same `R` that you passed into `MsgTable::build`)."))))
+            Some(downcast_response(
+                self.channel.as_ref().unwrap().recv().unwrap(),
+            ))
         } else {
             None
         }
@@ -479,9 +828,129 @@ impl OptionPendingClient {
 
     /// Blocks until the client is ready.
     ///
-    /// ### Panics
-    /// Panics if the generic parameter `R` isn't the response message type (the same `R` that you passed into `MsgTable::build`).
-    pub fn block<R: Any + Send + Sync>(self) -> Option<io::Result<(Client, R)>> {
-        Some(self.channel?.recv().unwrap().map(|(client, m)| (client, *m.downcast::<R>().expect("The generic parameter `R` must be the response message type (the same `R` that you passed into `MsgTable::build`)."))))
+    /// Yields an [`Error::WrongType`] if the generic parameter `R` isn't the response message
+    /// type (the same `R` that you passed into `MsgTable::build`).
+    pub fn block<R: Any + Send + Sync>(self) -> Option<Result<(Client, R)>> {
+        Some(downcast_response(self.channel?.recv().unwrap()))
+    }
+}
+
+/// A [`PendingClient`] that knows the response message type `R`.
+///
+/// Created with [`Client::connect()`].
+pub struct TypedPendingClient<R, D> {
+    pending: PendingClient,
+    _types: PhantomData<fn() -> (R, D)>,
+}
+
+impl<R, D> Debug for TypedPendingClient<R, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedPendingClient")
+            .field("pending", &self.pending)
+            .field("response", &type_name::<R>())
+            .field("disconnect", &type_name::<D>())
+            .finish()
+    }
+}
+
+impl<R, D> Display for TypedPendingClient<R, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.pending, f)
+    }
+}
+
+impl<R: Any + Send + Sync, D: Any + Send + Sync> TypedPendingClient<R, D> {
+    /// Returns whether the client is finished connecting.
+    pub fn done(&self) -> bool {
+        self.pending.done()
+    }
+
+    /// Takes the [`Result<TypedClient>`] from the [`TypedPendingClient`].
+    /// This **will** yield a value if [`done()`](Self::done) returned `true`.
+    #[allow(clippy::type_complexity)]
+    pub fn take(self) -> std::result::Result<Result<(TypedClient<D>, R)>, Self> {
+        match self.pending.take() {
+            Ok(result) => Ok(result.map(|(client, resp)| (TypedClient::new(client), resp))),
+            Err(pending) => Err(TypedPendingClient {
+                pending,
+                _types: PhantomData,
+            }),
+        }
+    }
+
+    /// Blocks until the client is ready.
+    pub fn block(self) -> Result<(TypedClient<D>, R)> {
+        let (client, resp) = self.pending.block()?;
+        Ok((TypedClient::new(client), resp))
+    }
+
+    /// Forgets the message types, giving back the [`PendingClient`].
+    pub fn erase(self) -> PendingClient {
+        self.pending
+    }
+}
+
+/// A [`Client`] that knows the disconnect message type `D`.
+///
+/// Created by a [`TypedPendingClient`]. The methods that use `D` can not be called with the
+/// wrong type. All other methods are available through [`Deref`] to the [`Client`].
+pub struct TypedClient<D> {
+    client: Client,
+    _d: PhantomData<fn() -> D>,
+}
+
+impl<D: Any + Send + Sync> TypedClient<D> {
+    /// Creates a new [`TypedClient`].
+    fn new(client: Client) -> Self {
+        TypedClient {
+            client,
+            _d: PhantomData,
+        }
+    }
+
+    /// Disconnects from the server. See [`Client::disconnect()`].
+    pub fn disconnect(&mut self, discon_msg: &D) -> Result<()> {
+        self.client.disconnect(discon_msg)
+    }
+
+    /// Gets the status of the connection.
+    pub fn status(&self) -> TypedStatus<'_, D> {
+        TypedStatus::new(self.client.status())
+    }
+
+    /// Forgets the disconnect message type, giving back the [`Client`].
+    pub fn erase(self) -> Client {
+        self.client
+    }
+}
+
+impl<D> Debug for TypedClient<D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.client, f)
+    }
+}
+
+impl<D> Deref for TypedClient<D> {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl<D> DerefMut for TypedClient<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+/// Downcasts the response of a finished connection attempt to `R`.
+fn downcast_response<R: Any + Send + Sync>(
+    result: Result<(Client, Box<dyn Any + Send + Sync>)>,
+) -> Result<(Client, R)> {
+    let (client, m) = result?;
+    match m.downcast::<R>() {
+        Ok(m) => Ok((client, *m)),
+        Err(_) => Err(Error::WrongType(type_name::<R>())),
     }
 }
@@ -0,0 +1,152 @@
+//! Optional compression of message payloads.
+//!
+//! Message types opt in to compression with [`MsgOptions::compress`]. Payloads of those types
+//! that are at least [`Config::compress_threshold`](crate::net::Config::compress_threshold) bytes
+//! are compressed with lz4 before sending, as long as that makes them smaller. Compressed payloads
+//! are marked by setting [`COMPRESSED_FLAG`] on the [`MId`] in the header.
+//!
+//! Compression is only done when the `compression` feature is enabled. Without it, messages are
+//! always sent uncompressed, and receiving a compressed message is an error.
+
+use crate::header::COMPRESSED_FLAG;
+use crate::message_table::MsgOptions;
+use crate::{Error, MId, Result};
+use std::borrow::Cow;
+
+/// Compresses `payload` if the message type opted in to compression and the payload is at least
+/// `threshold` bytes long.
+///
+/// Returns the [`MId`] to put in the header (with [`COMPRESSED_FLAG`] set if the payload was
+/// compressed) and the payload to send.
+#[cfg(feature = "compression")]
+pub(crate) fn compress(
+    mid: MId,
+    payload: &[u8],
+    options: MsgOptions,
+    threshold: usize,
+) -> (MId, Cow<'_, [u8]>) {
+    if !options.compress || payload.len() < threshold {
+        return (mid, Cow::Borrowed(payload));
+    }
+
+    let compressed = lz4_flex::compress_prepend_size(payload);
+    if compressed.len() < payload.len() {
+        (mid | COMPRESSED_FLAG, Cow::Owned(compressed))
+    } else {
+        (mid, Cow::Borrowed(payload))
+    }
+}
+
+/// Compresses `payload` if the message type opted in to compression and the payload is at least
+/// `threshold` bytes long.
+///
+/// The `compression` feature is disabled, so this always returns the payload unchanged.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn compress(
+    mid: MId,
+    payload: &[u8],
+    _options: MsgOptions,
+    _threshold: usize,
+) -> (MId, Cow<'_, [u8]>) {
+    (mid, Cow::Borrowed(payload))
+}
+
+/// Decompresses `payload` if [`COMPRESSED_FLAG`] is set on `mid`.
+///
+/// Returns the [`MId`] without the flag, and the decompressed payload. Payloads that decompress
+/// to more than `max_size` bytes are rejected.
+#[cfg(feature = "compression")]
+pub(crate) fn decompress(
+    mid: MId,
+    payload: &[u8],
+    max_size: usize,
+) -> Result<(MId, Cow<'_, [u8]>)> {
+    if mid & COMPRESSED_FLAG == 0 {
+        return Ok((mid, Cow::Borrowed(payload)));
+    }
+    let mid = mid & !COMPRESSED_FLAG;
+
+    // lz4_flex prepends the uncompressed size as a little endian u32.
+    if payload.len() < 4 {
+        return Err(Error::deserialize(
+            "Compressed payload is too short to contain its size.",
+        ));
+    }
+    let size = u32::from_le_bytes(payload[..4].try_into().unwrap()) as usize;
+    if size > max_size {
+        return Err(Error::MessageTooLarge {
+            mid,
+            name: None,
+            size,
+            max: max_size,
+        });
+    }
+
+    let decompressed =
+        lz4_flex::decompress(&payload[4..], size).map_err(|e| Error::Deserialize(Box::new(e)))?;
+    Ok((mid, Cow::Owned(decompressed)))
+}
+
+/// Decompresses `payload` if [`COMPRESSED_FLAG`] is set on `mid`.
+///
+/// The `compression` feature is disabled, so this yields an error if the flag is set.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress(
+    mid: MId,
+    payload: &[u8],
+    _max_size: usize,
+) -> Result<(MId, Cow<'_, [u8]>)> {
+    if mid & COMPRESSED_FLAG == 0 {
+        return Ok((mid, Cow::Borrowed(payload)));
+    }
+
+    let e_msg = format!(
+        "Received a compressed message of MId {}, but the `compression` feature is not enabled.",
+        mid & !COMPRESSED_FLAG
+    );
+    Err(Error::deserialize(e_msg))
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use crate::compression::{compress, decompress};
+    use crate::header::COMPRESSED_FLAG;
+    use crate::message_table::MsgOptions;
+
+    #[test]
+    fn round_trip() {
+        let options = MsgOptions {
+            compress: true,
+            ..MsgOptions::default()
+        };
+        let payload = vec![7; 1000];
+
+        let (mid, compressed) = compress(5, &payload, options, 64);
+        assert_eq!(mid, 5 | COMPRESSED_FLAG);
+        assert!(compressed.len() < payload.len());
+
+        let (mid, decompressed) = decompress(mid, &compressed, 2048).unwrap();
+        assert_eq!(mid, 5);
+        assert_eq!(*decompressed, payload);
+
+        // Too big.
+        assert!(decompress(5 | COMPRESSED_FLAG, &compressed, 999).is_err());
+    }
+
+    #[test]
+    fn threshold() {
+        let options = MsgOptions {
+            compress: true,
+            ..MsgOptions::default()
+        };
+        let payload = vec![7; 32];
+
+        let (mid, uncompressed) = compress(5, &payload, options, 64);
+        assert_eq!(mid, 5);
+        assert_eq!(*uncompressed, payload);
+
+        // Not opted in.
+        let (mid, _) = compress(5, &[7; 1000], MsgOptions::default(), 64);
+        assert_eq!(mid, 5);
+    }
+}
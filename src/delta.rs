@@ -0,0 +1,533 @@
+//! Delta compression for repeated state snapshots.
+//!
+//! When the same kind of snapshot is sent over and over (like the state of the world every
+//! frame), consecutive snapshots are usually nearly identical. A [`DeltaEncoder`] on the server
+//! encodes each snapshot against the last snapshot that the client acknowledged, and a
+//! [`DeltaDecoder`] on the client decodes it and sends the acknowledgement back. When the server
+//! has no acknowledged snapshot for a client yet, or when the client has not acknowledged any
+//! of the last `max_pending` snapshots, the full snapshot is sent.
+//!
+//! The [`Delta<T>`] and [`DeltaAck<T>`] message types need to be registered on both sides, using
+//! [`register_delta`] or [`register_delta_sorted`].
+//!
+//! ```no_run
+//! # use carrier_pigeon::{MsgTable, Transport, Server};
+//! # use carrier_pigeon::delta::{register_delta, DeltaEncoder};
+//! # use serde::{Serialize, Deserialize};
+//! # #[derive(Serialize, Deserialize)]
+//! # struct World { positions: Vec<(f32, f32)> }
+//! # fn run(server: &Server, world: &World) {
+//! // When building the table:
+//! let mut table = MsgTable::new();
+//! register_delta::<World>(&mut table).unwrap();
+//!
+//! // Every frame on the server, after `recv_msgs()`:
+//! let mut encoder = DeltaEncoder::<World>::new(32);
+//! encoder.handle_acks(server);
+//! encoder.broadcast(server, world).unwrap();
+//! # }
+//! ```
+
+use crate::message_table::MsgRegError;
+use crate::net::{CId, Config};
+use crate::{Client, Error, MsgTable, Server, SortedMsgTable, Transport};
+use hashbrown::HashMap;
+use log::{error, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+/// A snapshot of type `T`, possibly encoded against an older snapshot.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Delta<T> {
+    /// The sequence number of this snapshot.
+    seq: u32,
+    /// The sequence number of the snapshot this was encoded against.
+    ///
+    /// `None` if this is a full snapshot.
+    base: Option<u32>,
+    /// The encoded snapshot.
+    data: Vec<u8>,
+    #[serde(skip)]
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T> Delta<T> {
+    /// Gets the sequence number of this snapshot.
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    /// Gets whether this is a full snapshot, instead of a delta.
+    pub fn is_full(&self) -> bool {
+        self.base.is_none()
+    }
+}
+
+/// The acknowledgement of a received [`Delta<T>`].
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DeltaAck<T> {
+    /// The sequence number of the received snapshot.
+    seq: u32,
+    #[serde(skip)]
+    _t: PhantomData<fn() -> T>,
+}
+
+/// Registers the [`Delta<T>`] and [`DeltaAck<T>`] messages for snapshots of type `T`.
+///
+/// Both are sent over UDP, as a lost snapshot will be replaced by the next one anyways.
+pub fn register_delta<T>(table: &mut MsgTable) -> Result<(), MsgRegError>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    let mut delta_table = MsgTable::new();
+    delta_table.register::<Delta<T>>(Transport::UDP)?;
+    delta_table.register::<DeltaAck<T>>(Transport::UDP)?;
+    table.join(&delta_table)
+}
+
+/// Registers the [`Delta<T>`] and [`DeltaAck<T>`] messages for snapshots of type `T`.
+///
+/// They are registered with the identifiers `"{identifier}::delta"` and `"{identifier}::ack"`.
+/// Both are sent over UDP, as a lost snapshot will be replaced by the next one anyways.
+pub fn register_delta_sorted<T>(
+    table: &mut SortedMsgTable,
+    identifier: &str,
+) -> Result<(), MsgRegError>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    let mut delta_table = SortedMsgTable::new();
+    delta_table.register::<Delta<T>>(Transport::UDP, &format!("{}::delta", identifier))?;
+    delta_table.register::<DeltaAck<T>>(Transport::UDP, &format!("{}::ack", identifier))?;
+    table.join(&delta_table)
+}
+
+/// The snapshots sent to a single [`CId`].
+#[derive(Clone, Debug, Default)]
+struct History {
+    /// The latest snapshot that the client acknowledged.
+    acked: Option<(u32, Vec<u8>)>,
+    /// The snapshots sent after `acked`, oldest first.
+    pending: VecDeque<(u32, Vec<u8>)>,
+    /// The number of snapshots sent after `acked`.
+    ///
+    /// Unlike the length of `pending`, this is not capped at `max_pending`.
+    since_acked: usize,
+}
+
+/// Encodes snapshots of type `T` for each client, against the last snapshot they acknowledged.
+///
+/// This is used on the server side. See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct DeltaEncoder<T> {
+    histories: HashMap<CId, History>,
+    next_seq: u32,
+    max_pending: usize,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T> DeltaEncoder<T>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    /// Creates a new [`DeltaEncoder`].
+    ///
+    /// `max_pending` is the number of unacknowledged snapshots to remember per client. Acks for
+    /// snapshots older than that are ignored, and once that many snapshots were sent after the
+    /// acknowledged one, full snapshots are sent until a newer one is acknowledged.
+    pub fn new(max_pending: usize) -> Self {
+        DeltaEncoder {
+            histories: HashMap::new(),
+            next_seq: 0,
+            max_pending,
+            _t: PhantomData,
+        }
+    }
+
+    /// Encodes `snapshot` for `cid`, against the last snapshot that `cid` acknowledged.
+    pub fn encode(&mut self, cid: CId, snapshot: &T) -> crate::Result<Delta<T>> {
+        let full = bincode::serialize(snapshot).map_err(|e| Error::Serialize(e))?;
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let history = self.histories.entry(cid).or_default();
+        let (base, data) = match &history.acked {
+            // The decoder might not remember the base anymore.
+            Some(_) if history.since_acked >= self.max_pending => (None, full.clone()),
+            Some((base_seq, base)) => {
+                let delta = encode_delta(base, &full);
+                if delta.len() < full.len() {
+                    (Some(*base_seq), delta)
+                } else {
+                    (None, full.clone())
+                }
+            }
+            None => (None, full.clone()),
+        };
+
+        history.pending.push_back((seq, full));
+        history.since_acked += 1;
+        if history.pending.len() > self.max_pending {
+            history.pending.pop_front();
+        }
+
+        Ok(Delta {
+            seq,
+            base,
+            data,
+            _t: PhantomData,
+        })
+    }
+
+    /// Marks the snapshot `seq` as received by `cid`, making it the base for future deltas.
+    pub fn ack(&mut self, cid: CId, seq: u32) {
+        let history = match self.histories.get_mut(&cid) {
+            Some(history) => history,
+            None => return,
+        };
+
+        if let Some(idx) = history.pending.iter().position(|(s, _)| *s == seq) {
+            // Older pending snapshots can not be acknowledged anymore.
+            let acked = history.pending.drain(..=idx).next_back();
+            history.acked = acked;
+            history.since_acked = history.pending.len();
+        }
+    }
+
+    /// Forgets everything about `cid`. Call this when `cid` disconnects.
+    pub fn remove(&mut self, cid: CId) {
+        self.histories.remove(&cid);
+    }
+
+    /// Encodes and sends `snapshot` to `cid`.
+    pub fn send_to(&mut self, server: &Server, cid: CId, snapshot: &T) -> crate::Result<()> {
+        let delta = self.encode(cid, snapshot)?;
+        server.send_to(cid, &delta)
+    }
+
+    /// Encodes and sends `snapshot` to all connected clients.
+    pub fn broadcast(&mut self, server: &Server, snapshot: &T) -> crate::Result<()> {
+        for cid in server.cids() {
+            self.send_to(server, cid, snapshot)?;
+        }
+        Ok(())
+    }
+
+    /// Handles the [`DeltaAck<T>`]s that the server received.
+    ///
+    /// Make sure to call [`Server::recv_msgs()`] before calling this.
+    pub fn handle_acks(&mut self, server: &Server) {
+        for ack in server.recv::<DeltaAck<T>>() {
+            self.ack(ack.cid, ack.seq);
+        }
+        self.histories.retain(|cid, _| server.alive(*cid));
+    }
+}
+
+/// Decodes the snapshots of type `T` sent by a [`DeltaEncoder`].
+///
+/// This is used on the client side. See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct DeltaDecoder<T> {
+    /// The received snapshots that the server might use as a base, oldest first.
+    bases: VecDeque<(u32, Vec<u8>)>,
+    /// The base of the last decoded delta.
+    ///
+    /// This is kept even when more than `max_bases` snapshots are received after it, as the
+    /// server keeps using it while the newer acknowledgements are delayed or lost.
+    matched: Option<(u32, Vec<u8>)>,
+    max_bases: usize,
+    /// The maximum size of a decoded snapshot.
+    max_size: usize,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T> DeltaDecoder<T>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    /// Creates a new [`DeltaDecoder`].
+    ///
+    /// `max_bases` is the number of received snapshots to remember as possible bases. This
+    /// should be the same as the `max_pending` of the [`DeltaEncoder`].
+    pub fn new(max_bases: usize) -> Self {
+        DeltaDecoder {
+            bases: VecDeque::new(),
+            matched: None,
+            max_bases,
+            max_size: Config::default().max_msg_size,
+            _t: PhantomData,
+        }
+    }
+
+    /// Decodes `delta`, remembering it as a possible base for future deltas.
+    ///
+    /// Deltas that decode to more than [`Config::max_msg_size`] bytes are rejected, using the
+    /// config of the client last passed to [`recv()`](Self::recv).
+    pub fn decode(&mut self, delta: &Delta<T>) -> crate::Result<T> {
+        let full = match delta.base {
+            None => delta.data.clone(),
+            Some(base_seq) => {
+                if let Some(idx) = self.bases.iter().position(|(s, _)| *s == base_seq) {
+                    // The server will never use an older base again.
+                    self.matched = self.bases.drain(..=idx).next_back();
+                }
+                match &self.matched {
+                    Some((s, base)) if *s == base_seq => {
+                        decode_delta(base, &delta.data, self.max_size)?
+                    }
+                    _ => {
+                        return Err(Error::deserialize(format!(
+                            "Delta {} is based on snapshot {}, which is not known.",
+                            delta.seq, base_seq
+                        )))
+                    }
+                }
+            }
+        };
+
+        let snapshot = bincode::deserialize(&full).map_err(|e| Error::Deserialize(e))?;
+
+        self.bases.push_back((delta.seq, full));
+        if self.bases.len() > self.max_bases {
+            self.bases.pop_front();
+        }
+        Ok(snapshot)
+    }
+
+    /// Decodes all [`Delta<T>`]s that the client received, and acknowledges them.
+    ///
+    /// Snapshots that fail to decode are logged and skipped. Make sure to call
+    /// [`Client::recv_msgs()`] before calling this.
+    pub fn recv(&mut self, client: &Client) -> Vec<T> {
+        self.max_size = client.config().max_msg_size;
+        let mut deltas: Vec<_> = client.recv::<Delta<T>>().map(|msg| msg.m).collect();
+        // UDP is unordered.
+        deltas.sort_by_key(|delta| delta.seq);
+
+        let mut snapshots = vec![];
+        for delta in deltas {
+            match self.decode(delta) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => {
+                    warn!("Failed to decode a delta snapshot. {}", e);
+                    continue;
+                }
+            }
+
+            let ack = DeltaAck::<T> {
+                seq: delta.seq,
+                _t: PhantomData,
+            };
+            if let Err(e) = client.send(&ack) {
+                error!("Failed to acknowledge a delta snapshot. {}", e);
+            }
+        }
+        snapshots
+    }
+}
+
+/// Encodes `new` against `base`.
+///
+/// The bytes are XOR-ed with the base, and the result is run length encoded as alternating
+/// `(zero_run, literal_run)` varint pairs, each literal run followed by its bytes. The encoding
+/// starts with the length of `new`.
+fn encode_delta(base: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    write_varint(&mut out, new.len());
+
+    let diff: Vec<u8> = new
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while i < diff.len() {
+        let zeros = diff[i..].iter().take_while(|b| **b == 0).count();
+        i += zeros;
+        let literals = diff[i..].iter().take_while(|b| **b != 0).count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&diff[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+/// Decodes `delta`, which was encoded against `base` by [`encode_delta`].
+///
+/// Fails if the decoded bytes would be longer than `max_len`.
+fn decode_delta(base: &[u8], delta: &[u8], max_len: usize) -> crate::Result<Vec<u8>> {
+    let invalid = || Error::deserialize("Malformed delta.");
+
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos).ok_or_else(invalid)?;
+    if len > max_len {
+        return Err(invalid());
+    }
+    let mut out: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0usize;
+    while pos < delta.len() {
+        let zeros = read_varint(delta, &mut pos).ok_or_else(invalid)?;
+        let literals = read_varint(delta, &mut pos).ok_or_else(invalid)?;
+        i = i.checked_add(zeros).ok_or_else(invalid)?;
+        let end = i.checked_add(literals).ok_or_else(invalid)?;
+        let delta_end = pos.checked_add(literals).ok_or_else(invalid)?;
+        if end > len || delta_end > delta.len() {
+            return Err(invalid());
+        }
+        for (o, d) in out[i..end].iter_mut().zip(&delta[pos..delta_end]) {
+            *o ^= d;
+        }
+        i = end;
+        pos = delta_end;
+    }
+    Ok(out)
+}
+
+/// Writes `n` as a LEB128 varint.
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads a LEB128 varint, advancing `pos`.
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut n = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        n |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delta::{
+        decode_delta, encode_delta, write_varint, Delta, DeltaDecoder, DeltaEncoder,
+    };
+    use std::marker::PhantomData;
+
+    #[test]
+    fn delta_round_trip() {
+        let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (vec![1, 2, 3, 4], vec![1, 2, 3, 4]),
+            (vec![1, 2, 3, 4], vec![1, 9, 3, 4, 5, 6]),
+            (vec![1, 2, 3, 4, 5, 6], vec![1, 2]),
+            (vec![], vec![7; 300]),
+            (vec![0; 1000], vec![0; 1000]),
+        ];
+
+        for (base, new) in cases {
+            let delta = encode_delta(&base, &new);
+            assert_eq!(decode_delta(&base, &delta, usize::MAX).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn encoder_decoder() {
+        let mut encoder = DeltaEncoder::<Vec<u32>>::new(8);
+        let mut decoder = DeltaDecoder::<Vec<u32>>::new(8);
+        let mut world: Vec<u32> = (0..100).collect();
+
+        // Nothing was acknowledged yet.
+        let first = encoder.encode(1, &world).unwrap();
+        assert!(first.is_full());
+        assert_eq!(decoder.decode(&first).unwrap(), world);
+        encoder.ack(1, first.seq());
+
+        world[50] = 12345;
+        let second = encoder.encode(1, &world).unwrap();
+        assert!(!second.is_full());
+        assert!(second.data.len() < first.data.len());
+        assert_eq!(decoder.decode(&second).unwrap(), world);
+
+        // The second snapshot was not acknowledged, so this is still based on the first.
+        world[10] = 0;
+        let third = encoder.encode(1, &world).unwrap();
+        assert_eq!(third.base, Some(first.seq()));
+        assert_eq!(decoder.decode(&third).unwrap(), world);
+
+        // Other CIds get full snapshots.
+        assert!(encoder.encode(2, &world).unwrap().is_full());
+    }
+
+    #[test]
+    fn lost_acks() {
+        let mut encoder = DeltaEncoder::<Vec<u32>>::new(4);
+        let mut decoder = DeltaDecoder::<Vec<u32>>::new(4);
+        let mut world: Vec<u32> = (0..100).collect();
+
+        let first = encoder.encode(1, &world).unwrap();
+        assert_eq!(decoder.decode(&first).unwrap(), world);
+        encoder.ack(1, first.seq());
+
+        // All of these acks are lost.
+        for i in 0..10 {
+            world[i] = 1000 + i as u32;
+            let delta = encoder.encode(1, &world).unwrap();
+            assert_eq!(decoder.decode(&delta).unwrap(), world);
+            // The base is too old to use once 4 snapshots were sent after it.
+            assert_eq!(delta.is_full(), i >= 4);
+        }
+
+        // A delayed ack for a snapshot that the encoder no longer remembers changes nothing.
+        encoder.ack(1, first.seq() + 3);
+        let delta = encoder.encode(1, &world).unwrap();
+        assert!(delta.is_full());
+        assert_eq!(decoder.decode(&delta).unwrap(), world);
+        encoder.ack(1, delta.seq());
+
+        // The decoder keeps the matched base, even after more than `max_bases` snapshots.
+        world[0] = 0;
+        let based = encoder.encode(1, &world).unwrap();
+        assert_eq!(based.base, Some(delta.seq()));
+        assert_eq!(decoder.decode(&based).unwrap(), world);
+        for _ in 0..6 {
+            let full = Delta {
+                seq: 100,
+                base: None,
+                data: bincode::serialize(&world).unwrap(),
+                _t: PhantomData,
+            };
+            decoder.decode(&full).unwrap();
+        }
+        let again = encoder.encode(1, &world).unwrap();
+        assert_eq!(again.base, Some(delta.seq()));
+        assert_eq!(decoder.decode(&again).unwrap(), world);
+    }
+
+    #[test]
+    fn malformed_delta() {
+        let mut huge = vec![];
+        write_varint(&mut huge, usize::MAX);
+        assert!(decode_delta(&[], &huge, 1024).is_err());
+
+        // A zero run that overflows.
+        let mut overflow = vec![];
+        write_varint(&mut overflow, 4);
+        write_varint(&mut overflow, usize::MAX);
+        write_varint(&mut overflow, 2);
+        overflow.extend_from_slice(&[1, 2]);
+        assert!(decode_delta(&[1, 2, 3, 4], &overflow, 1024).is_err());
+    }
+}
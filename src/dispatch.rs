@@ -0,0 +1,124 @@
+//! Calling handlers for received messages.
+//!
+//! Instead of polling `recv::<T>()` for every message type, register a handler for each type on
+//! a [`Dispatcher`]. After `recv_msgs()`, [`Dispatcher::dispatch_client()`] or
+//! [`Dispatcher::dispatch_server()`] calls the matching handler for every buffered message, in
+//! the order the messages arrived. Messages without a handler go to the optional catch-all
+//! handler.
+//!
+//! This is built on [`Client::recv_all_ordered()`] and [`Server::recv_all_ordered()`].
+//!
+//! Handlers get mutable access to a state of type `S`, which is passed in when dispatching.
+//!
+//! ```no_run
+//! # use carrier_pigeon::dispatch::Dispatcher;
+//! # use carrier_pigeon::Client;
+//! # use serde::{Serialize, Deserialize};
+//! # #[derive(Serialize, Deserialize)]
+//! # struct Chat { text: String }
+//! # fn run(client: &Client) {
+//! let mut dispatcher = Dispatcher::<Vec<String>>::new();
+//! dispatcher
+//!     .on::<Chat>(|log, msg| log.push(msg.text.clone()))
+//!     .on_unhandled(|_log, msg| println!("Unhandled MId {} from {}", msg.mid, msg.cid));
+//!
+//! let mut log = vec![];
+//! // Every frame, after `recv_msgs()`:
+//! dispatcher.dispatch_client(client, &mut log);
+//! # }
+//! ```
+
+use crate::net::{AnyNetMsg, NetMsg};
+use crate::{Client, Server};
+use hashbrown::HashMap;
+use std::any::{Any, TypeId};
+
+/// A handler for a single message type.
+type Handler<S> = Box<dyn FnMut(&mut S, AnyNetMsg<'_>) + Send>;
+/// A handler for the messages that do not have their own handler.
+type CatchAll<S> = Box<dyn FnMut(&mut S, AnyNetMsg<'_>) + Send>;
+
+/// A registry of handlers for received messages.
+///
+/// See the [module level docs](self) for more.
+pub struct Dispatcher<S = ()> {
+    handlers: HashMap<TypeId, Handler<S>>,
+    catch_all: Option<CatchAll<S>>,
+}
+
+impl<S> Default for Dispatcher<S> {
+    fn default() -> Self {
+        Dispatcher {
+            handlers: HashMap::new(),
+            catch_all: None,
+        }
+    }
+}
+
+impl<S> Dispatcher<S> {
+    /// Creates a new [`Dispatcher`] without any handlers.
+    pub fn new() -> Self {
+        Dispatcher::default()
+    }
+
+    /// Sets the handler for messages of type `T`, replacing the old one if there is one.
+    pub fn on<T: Any + Send + Sync>(
+        &mut self,
+        mut handler: impl FnMut(&mut S, NetMsg<'_, T>) + Send + 'static,
+    ) -> &mut Self {
+        let handler: Handler<S> = Box::new(move |state, msg| {
+            // The handler was looked up from the TypeId of `T`, so this always succeeds.
+            handler(state, msg.downcast::<T>().unwrap())
+        });
+        self.handlers.insert(TypeId::of::<T>(), handler);
+        self
+    }
+
+    /// Sets the handler for messages whose type does not have a handler.
+    ///
+    /// It is called with the erased message, which includes the [`MId`](crate::MId) of the type.
+    pub fn on_unhandled(
+        &mut self,
+        handler: impl FnMut(&mut S, AnyNetMsg<'_>) + Send + 'static,
+    ) -> &mut Self {
+        self.catch_all = Some(Box::new(handler));
+        self
+    }
+
+    /// Whether there is a handler for messages of type `T`.
+    pub fn is_handled<T: Any + Send + Sync>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Calls the handlers for all the messages buffered on `client`, in the order they arrived.
+    ///
+    /// Make sure to call [`Client::recv_msgs()`] before calling this.
+    ///
+    /// Returns the number of messages that were handled, including by the catch-all handler.
+    pub fn dispatch_client(&mut self, client: &Client, state: &mut S) -> u32 {
+        self.dispatch(client.recv_all_ordered(), state)
+    }
+
+    /// Calls the handlers for all the messages buffered on `server`, in the order they arrived.
+    ///
+    /// Make sure to call [`Server::recv_msgs()`] before calling this.
+    ///
+    /// Returns the number of messages that were handled, including by the catch-all handler.
+    pub fn dispatch_server(&mut self, server: &Server, state: &mut S) -> u32 {
+        self.dispatch(server.recv_all_ordered(), state)
+    }
+
+    /// The shared dispatching logic.
+    fn dispatch<'n>(&mut self, msgs: impl Iterator<Item = AnyNetMsg<'n>>, state: &mut S) -> u32 {
+        let mut i = 0;
+        for msg in msgs {
+            match (self.handlers.get_mut(&msg.type_id()), &mut self.catch_all) {
+                (Some(handler), _) => handler(state, msg),
+                (None, Some(catch_all)) => catch_all(state, msg),
+                (None, None) => continue,
+            }
+            i += 1;
+        }
+        i
+    }
+}
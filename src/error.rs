@@ -0,0 +1,209 @@
+use crate::message_table::MsgTableParts;
+use crate::net::CId;
+use crate::version::Version;
+use crate::MId;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::ErrorKind;
+
+/// The error type of carrier-pigeon.
+#[derive(Debug)]
+pub enum Error {
+    /// The message type was not registered in the [`MsgTable`](crate::MsgTable).
+    ///
+    /// Contains the name of the type.
+    UnregisteredType(&'static str),
+    /// The message type is registered, but it is not the type that was expected. For example, the
+    /// type passed into `disconnect()` is not the disconnection message type.
+    ///
+    /// Contains the name of the type.
+    WrongType(&'static str),
+    /// There is no connection with this [`CId`].
+    InvalidCId(CId),
+    /// A received message had an [`MId`] that is not in the [`MsgTable`](crate::MsgTable), or
+    /// that was not expected at that point.
+    InvalidMId {
+        /// The [`MId`] of the message.
+        mid: MId,
+        /// The name of the message type, if `mid` is in the [`MsgTable`](crate::MsgTable).
+        name: Option<&'static str>,
+    },
+    /// A message is bigger than the maximum message size.
+    MessageTooLarge {
+        /// The [`MId`] of the message.
+        mid: MId,
+        /// The name of the message type, if `mid` is in the [`MsgTable`](crate::MsgTable).
+        name: Option<&'static str>,
+        /// The size of the message in bytes.
+        size: usize,
+        /// The maximum size in bytes.
+        max: usize,
+    },
+    /// A message could not be serialized.
+    Serialize(Box<dyn std::error::Error + Send + Sync>),
+    /// Received data could not be decoded or deserialized.
+    Deserialize(Box<dyn std::error::Error + Send + Sync>),
+    /// An IO error occurred.
+    Io(io::Error),
+    /// The peer has a version of the message type `name` that can't be converted to or from. See
+    /// the [`version`](crate::version) module.
+    IncompatibleVersion {
+        /// The name of the message type.
+        name: &'static str,
+        /// The version of the message type.
+        version: Version,
+    },
+    /// A message type that is not optional is only registered by one of the peers. See the
+    /// [`negotiate`](crate::negotiate) module.
+    ///
+    /// Contains the identifier of the type, or its name if it has none.
+    MissingType(String),
+    /// A message type is registered with a different [`Transport`](crate::Transport) by the peer.
+    ///
+    /// Contains the identifier of the type, or its name if it has none.
+    TransportMismatch(String),
+    /// The message type is optional, and is disabled for the connection because the peer doesn't
+    /// have it. See the [`negotiate`](crate::negotiate) module.
+    ///
+    /// Contains the name of the type.
+    DisabledType(&'static str),
+    /// The server closed the connection before responding to the connection message, or refused
+    /// the connection without a reason.
+    HandshakeRejected,
+    /// The peer did not respond in time.
+    Timeout,
+}
+
+impl Error {
+    /// Creates an [`Error::Deserialize`] from a message.
+    pub(crate) fn deserialize(msg: impl Into<String>) -> Self {
+        Error::Deserialize(msg.into().into())
+    }
+
+    /// Returns whether this is an IO error of kind [`ErrorKind::WouldBlock`].
+    ///
+    /// This means that there is no more data to read without blocking.
+    pub fn is_would_block(&self) -> bool {
+        self.io_kind() == Some(ErrorKind::WouldBlock)
+    }
+
+    /// Returns whether this is an error from matching the message types with a peer.
+    ///
+    /// See the [`negotiate`](crate::negotiate) module.
+    pub(crate) fn is_negotiation(&self) -> bool {
+        matches!(
+            self,
+            Error::MissingType(_) | Error::TransportMismatch(_) | Error::IncompatibleVersion { .. }
+        )
+    }
+
+    /// Fills in the name of the message type from `parts`, if this error has an [`MId`] but no
+    /// name.
+    ///
+    /// The connections only know the [`MId`]s, so this is done by the client and server.
+    pub(crate) fn with_name(self, parts: &MsgTableParts) -> Self {
+        match self {
+            Error::InvalidMId { mid, name: None } => Error::InvalidMId {
+                mid,
+                name: parts.name(mid),
+            },
+            Error::MessageTooLarge {
+                mid,
+                name: None,
+                size,
+                max,
+            } => Error::MessageTooLarge {
+                mid,
+                name: parts.name(mid),
+                size,
+                max,
+            },
+            e => e,
+        }
+    }
+
+    /// Gets the [`ErrorKind`] if this is an [`Error::Io`].
+    pub fn io_kind(&self) -> Option<ErrorKind> {
+        match self {
+            Error::Io(e) => Some(e.kind()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnregisteredType(name) => write!(f, "Type ({}) not registered.", name),
+            Error::WrongType(name) => write!(f, "Type ({}) is not the expected type.", name),
+            Error::InvalidCId(cid) => write!(f, "Invalid CId {}.", cid),
+            Error::InvalidMId { mid, name } => {
+                write!(
+                    f,
+                    "Invalid MId {} ({}).",
+                    mid,
+                    name.unwrap_or("unknown type")
+                )
+            }
+            Error::MessageTooLarge {
+                mid,
+                name,
+                size,
+                max,
+            } => write!(
+                f,
+                "Message of type {} (MId {}) is {} bytes, but the maximum message size is {}.",
+                name.unwrap_or("unknown"),
+                mid,
+                size,
+                max
+            ),
+            Error::Serialize(e) => write!(f, "Serialization error: {}", e),
+            Error::Deserialize(e) => write!(f, "Deserialization error: {}", e),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::IncompatibleVersion { name, version } => write!(
+                f,
+                "Version {} of the message type {} is not supported.",
+                version, name
+            ),
+            Error::MissingType(name) => {
+                write!(
+                    f,
+                    "The message type {} is not registered by the peer.",
+                    name
+                )
+            }
+            Error::TransportMismatch(name) => write!(
+                f,
+                "The message type {} is registered with a different transport by the peer.",
+                name
+            ),
+            Error::DisabledType(name) => write!(
+                f,
+                "The message type {} is disabled, as the peer doesn't have it.",
+                name
+            ),
+            Error::HandshakeRejected => write!(f, "The server closed the connection."),
+            Error::Timeout => write!(f, "Timed out."),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialize(e) | Error::Deserialize(e) => Some(&**e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A [`Result`](std::result::Result) with the carrier-pigeon [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,12 +1,88 @@
 use crate::time::{reconstruct_millis, unix_millis};
-use crate::MId;
+use crate::{Error, MId, Result};
+use std::borrow::Cow;
 
 /// The number of bytes the tcp header takes up.
 pub const TCP_HEADER_LEN: usize = 4;
 
-/// The number of bytes the tcp header takes up.
+/// The number of bytes the udp header takes up.
 pub const UDP_HEADER_LEN: usize = 4;
 
+/// The [`MId`] in the [`UdpHeader`] of a datagram that contains multiple messages.
+///
+/// The header of a batched datagram is followed by the messages, each prefixed with a
+/// [`TcpHeader`] holding its [`MId`] and length.
+pub const UDP_BATCH_MID: MId = 0xFFFF;
+
+/// Set on the [`MId`] in the header of a message whose payload is compressed.
+///
+/// This means that the highest usable [`MId`] is [`MAX_MID`].
+pub const COMPRESSED_FLAG: MId = 0x8000;
+
+/// The highest [`MId`] that a message type can have.
+///
+/// Building a message table with more types than this returns a
+/// [`MsgRegError::TooManyTypes`](crate::MsgRegError::TooManyTypes).
+pub const MAX_MID: MId = 0x7FFE;
+
+/// The number of bytes that each message in a batched datagram is prefixed with.
+pub const UDP_BATCH_ENTRY_LEN: usize = TCP_HEADER_LEN;
+
+/// The number of bytes the optional tick takes up.
+///
+/// When [`Config::tick_header`](crate::net::Config::tick_header) is set, the header of every
+/// message is followed by the sender's current tick, as a big endian u32.
+pub const TICK_LEN: usize = 4;
+
+/// Checks that `payload`, the payload of a message of type `mid` named `name`, is not bigger than
+/// `max`.
+///
+/// This is checked before the payload is handed to the connection, so that the error has the
+/// local [`MId`] instead of the one in the header.
+pub(crate) fn check_size(
+    mid: MId,
+    name: Option<&'static str>,
+    payload: &[u8],
+    max: usize,
+) -> Result<()> {
+    if payload.len() > max {
+        return Err(Error::MessageTooLarge {
+            mid,
+            name,
+            size: payload.len(),
+            max,
+        });
+    }
+    Ok(())
+}
+
+/// Puts `tick` in front of `payload`, if there is a tick.
+pub(crate) fn prepend_tick(tick: Option<u32>, payload: &[u8]) -> Cow<'_, [u8]> {
+    match tick {
+        None => Cow::Borrowed(payload),
+        Some(tick) => {
+            let mut buff = Vec::with_capacity(TICK_LEN + payload.len());
+            buff.extend_from_slice(&tick.to_be_bytes());
+            buff.extend_from_slice(payload);
+            Cow::Owned(buff)
+        }
+    }
+}
+
+/// Splits the tick off of the front of `bytes`, if `has_tick` is set.
+pub(crate) fn split_tick(has_tick: bool, bytes: &[u8]) -> Result<(Option<u32>, &[u8])> {
+    if !has_tick {
+        return Ok((None, bytes));
+    }
+    if bytes.len() < TICK_LEN {
+        return Err(Error::deserialize(
+            "Received a message that is too small to contain a tick.",
+        ));
+    }
+    let tick = u32::from_be_bytes(bytes[..TICK_LEN].try_into().unwrap());
+    Ok((Some(tick), &bytes[TICK_LEN..]))
+}
+
 /// A header to be sent before the payload on TCP.
 ///
 /// `len` and `mid` are sent as big endian u16s.
@@ -90,7 +166,7 @@ impl UdpHeader {
 
 #[cfg(test)]
 mod tests {
-    use crate::header::TcpHeader;
+    use crate::header::{prepend_tick, split_tick, TcpHeader};
 
     #[test]
     fn tcp_to_from_bytes() {
@@ -103,4 +179,18 @@ mod tests {
             assert_eq!(header, de);
         }
     }
+
+    #[test]
+    fn tick() {
+        let with_tick = prepend_tick(Some(0x0102_0304), &[9, 9]);
+        assert_eq!(*with_tick, [1, 2, 3, 4, 9, 9]);
+        assert_eq!(
+            split_tick(true, &with_tick).unwrap(),
+            (Some(0x0102_0304), &[9, 9][..])
+        );
+
+        assert_eq!(*prepend_tick(None, &[9, 9]), [9, 9]);
+        assert_eq!(split_tick(false, &[9, 9]).unwrap(), (None, &[9, 9][..]));
+        assert!(split_tick(true, &[1, 2]).is_err());
+    }
 }
@@ -0,0 +1,116 @@
+//! Interest management.
+//!
+//! Each connected [`CId`](crate::CId) has an [`Interest`] on the server, that decides which
+//! positions in the world are relevant to that client. Messages sent with
+//! [`Server::broadcast_relevant()`](crate::Server::broadcast_relevant) and entities synced with
+//! [`ReplicationServer::sync_relevant()`](crate::replication::ReplicationServer::sync_relevant)
+//! only go to the clients that they are relevant to.
+//!
+//! By default everything is relevant to every client.
+
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Formatter};
+
+/// A position in the world.
+///
+/// 2D games can leave the z coordinate at `0.0`.
+pub type Position = [f32; 3];
+
+/// A region of the world.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Region {
+    /// All positions within `radius` of `center`.
+    Sphere { center: Position, radius: f32 },
+    /// All positions between `min` and `max` on every axis.
+    Box { min: Position, max: Position },
+}
+
+impl Region {
+    /// Creates a new [`Region::Sphere`].
+    pub fn sphere(center: Position, radius: f32) -> Self {
+        Region::Sphere { center, radius }
+    }
+
+    /// Creates a new [`Region::Box`].
+    pub fn cuboid(min: Position, max: Position) -> Self {
+        Region::Box { min, max }
+    }
+
+    /// Whether `pos` is inside this region.
+    pub fn contains(&self, pos: Position) -> bool {
+        match self {
+            Region::Sphere { center, radius } => {
+                let dist_sq: f32 = (0..3).map(|i| (pos[i] - center[i]).powi(2)).sum();
+                dist_sq <= radius * radius
+            }
+            Region::Box { min, max } => (0..3).all(|i| min[i] <= pos[i] && pos[i] <= max[i]),
+        }
+    }
+}
+
+/// Which positions are relevant to a client.
+#[derive(Default)]
+pub enum Interest {
+    /// Everything is relevant.
+    #[default]
+    All,
+    /// Nothing is relevant.
+    None,
+    /// Positions inside any of the regions are relevant.
+    Regions(Vec<Region>),
+    /// Positions for which the callback returns `true` are relevant.
+    Custom(Box<dyn Fn(Position) -> bool + Send + Sync>),
+}
+
+impl Interest {
+    /// Creates a new [`Interest::Custom`] from `relevant`.
+    pub fn custom(relevant: impl Fn(Position) -> bool + Send + Sync + 'static) -> Self {
+        Interest::Custom(Box::new(relevant))
+    }
+
+    /// Whether `pos` is relevant.
+    pub fn is_relevant(&self, pos: Position) -> bool {
+        match self {
+            Interest::All => true,
+            Interest::None => false,
+            Interest::Regions(regions) => regions.iter().any(|r| r.contains(pos)),
+            Interest::Custom(relevant) => relevant(pos),
+        }
+    }
+}
+
+impl Debug for Interest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interest::All => write!(f, "All"),
+            Interest::None => write!(f, "None"),
+            Interest::Regions(regions) => f.debug_tuple("Regions").field(regions).finish(),
+            Interest::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interest::{Interest, Region};
+
+    #[test]
+    fn regions() {
+        let sphere = Region::sphere([0.0, 0.0, 0.0], 10.0);
+        assert!(sphere.contains([6.0, 8.0, 0.0]));
+        assert!(!sphere.contains([6.0, 8.0, 1.0]));
+
+        let cuboid = Region::cuboid([0.0, 0.0, 0.0], [10.0, 10.0, 0.0]);
+        assert!(cuboid.contains([10.0, 5.0, 0.0]));
+        assert!(!cuboid.contains([-1.0, 5.0, 0.0]));
+
+        let interest = Interest::Regions(vec![sphere, cuboid]);
+        assert!(interest.is_relevant([-5.0, 0.0, 0.0]));
+        assert!(interest.is_relevant([10.0, 10.0, 0.0]));
+        assert!(!interest.is_relevant([20.0, 0.0, 0.0]));
+
+        let custom = Interest::custom(|pos| pos[0] > 0.0);
+        assert!(custom.is_relevant([1.0, 0.0, 0.0]));
+        assert!(!custom.is_relevant([-1.0, 0.0, 0.0]));
+    }
+}
@@ -15,18 +15,36 @@
 //! [`examples/` directory](https://github.com/MitchellMarinoDev/carrier-pigeon/blob/main/examples)
 //! on the GitHub repo.
 
+pub mod accept;
+pub mod bandwidth;
+pub mod delta;
+pub mod dispatch;
+pub mod interest;
+pub mod negotiate;
 pub mod net;
+pub mod prediction;
+pub mod rate_limit;
+pub mod replication;
+pub mod rpc;
+pub mod snapshot;
 pub mod tcp;
+pub mod tick;
 pub mod udp;
+pub mod version;
 
 mod client;
+mod compression;
+mod error;
 mod header;
 mod message_table;
 mod server;
 mod time;
 
-pub use client::{Client, OptionPendingClient, PendingClient};
-pub use header::TcpHeader;
-pub use message_table::{MsgRegError, MsgTable, MsgTableParts, SortedMsgTable};
+pub use client::{Client, OptionPendingClient, PendingClient, TypedClient, TypedPendingClient};
+pub use error::{Error, Result};
+pub use header::{TcpHeader, MAX_MID};
+pub use message_table::{
+    MsgHandle, MsgOptions, MsgRegError, MsgTable, MsgTableParts, SortedMsgTable, TypedParts,
+};
 pub use net::{CId, MId, Transport};
-pub use server::Server;
+pub use server::{Server, TypedServer};
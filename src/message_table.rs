@@ -1,13 +1,20 @@
+use crate::header::MAX_MID;
 use crate::message_table::MsgRegError::TypeAlreadyRegistered;
 use crate::net::{DeserFn, SerFn, Transport};
-use crate::MId;
+use crate::rate_limit::RateLimit;
+use crate::rpc::{Request, RpcRequest, RpcResponse};
+use crate::version::{MsgVersions, Version, Versioned};
+use crate::{Error, MId};
 use hashbrown::HashMap;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::any::{Any, TypeId};
-use std::fmt::{Display, Formatter};
-use std::io;
-use MsgRegError::NonUniqueIdentifier;
+use std::any::{type_name, Any, TypeId};
+use std::fmt::{Debug, Display, Formatter, Write};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::time::Duration;
+use MsgRegError::{NonUniqueIdentifier, TooManyTypes, VersionNotOlder};
 
 /// A type for collecting the parts needed to send a struct over the network.
 ///
@@ -16,7 +23,7 @@ use MsgRegError::NonUniqueIdentifier;
 #[derive(Clone, Default)]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Resource))]
 pub struct MsgTable {
-    table: Vec<(TypeId, Transport, SerFn, DeserFn)>,
+    table: Vec<Registration>,
 }
 
 /// A type for collecting the parts needed to send a struct over the network.
@@ -28,12 +35,70 @@ pub struct MsgTable {
 /// If a type is registered with the same name, it will be ignored, therefore namespacing is
 /// encouraged if you are allowing mods or external plugins to add networking types.
 ///
-/// IMPORTANT: The Message tables on all clients and the server **need** to have exactly the
-/// same types registered, although they do **not** need to be registered in the same order.
+/// The peers don't need to have exactly the same types registered. When connecting, the
+/// registrations are matched by their identifiers, and the types that only one side registered
+/// are reported. Types registered as optional (see [`MsgOptions::optional`]) are disabled for
+/// that connection, while any other missing type refuses the connection. See the
+/// [`negotiate`](crate::negotiate) module.
 #[derive(Clone, Default)]
 #[cfg_attr(feature = "bevy", derive(bevy::prelude::Resource))]
 pub struct SortedMsgTable {
-    table: Vec<(String, TypeId, Transport, SerFn, DeserFn)>,
+    table: Vec<(String, Registration)>,
+}
+
+/// Everything that is known about a registered type.
+#[derive(Clone)]
+struct Registration {
+    tid: TypeId,
+    name: &'static str,
+    transport: Transport,
+    options: MsgOptions,
+    ser: SerFn,
+    deser: DeserFn,
+    versions: MsgVersions,
+}
+
+/// Optional settings for a registered message type.
+///
+/// The priority and time-to-live only affect messages sent over [`Transport::UDP`], as TCP messages
+/// are never queued.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct MsgOptions {
+    /// The priority of the message type. When UDP messages are queued, the ones with the
+    /// highest priority are sent first.
+    pub priority: u8,
+    /// How long a queued UDP message stays relevant. Messages that are still queued after
+    /// this time are discarded instead of sent. `None` means they never expire.
+    pub ttl: Option<Duration>,
+    /// Whether the payloads of this message type should be compressed.
+    ///
+    /// Only payloads of at least [`Config::compress_threshold`](crate::net::Config::compress_threshold)
+    /// bytes are compressed. This has no effect unless the `compression` feature is enabled.
+    pub compress: bool,
+    /// The maximum rate that the server receives messages of this type from each connection.
+    /// `None` means unlimited.
+    ///
+    /// See the [`rate_limit`](crate::rate_limit) module.
+    pub recv_rate_limit: Option<RateLimit>,
+    /// Whether the message type can be missing on the peer, like the messages of a mod that only
+    /// some peers have. Missing optional types are disabled for that connection instead of
+    /// refusing it.
+    ///
+    /// See the [`negotiate`](crate::negotiate) module.
+    pub optional: bool,
+}
+
+impl MsgOptions {
+    /// Creates a new [`MsgOptions`].
+    pub fn new(priority: u8, ttl: Option<Duration>) -> Self {
+        MsgOptions {
+            priority,
+            ttl,
+            compress: false,
+            recv_rate_limit: None,
+            optional: false,
+        }
+    }
 }
 
 /// The useful parts of the [`MsgTable`] (or [`SortedMsgTable`]).
@@ -45,12 +110,22 @@ pub struct SortedMsgTable {
 pub struct MsgTableParts {
     /// The mapping from TypeId to MessageId.
     pub tid_map: HashMap<TypeId, MId>,
+    /// The [`TypeId`] of each message type.
+    pub tids: Vec<TypeId>,
+    /// The name of each message type, from [`type_name()`].
+    pub names: Vec<&'static str>,
     /// The transport associated with each message type.
     pub transports: Vec<Transport>,
+    /// The [`MsgOptions`] associated with each message type.
+    pub options: Vec<MsgOptions>,
     /// The serialization functions associated with each message type.
     pub ser: Vec<SerFn>,
     /// The deserialization functions associated with each message type.
     pub deser: Vec<DeserFn>,
+    /// The versions of each message type. See the [`version`](crate::version) module.
+    pub versions: Vec<MsgVersions>,
+    /// The identifier of each message type, if it was registered with a [`SortedMsgTable`].
+    pub identifiers: Vec<Option<String>>,
 }
 
 pub const CONNECTION_TYPE_MID: MId = 0;
@@ -64,22 +139,18 @@ impl MsgTable {
     }
 
     /// Adds all registrations from `other` into this table.
-
+    ///
     /// All errors are thrown before mutating self. If no errors are thrown, all entries are added;
     /// if an error is thrown, no entries are added.
     pub fn join(&mut self, other: &MsgTable) -> Result<(), MsgRegError> {
         // Validate
-        if other
-            .table
-            .iter()
-            .any(|(tid, _, _, _)| self.tid_registered(*tid))
-        {
+        if other.table.iter().any(|reg| self.tid_registered(reg.tid)) {
             return Err(TypeAlreadyRegistered);
         }
 
         // Join
         for entry in other.table.iter() {
-            self.table.push(*entry);
+            self.table.push(entry.clone());
         }
         Ok(())
     }
@@ -95,23 +166,79 @@ impl MsgTable {
 
     /// If the type with [`TypeId`] `tid` has been registered or not.
     pub fn tid_registered(&self, tid: TypeId) -> bool {
-        self.table.iter().any(|(o_tid, _, _, _)| tid == *o_tid)
+        self.table.iter().any(|reg| tid == reg.tid)
     }
 
     /// Registers a message type so that it can be sent over the network.
-    pub fn register<T>(&mut self, transport: Transport) -> Result<(), MsgRegError>
+    ///
+    /// Returns the [`MsgHandle`] of `T`. It stays valid as long as no other [`MsgTable`] is
+    /// joined into this one before `T`'s registration.
+    pub fn register<T>(&mut self, transport: Transport) -> Result<MsgHandle<T>, MsgRegError>
     where
         T: Any + Send + Sync + DeserializeOwned + Serialize,
     {
-        self.table.push(self.get_registration::<T>(transport)?);
-        Ok(())
+        self.register_with::<T>(transport, MsgOptions::default())
+    }
+
+    /// Registers a message type with the given [`MsgOptions`] so that it can be sent over the
+    /// network.
+    ///
+    /// Returns the [`MsgHandle`] of `T`. See [`register()`](Self::register).
+    pub fn register_with<T>(
+        &mut self,
+        transport: Transport,
+        options: MsgOptions,
+    ) -> Result<MsgHandle<T>, MsgRegError>
+    where
+        T: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        let reg = self.get_registration::<T>(transport, options)?;
+        Ok(self.push(reg))
+    }
+
+    /// Registers a message type with the given [`MsgOptions`] and [`Versioned`], so that peers
+    /// with older versions of the type can still send and receive it. See the
+    /// [`version`](crate::version) module.
+    ///
+    /// Returns the [`MsgHandle`] of `T`. See [`register()`](Self::register).
+    pub fn register_versioned<T>(
+        &mut self,
+        transport: Transport,
+        options: MsgOptions,
+        versioned: Versioned<T>,
+    ) -> Result<MsgHandle<T>, MsgRegError>
+    where
+        T: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        let mut reg = self.get_registration::<T>(transport, options)?;
+        reg.versions = versioned.into_versions()?;
+        Ok(self.push(reg))
+    }
+
+    /// Adds the registration `reg` of `T`, and returns the [`MsgHandle`] of `T`.
+    fn push<T>(&mut self, reg: Registration) -> MsgHandle<T> {
+        self.table.push(reg);
+        // The connection, response and disconnect types are put in front when building.
+        MsgHandle::new(self.table.len() + DISCONNECT_TYPE_MID)
+    }
+
+    /// Registers the request type `Req` and its response type, so that `Req` can be used for
+    /// remote procedure calls. See the [`rpc`](crate::rpc) module for more.
+    ///
+    /// Both are sent over TCP.
+    pub fn register_rpc<Req: Request>(&mut self) -> Result<(), MsgRegError> {
+        let mut rpc_table = MsgTable::new();
+        rpc_table.register::<RpcRequest<Req>>(Transport::TCP)?;
+        rpc_table.register::<RpcResponse<Req>>(Transport::TCP)?;
+        self.join(&rpc_table)
     }
 
     /// Builds the things needed for the registration.
     fn get_registration<T>(
         &self,
         transport: Transport,
-    ) -> Result<(TypeId, Transport, SerFn, DeserFn), MsgRegError>
+        options: MsgOptions,
+    ) -> Result<Registration, MsgRegError>
     where
         T: Any + Send + Sync + DeserializeOwned + Serialize,
     {
@@ -127,17 +254,21 @@ impl MsgTable {
         let deser: DeserFn = |bytes: &[u8]| {
             bincode::deserialize::<T>(bytes)
                 .map(|d| Box::new(d) as Box<dyn Any + Send + Sync>)
-                .map_err(|o| {
-                    io::Error::new(io::ErrorKind::InvalidData, format!("Deser Error: {}", o))
-                })
+                .map_err(|e| Error::Deserialize(e))
         };
         let ser: SerFn = |m: &(dyn Any + Send + Sync)| {
-            bincode::serialize(m.downcast_ref::<T>().unwrap()).map_err(|o| {
-                io::Error::new(io::ErrorKind::InvalidData, format!("Ser Error: {}", o))
-            })
+            bincode::serialize(m.downcast_ref::<T>().unwrap()).map_err(|e| Error::Serialize(e))
         };
 
-        Ok((tid, transport, ser, deser))
+        Ok(Registration {
+            tid,
+            name: type_name::<T>(),
+            transport,
+            options,
+            ser,
+            deser,
+            versions: MsgVersions::default(),
+        })
     }
 
     /// Builds the [`MsgTable`] into useful parts.
@@ -149,7 +280,8 @@ impl MsgTable {
     ///  - `R` is the response message type.
     ///  - `D` is the disconnect message type.
     ///
-    /// The generic parameters should **not** be registered before hand.
+    /// The generic parameters should **not** be registered before hand. They can not be versioned;
+    /// see the [`version`](crate::version) module.
     pub fn build<C, R, D>(self) -> Result<MsgTableParts, MsgRegError>
     where
         C: Any + Send + Sync + DeserializeOwned + Serialize,
@@ -159,33 +291,34 @@ impl MsgTable {
         // Always prepend the Connection and Disconnect types first.
         // This gives them universal MIds.
         let con_discon_types = [
-            self.get_registration::<C>(Transport::TCP)?,
-            self.get_registration::<R>(Transport::TCP)?,
-            self.get_registration::<D>(Transport::TCP)?,
+            self.get_registration::<C>(Transport::TCP, MsgOptions::default())?,
+            self.get_registration::<R>(Transport::TCP, MsgOptions::default())?,
+            self.get_registration::<D>(Transport::TCP, MsgOptions::default())?,
         ];
 
-        let mut tid_map = HashMap::with_capacity(self.table.len() + 3);
-        let mut transports = Vec::with_capacity(self.table.len() + 3);
-        let mut ser = Vec::with_capacity(self.table.len() + 3);
-        let mut deser = Vec::with_capacity(self.table.len() + 3);
-
         // Add all types to parts. Connect type first, disconnect type second, all other types after
-        for (idx, (tid, transport, s_fn, d_fn)) in con_discon_types
-            .into_iter()
-            .chain(self.table.into_iter())
-            .enumerate()
-        {
-            tid_map.insert(tid, idx);
-            transports.push(transport);
-            ser.push(s_fn);
-            deser.push(d_fn);
-        }
+        MsgTableParts::from_registrations(
+            con_discon_types
+                .into_iter()
+                .chain(self.table)
+                .map(|reg| (None, reg))
+                .collect(),
+        )
+    }
 
-        Ok(MsgTableParts {
-            tid_map,
-            transports,
-            ser,
-            deser,
+    /// Builds the [`MsgTable`] into [`TypedParts`], which remember the connection (`C`), response
+    /// (`R`) and disconnect (`D`) message types.
+    ///
+    /// See [`build()`](Self::build).
+    pub fn build_typed<C, R, D>(self) -> Result<TypedParts<C, R, D>, MsgRegError>
+    where
+        C: Any + Send + Sync + DeserializeOwned + Serialize,
+        R: Any + Send + Sync + DeserializeOwned + Serialize,
+        D: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        Ok(TypedParts {
+            parts: self.build::<C, R, D>()?,
+            _types: PhantomData,
         })
     }
 }
@@ -205,7 +338,7 @@ impl SortedMsgTable {
         if other
             .table
             .iter()
-            .any(|(_, tid, _, _, _)| self.tid_registered(*tid))
+            .any(|(_, reg)| self.tid_registered(reg.tid))
         {
             return Err(TypeAlreadyRegistered);
         }
@@ -213,7 +346,7 @@ impl SortedMsgTable {
         if other
             .table
             .iter()
-            .any(|(id, _, _, _, _)| self.identifier_registered(id))
+            .any(|(id, _)| self.identifier_registered(id))
         {
             return Err(NonUniqueIdentifier);
         }
@@ -236,30 +369,97 @@ impl SortedMsgTable {
 
     /// If the type with [`TypeId`] `tid` has been registered or not.
     pub fn tid_registered(&self, tid: TypeId) -> bool {
-        self.table.iter().any(|(_, o_tid, _, _, _)| tid == *o_tid)
+        self.table.iter().any(|(_, reg)| tid == reg.tid)
     }
 
     /// If the type with [`TypeId`] `tid` has been registered or not.
     pub fn identifier_registered(&self, identifier: &str) -> bool {
-        self.table.iter().any(|(id, _, _, _, _)| identifier == *id)
+        self.table.iter().any(|(id, _)| identifier == *id)
     }
 
     /// Registers a message type so that it can be sent over the network.
     pub fn register<T>(&mut self, transport: Transport, identifier: &str) -> Result<(), MsgRegError>
+    where
+        T: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        self.register_with::<T>(transport, identifier, MsgOptions::default())
+    }
+
+    /// Registers a message type with the given [`MsgOptions`] so that it can be sent over the
+    /// network.
+    pub fn register_with<T>(
+        &mut self,
+        transport: Transport,
+        identifier: &str,
+        options: MsgOptions,
+    ) -> Result<(), MsgRegError>
     where
         T: Any + Send + Sync + DeserializeOwned + Serialize,
     {
         self.table
-            .push(self.get_registration::<T>(identifier.into(), transport)?);
+            .push(self.get_registration::<T>(identifier.into(), transport, options)?);
         Ok(())
     }
 
+    /// Registers a message type that can be missing on the peer, like the messages of a mod.
+    ///
+    /// See [`MsgOptions::optional`].
+    pub fn register_optional<T>(
+        &mut self,
+        transport: Transport,
+        identifier: &str,
+    ) -> Result<(), MsgRegError>
+    where
+        T: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        let options = MsgOptions {
+            optional: true,
+            ..MsgOptions::default()
+        };
+        self.register_with::<T>(transport, identifier, options)
+    }
+
+    /// Registers a message type with the given [`MsgOptions`] and [`Versioned`], so that peers
+    /// with older versions of the type can still send and receive it. See the
+    /// [`version`](crate::version) module.
+    pub fn register_versioned<T>(
+        &mut self,
+        transport: Transport,
+        identifier: &str,
+        options: MsgOptions,
+        versioned: Versioned<T>,
+    ) -> Result<(), MsgRegError>
+    where
+        T: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        let (identifier, mut reg) =
+            self.get_registration::<T>(identifier.into(), transport, options)?;
+        reg.versions = versioned.into_versions()?;
+        self.table.push((identifier, reg));
+        Ok(())
+    }
+
+    /// Registers the request type `Req` and its response type, so that `Req` can be used for
+    /// remote procedure calls. See the [`rpc`](crate::rpc) module for more.
+    ///
+    /// They are registered with the identifiers `"{identifier}::request"` and
+    /// `"{identifier}::response"`. Both are sent over TCP.
+    pub fn register_rpc<Req: Request>(&mut self, identifier: &str) -> Result<(), MsgRegError> {
+        let mut rpc_table = SortedMsgTable::new();
+        rpc_table
+            .register::<RpcRequest<Req>>(Transport::TCP, &format!("{}::request", identifier))?;
+        rpc_table
+            .register::<RpcResponse<Req>>(Transport::TCP, &format!("{}::response", identifier))?;
+        self.join(&rpc_table)
+    }
+
     /// Builds the things needed for the registration.
     fn get_registration<T>(
         &self,
         identifier: String,
         transport: Transport,
-    ) -> Result<(String, TypeId, Transport, SerFn, DeserFn), MsgRegError>
+        options: MsgOptions,
+    ) -> Result<(String, Registration), MsgRegError>
     where
         T: Any + Send + Sync + DeserializeOwned + Serialize,
     {
@@ -267,14 +467,10 @@ impl SortedMsgTable {
         let deser: DeserFn = |bytes: &[u8]| {
             bincode::deserialize::<T>(bytes)
                 .map(|d| Box::new(d) as Box<dyn Any + Send + Sync>)
-                .map_err(|o| {
-                    io::Error::new(io::ErrorKind::InvalidData, format!("Deser Error: {}", o))
-                })
+                .map_err(|e| Error::Deserialize(e))
         };
         let ser: SerFn = |m: &(dyn Any + Send + Sync)| {
-            bincode::serialize(m.downcast_ref::<T>().unwrap()).map_err(|o| {
-                io::Error::new(io::ErrorKind::InvalidData, format!("Ser Error: {}", o))
-            })
+            bincode::serialize(m.downcast_ref::<T>().unwrap()).map_err(|e| Error::Serialize(e))
         };
 
         // Check if the identifier has been registered already.
@@ -290,7 +486,18 @@ impl SortedMsgTable {
             return Err(TypeAlreadyRegistered);
         }
 
-        Ok((identifier, tid, transport, ser, deser))
+        Ok((
+            identifier,
+            Registration {
+                tid,
+                name: type_name::<T>(),
+                transport,
+                options,
+                ser,
+                deser,
+                versions: MsgVersions::default(),
+            },
+        ))
     }
 
     /// Builds the [`SortedMsgTable`] into useful parts.
@@ -302,7 +509,8 @@ impl SortedMsgTable {
     ///  - `R` is the response message type.
     ///  - `f` is the disconnect message type.
     ///
-    /// The generic parameters should **not** be registered before hand.
+    /// The generic parameters should **not** be registered before hand. They can not be versioned;
+    /// see the [`version`](crate::version) module.
     pub fn build<C, R, D>(mut self) -> Result<MsgTableParts, MsgRegError>
     where
         C: Any + Send + Sync + DeserializeOwned + Serialize,
@@ -312,42 +520,97 @@ impl SortedMsgTable {
         // Always prepend the Connection and Disconnect types first.
         // This gives them universal MIds.
         let con_discon_types = [
-            self.get_registration::<C>("carrier-pigeon::connection".to_owned(), Transport::TCP)?,
-            self.get_registration::<R>("carrier-pigeon::response".to_owned(), Transport::TCP)?,
-            self.get_registration::<D>("carrier-pigeon::disconnect".to_owned(), Transport::TCP)?,
+            self.get_registration::<C>(
+                "carrier-pigeon::connection".to_owned(),
+                Transport::TCP,
+                MsgOptions::default(),
+            )?,
+            self.get_registration::<R>(
+                "carrier-pigeon::response".to_owned(),
+                Transport::TCP,
+                MsgOptions::default(),
+            )?,
+            self.get_registration::<D>(
+                "carrier-pigeon::disconnect".to_owned(),
+                Transport::TCP,
+                MsgOptions::default(),
+            )?,
         ];
 
         // Sort by identifier string so that registration order doesn't matter.
-        self.table
-            .sort_by(|(id0, _, _, _, _), (id1, _, _, _, _)| id0.cmp(id1));
-
-        let mut tid_map = HashMap::with_capacity(self.table.len() + 3);
-        let mut transports = Vec::with_capacity(self.table.len() + 3);
-        let mut ser = Vec::with_capacity(self.table.len() + 3);
-        let mut deser = Vec::with_capacity(self.table.len() + 3);
+        self.table.sort_by(|(id0, _), (id1, _)| id0.cmp(id1));
 
         // Add all types to parts. Connect type first, disconnect type second, all other types after
-        for (idx, (_identifier, tid, transport, s_fn, d_fn)) in con_discon_types
-            .into_iter()
-            .chain(self.table.into_iter())
-            .enumerate()
-        {
-            tid_map.insert(tid, idx);
-            transports.push(transport);
-            ser.push(s_fn);
-            deser.push(d_fn);
+        MsgTableParts::from_registrations(
+            con_discon_types
+                .into_iter()
+                .chain(self.table)
+                .map(|(identifier, reg)| (Some(identifier), reg))
+                .collect(),
+        )
+    }
+
+    /// Builds the [`SortedMsgTable`] into [`TypedParts`], which remember the connection (`C`), response
+    /// (`R`) and disconnect (`D`) message types.
+    ///
+    /// See [`build()`](Self::build).
+    pub fn build_typed<C, R, D>(self) -> Result<TypedParts<C, R, D>, MsgRegError>
+    where
+        C: Any + Send + Sync + DeserializeOwned + Serialize,
+        R: Any + Send + Sync + DeserializeOwned + Serialize,
+        D: Any + Send + Sync + DeserializeOwned + Serialize,
+    {
+        Ok(TypedParts {
+            parts: self.build::<C, R, D>()?,
+            _types: PhantomData,
+        })
+    }
+}
+
+impl MsgTableParts {
+    /// Builds the [`MsgTableParts`] from the registrations, in [`MId`] order.
+    fn from_registrations(
+        registrations: Vec<(Option<String>, Registration)>,
+    ) -> Result<Self, MsgRegError> {
+        let len = registrations.len();
+        if len > MAX_MID + 1 {
+            return Err(TooManyTypes);
+        }
+        let mut tid_map = HashMap::with_capacity(len);
+        let mut tids = Vec::with_capacity(len);
+        let mut names = Vec::with_capacity(len);
+        let mut transports = Vec::with_capacity(len);
+        let mut options = Vec::with_capacity(len);
+        let mut ser = Vec::with_capacity(len);
+        let mut deser = Vec::with_capacity(len);
+        let mut versions = Vec::with_capacity(len);
+        let mut identifiers = Vec::with_capacity(len);
+
+        for (idx, (identifier, reg)) in registrations.into_iter().enumerate() {
+            tid_map.insert(reg.tid, idx);
+            tids.push(reg.tid);
+            names.push(reg.name);
+            transports.push(reg.transport);
+            options.push(reg.options);
+            ser.push(reg.ser);
+            deser.push(reg.deser);
+            versions.push(reg.versions);
+            identifiers.push(identifier);
         }
 
         Ok(MsgTableParts {
             tid_map,
+            tids,
+            names,
             transports,
+            options,
             ser,
             deser,
+            versions,
+            identifiers,
         })
     }
-}
 
-impl MsgTableParts {
     /// Gets the number of registered `MId`s.
     pub fn mid_count(&self) -> usize {
         self.transports.len()
@@ -355,13 +618,179 @@ impl MsgTableParts {
 
     /// Checks if the [`MId`] `mid` is valid.
     pub fn valid_mid(&self, mid: MId) -> bool {
-        mid <= self.mid_count()
+        mid < self.mid_count()
     }
 
     /// Checks if the [`TypeId`] `tid` is registered.
     pub fn valid_tid(&self, tid: TypeId) -> bool {
         self.tid_map.contains_key(&tid)
     }
+
+    /// Checks if `T` is the type registered with the [`MId`] `mid`.
+    pub fn is_type<T: Any>(&self, mid: MId) -> bool {
+        self.tids.get(mid) == Some(&TypeId::of::<T>())
+    }
+
+    /// Gets the [`MsgHandle`] of the type `T`, or `None` if `T` is not registered.
+    ///
+    /// This is how to get the handles of types registered with a [`SortedMsgTable`], as their
+    /// [`MId`]s are only known after it is built.
+    pub fn handle<T: Any + Send + Sync>(&self) -> Option<MsgHandle<T>> {
+        let mid = *self.tid_map.get(&TypeId::of::<T>())?;
+        Some(MsgHandle::new(mid))
+    }
+
+    /// Gets the name of the type registered with the [`MId`] `mid`.
+    pub fn name(&self, mid: MId) -> Option<&'static str> {
+        self.names.get(mid).copied()
+    }
+
+    /// Gets the identifier of the type registered with the [`MId`] `mid`.
+    ///
+    /// Returns `None` if `mid` is not valid, or if the type was not registered with a
+    /// [`SortedMsgTable`].
+    pub fn identifier(&self, mid: MId) -> Option<&str> {
+        self.identifiers.get(mid)?.as_deref()
+    }
+
+    /// Lists the registered types, one per line, in [`MId`] order. Each line has the [`MId`], the
+    /// name of the type, its identifier (if it has one) and its [`Transport`]:
+    ///
+    /// ```text
+    /// 3: my_game::Chat "my_game::chat" TCP
+    /// ```
+    ///
+    /// This is useful for finding the type of an [`MId`] in a log.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        for mid in 0..self.mid_count() {
+            let _ = write!(out, "{}: {}", mid, self.names[mid]);
+            if let Some(identifier) = self.identifier(mid) {
+                let _ = write!(out, " {:?}", identifier);
+            }
+            let _ = writeln!(out, " {:?}", self.transports[mid]);
+        }
+        out
+    }
+}
+
+impl Debug for MsgTableParts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.names.iter().enumerate())
+            .finish()
+    }
+}
+
+/// A token for the registered message type `T`, holding its [`MId`].
+///
+/// Returned when registering `T` with a [`MsgTable`], or from [`MsgTableParts::handle()`].
+/// Sending and receiving with a handle (like with [`Client::send_with()`](crate::Client::send_with))
+/// skips looking up the [`MId`] of `T`, and can't fail because `T` is not registered.
+///
+/// A handle is only valid for the [`MsgTableParts`] that it came from.
+pub struct MsgHandle<T> {
+    mid: MId,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T> MsgHandle<T> {
+    /// Creates a new [`MsgHandle`] for the [`MId`] `mid`.
+    fn new(mid: MId) -> Self {
+        MsgHandle {
+            mid,
+            _t: PhantomData,
+        }
+    }
+
+    /// Gets the [`MId`] of `T`.
+    pub fn mid(&self) -> MId {
+        self.mid
+    }
+}
+
+impl<T> Copy for MsgHandle<T> {}
+
+impl<T> Clone for MsgHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for MsgHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.mid == other.mid
+    }
+}
+
+impl<T> Eq for MsgHandle<T> {}
+
+impl<T> Hash for MsgHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.mid.hash(state);
+    }
+}
+
+impl<T> Debug for MsgHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MsgHandle<{}>({})", type_name::<T>(), self.mid)
+    }
+}
+
+/// [`MsgTableParts`] that remember the connection (`C`), response (`R`) and disconnect (`D`)
+/// message types that they were built with.
+///
+/// This lets the compiler check that the right types are used when connecting, instead of
+/// failing at runtime. Build this with [`MsgTable::build_typed()`] or
+/// [`SortedMsgTable::build_typed()`].
+///
+/// Derefs to the erased [`MsgTableParts`], and can be turned into them with
+/// [`erase()`](Self::erase) for the APIs that take them.
+pub struct TypedParts<C, R, D> {
+    parts: MsgTableParts,
+    #[allow(clippy::type_complexity)]
+    _types: PhantomData<fn() -> (C, R, D)>,
+}
+
+impl<C, R, D> TypedParts<C, R, D> {
+    /// Forgets the message types, giving back the [`MsgTableParts`].
+    pub fn erase(self) -> MsgTableParts {
+        self.parts
+    }
+}
+
+impl<C, R, D> Clone for TypedParts<C, R, D> {
+    fn clone(&self) -> Self {
+        TypedParts {
+            parts: self.parts.clone(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<C, R, D> Debug for TypedParts<C, R, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedParts")
+            .field("connection", &type_name::<C>())
+            .field("response", &type_name::<R>())
+            .field("disconnect", &type_name::<D>())
+            .field("mid_count", &self.parts.mid_count())
+            .finish()
+    }
+}
+
+impl<C, R, D> Deref for TypedParts<C, R, D> {
+    type Target = MsgTableParts;
+
+    fn deref(&self) -> &Self::Target {
+        &self.parts
+    }
+}
+
+impl<C, R, D> From<TypedParts<C, R, D>> for MsgTableParts {
+    fn from(value: TypedParts<C, R, D>) -> Self {
+        value.erase()
+    }
 }
 
 /// The possible errors when registering a type.
@@ -371,6 +800,15 @@ pub enum MsgRegError {
     TypeAlreadyRegistered,
     /// The identifier string was already used.
     NonUniqueIdentifier,
+    /// More types were registered than there are [`MId`]s. See [`MAX_MID`].
+    TooManyTypes,
+    /// A [`Versioned`] has conversions for a version that is not older than its current version.
+    VersionNotOlder {
+        /// The version of the conversions.
+        version: Version,
+        /// The current version.
+        current: Version,
+    },
 }
 
 impl Display for MsgRegError {
@@ -378,6 +816,12 @@ impl Display for MsgRegError {
         match self {
             TypeAlreadyRegistered => write!(f, "Type was already registered."),
             NonUniqueIdentifier => write!(f, "The identifier was not unique."),
+            TooManyTypes => write!(f, "Too many types were registered."),
+            VersionNotOlder { version, current } => write!(
+                f,
+                "Version {} is not older than the current version {}.",
+                version, current
+            ),
         }
     }
 }
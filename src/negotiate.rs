@@ -0,0 +1,318 @@
+//! Negotiating the message table with a peer, for games with mods or plugins.
+//!
+//! When connecting, both peers send a [`MsgInfo`] for each of their registered message types.
+//! Each peer then matches the other peer's message types to its own, and builds a [`Negotiated`]
+//! with the mapping between their [`MId`]s. Types registered with a
+//! [`SortedMsgTable`](crate::SortedMsgTable) are matched by their identifiers, so the peers can
+//! register different types, in any order. Types registered with a [`MsgTable`](crate::MsgTable)
+//! have no identifiers, and are matched by their position.
+//!
+//! Messages are always sent with the [`MId`] of the receiving peer. A message type that only one
+//! of the peers registered is reported in the [`Negotiated`]. If it was registered as optional
+//! (see [`MsgOptions::optional`](crate::MsgOptions::optional)), it is disabled for that
+//! connection: sending it returns an [`Error::DisabledType`], and it is never received. If it
+//! is not optional, the connection is refused with an [`Error::MissingType`]. When the server
+//! refuses a connection like this, it still sends its message types back, so that the client
+//! gets the same error.
+//!
+//! ```
+//! # use carrier_pigeon::{SortedMsgTable, Transport};
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct Chat(String);
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Emote(u32);
+//!
+//! let mut table = SortedMsgTable::new();
+//! table.register::<Chat>(Transport::TCP, "game::chat").unwrap();
+//! // Only the peers with the emote mod have this type.
+//! table
+//!     .register_optional::<Emote>(Transport::UDP, "emote_mod::emote")
+//!     .unwrap();
+//! ```
+
+use crate::message_table::{MsgTableParts, DISCONNECT_TYPE_MID};
+use crate::net::Transport;
+use crate::version::Version;
+use crate::{Error, MId, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// What is sent to the peer about a registered message type when connecting.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MsgInfo {
+    /// The name of the type, from [`type_name()`](std::any::type_name).
+    pub name: String,
+    /// The identifier of the type, if it was registered with a
+    /// [`SortedMsgTable`](crate::SortedMsgTable).
+    pub identifier: Option<String>,
+    /// The transport of the type.
+    pub transport: Transport,
+    /// The current version of the type.
+    pub version: Version,
+    /// The older versions of the type that can be both sent and received.
+    pub older: Vec<Version>,
+    /// Whether the type can be missing on the peer.
+    pub optional: bool,
+}
+
+impl MsgInfo {
+    /// Returns whether messages of version `version` can be sent and received.
+    fn supports(&self, version: Version) -> bool {
+        version == self.version || self.older.contains(&version)
+    }
+}
+
+/// The message types that were matched with a peer.
+///
+/// See the [module level docs](self) for more.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Negotiated {
+    /// The version of each message type that was picked, in [`MId`] order.
+    versions: Vec<Version>,
+    /// The [`MId`] of each message type on the peer, or `None` if it is disabled.
+    peer_mids: Vec<Option<MId>>,
+    /// The message types that the peer doesn't have.
+    missing_on_peer: Vec<String>,
+    /// The message types of the peer that are not registered.
+    missing_locally: Vec<String>,
+}
+
+impl Negotiated {
+    /// Creates a [`Negotiated`] for a peer with exactly the same message types as `parts`.
+    ///
+    /// This is used before the handshake is done.
+    pub(crate) fn local(parts: &MsgTableParts) -> Self {
+        Negotiated {
+            versions: parts.current_versions(),
+            peer_mids: (0..parts.mid_count()).map(Some).collect(),
+            missing_on_peer: vec![],
+            missing_locally: vec![],
+        }
+    }
+
+    /// Matches the message types of a peer, described by `peer`, to the ones in `parts`.
+    pub(crate) fn new(parts: &MsgTableParts, peer: &[MsgInfo]) -> Result<Self> {
+        if peer.len() <= DISCONNECT_TYPE_MID {
+            return Err(Error::deserialize(
+                "The peer is missing the connection, response or disconnect type.",
+            ));
+        }
+
+        let manifest = parts.manifest();
+        let mut versions = parts.current_versions();
+        let mut peer_mids = vec![None; manifest.len()];
+        let mut matched = vec![false; peer.len()];
+        let mut missing_on_peer = vec![];
+
+        for (mid, info) in manifest.iter().enumerate() {
+            let name = describe(info);
+            let peer_mid = match mid <= DISCONNECT_TYPE_MID {
+                // The connection, response and disconnect types always have the same MIds.
+                true => Some(mid),
+                false => find(info, mid, peer),
+            };
+            let peer_mid = match peer_mid {
+                Some(peer_mid) => peer_mid,
+                None if info.optional => {
+                    warn!(
+                        "The peer doesn't have the message type {}. It is disabled.",
+                        name
+                    );
+                    missing_on_peer.push(name);
+                    continue;
+                }
+                None => return Err(Error::MissingType(name)),
+            };
+            matched[peer_mid] = true;
+
+            let peer_info = &peer[peer_mid];
+            match pick(info, peer_info, parts.names[mid], &name) {
+                Ok(version) => {
+                    versions[mid] = version;
+                    peer_mids[mid] = Some(peer_mid);
+                }
+                Err(e) if info.optional || peer_info.optional => {
+                    warn!("The message type {} is disabled. {}", name, e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut missing_locally = vec![];
+        for (peer_mid, peer_info) in peer.iter().enumerate() {
+            if matched[peer_mid] {
+                continue;
+            }
+            let name = describe(peer_info);
+            if !peer_info.optional {
+                return Err(Error::MissingType(name));
+            }
+            warn!("The message type {} of the peer is not registered.", name);
+            missing_locally.push(name);
+        }
+
+        Ok(Negotiated {
+            versions,
+            peer_mids,
+            missing_on_peer,
+            missing_locally,
+        })
+    }
+
+    /// Gets the version of each message type that was picked, in [`MId`] order.
+    ///
+    /// See the [`version`](crate::version) module.
+    pub fn versions(&self) -> &[Version] {
+        &self.versions
+    }
+
+    /// Gets the [`MId`] that the peer has for the message type with [`MId`] `mid`.
+    ///
+    /// Returns `None` if the message type is disabled, or if `mid` is not valid.
+    pub fn peer_mid(&self, mid: MId) -> Option<MId> {
+        self.peer_mids.get(mid).copied().flatten()
+    }
+
+    /// Returns whether the message type with [`MId`] `mid` can be sent and received.
+    pub fn is_enabled(&self, mid: MId) -> bool {
+        self.peer_mid(mid).is_some()
+    }
+
+    /// Gets the message types that the peer doesn't have, which are disabled.
+    ///
+    /// The types are described by their identifier, or their name if they have none.
+    pub fn missing_on_peer(&self) -> &[String] {
+        &self.missing_on_peer
+    }
+
+    /// Gets the message types of the peer that are not registered, which are disabled.
+    ///
+    /// The types are described by their identifier, or their name if they have none.
+    pub fn missing_locally(&self) -> &[String] {
+        &self.missing_locally
+    }
+
+    /// Gets the [`MId`] to send the message type with [`MId`] `mid` in `parts` with.
+    pub(crate) fn wire_mid(&self, parts: &MsgTableParts, mid: MId) -> Result<MId> {
+        self.peer_mid(mid)
+            .ok_or(Error::DisabledType(parts.names[mid]))
+    }
+}
+
+/// Finds the [`MId`] that the peer has for the message type `info` with [`MId`] `mid`.
+fn find(info: &MsgInfo, mid: MId, peer: &[MsgInfo]) -> Option<MId> {
+    match &info.identifier {
+        Some(identifier) => peer
+            .iter()
+            .position(|p| p.identifier.as_ref() == Some(identifier)),
+        None => peer
+            .get(mid)
+            .filter(|p| p.identifier.is_none())
+            .map(|_| mid),
+    }
+}
+
+/// Picks the version of the message type `type_name` to use with the peer.
+///
+/// `name` is the description of the type from [`describe()`].
+fn pick(info: &MsgInfo, peer: &MsgInfo, type_name: &'static str, name: &str) -> Result<Version> {
+    if info.transport != peer.transport {
+        return Err(Error::TransportMismatch(name.to_owned()));
+    }
+    let version = info.version.min(peer.version);
+    match info.supports(version) && peer.supports(version) {
+        true => Ok(version),
+        false => Err(Error::IncompatibleVersion {
+            name: type_name,
+            version,
+        }),
+    }
+}
+
+/// Describes the message type `info` for errors and logs, by its identifier, or by its name if
+/// it has none.
+fn describe(info: &MsgInfo) -> String {
+    match &info.identifier {
+        Some(identifier) => identifier.clone(),
+        None => info.name.clone(),
+    }
+}
+
+impl MsgTableParts {
+    /// Gets the [`MsgInfo`] of every message type, in [`MId`] order.
+    pub fn manifest(&self) -> Vec<MsgInfo> {
+        (0..self.mid_count())
+            .map(|mid| MsgInfo {
+                name: self.names[mid].to_owned(),
+                identifier: self.identifiers[mid].clone(),
+                transport: self.transports[mid],
+                version: self.versions[mid].current,
+                older: self.versions[mid].supported_older().collect(),
+                optional: self.options[mid].optional,
+            })
+            .collect()
+    }
+}
+
+/// Put after the [`MsgInfo`]s of the server in a response, when the server matched the message
+/// types. The response message follows it.
+const MATCHED: u8 = 0;
+
+/// Put after the [`MsgInfo`]s of the server in a response, when the server could not match the
+/// message types. Nothing follows it.
+const NOT_MATCHED: u8 = 1;
+
+/// Builds the payload of the response to a new connection, with the [`MsgInfo`]s `manifest` of
+/// the server.
+///
+/// `resp` is the serialized response message, or `None` if the message types could not be
+/// matched. The client then matches them itself, to find out why.
+pub(crate) fn response_payload(manifest: &[MsgInfo], resp: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut payload = vec![];
+    match resp {
+        Some(resp) => {
+            payload.push(MATCHED);
+            payload.extend_from_slice(resp);
+        }
+        None => payload.push(NOT_MATCHED),
+    }
+    prepend_manifest(manifest, &payload)
+}
+
+/// Splits the payload of a response, that was built with [`response_payload`], into the
+/// [`MsgInfo`]s of the server and the response message.
+///
+/// The response message is `None` if the server could not match the message types.
+pub(crate) fn split_response(bytes: &[u8]) -> Result<(Vec<MsgInfo>, Option<&[u8]>)> {
+    let (manifest, bytes) = split_manifest(bytes)?;
+    match bytes.split_first() {
+        Some((&MATCHED, resp)) => Ok((manifest, Some(resp))),
+        Some((&NOT_MATCHED, _)) => Ok((manifest, None)),
+        _ => Err(Error::deserialize(
+            "The response doesn't say whether the message types were matched.",
+        )),
+    }
+}
+
+/// Puts the [`MsgInfo`]s `manifest` in front of `payload`.
+pub(crate) fn prepend_manifest(manifest: &[MsgInfo], payload: &[u8]) -> Result<Vec<u8>> {
+    let manifest = bincode::serialize(manifest).map_err(|e| Error::Serialize(e))?;
+    let mut out = Vec::with_capacity(4 + manifest.len() + payload.len());
+    out.extend_from_slice(&(manifest.len() as u32).to_be_bytes());
+    out.extend_from_slice(&manifest);
+    out.extend_from_slice(payload);
+    Ok(out)
+}
+
+/// Splits the [`MsgInfo`]s that were put in front of `bytes` by [`prepend_manifest`] from the
+/// payload.
+pub(crate) fn split_manifest(bytes: &[u8]) -> Result<(Vec<MsgInfo>, &[u8])> {
+    let invalid = || Error::deserialize("The message ended in the middle of the message types.");
+    let len = u32::from_be_bytes(bytes.get(..4).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+    let end = 4 + len;
+    let manifest = bincode::deserialize(bytes.get(4..end).ok_or_else(invalid)?)
+        .map_err(|e| Error::Deserialize(e))?;
+    Ok((manifest, &bytes[end..]))
+}
@@ -1,12 +1,15 @@
 //! Networking things that are not specific to either transport.
 
+use crate::bandwidth::BandwidthLimit;
 pub use crate::header::TcpHeader;
+use crate::rate_limit::RateLimit;
+use crate::{Error, Result};
 use serde::{Deserialize, Serialize};
-use std::any::Any;
+use std::any::{Any, TypeId};
+use std::collections::VecDeque;
 use std::fmt::{Debug, Display, Formatter};
-use std::io;
-use std::io::Error;
-use std::ops::Deref;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
 use std::time::Duration;
 
 /// The maximum safe message size that can be sent on udp,
@@ -21,7 +24,7 @@ pub const MAX_SAFE_MESSAGE_SIZE: usize = 504;
 ///
 /// - TCP is reliable but slower.
 /// - UDP is unreliable but quicker.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Transport {
     TCP,
     UDP,
@@ -29,12 +32,12 @@ pub enum Transport {
 
 /// The function used to deserialize a message.
 ///
-/// fn(&[u8]) -> Result<Box<dyn Any + Send + Sync>, io::Error>
-pub type DeserFn = fn(&[u8]) -> Result<Box<dyn Any + Send + Sync>, io::Error>;
+/// fn(&[u8]) -> Result<Box<dyn Any + Send + Sync>>
+pub type DeserFn = fn(&[u8]) -> Result<Box<dyn Any + Send + Sync>>;
 /// The function used to serialize a message.
 ///
-/// fn(&(dyn Any + Send + Sync)) -> Result<Vec<u8>, io::Error>
-pub type SerFn = fn(&(dyn Any + Send + Sync)) -> Result<Vec<u8>, io::Error>;
+/// `fn(&(dyn Any + Send + Sync)) -> Result<Vec<u8>>`
+pub type SerFn = fn(&(dyn Any + Send + Sync)) -> Result<Vec<u8>>;
 
 #[derive(Debug)]
 /// An enum for the possible states of a connection.
@@ -47,6 +50,26 @@ pub enum Status {
     Closed,
     /// The connection was dropped without sending a disconnection message.
     Dropped(Error),
+    /// The connection was closed by the server because the peer misbehaved.
+    Kicked(KickReason),
+}
+
+/// The reason that the server kicked a connection.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KickReason {
+    /// The peer went over a receive rate limit. See the [`rate_limit`](crate::rate_limit) module.
+    RateLimit,
+    /// The peer overflowed the receive buffers. See [`Config::overflow`].
+    BufferOverflow,
+}
+
+impl Display for KickReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimit => write!(f, "rate limit exceeded"),
+            Self::BufferOverflow => write!(f, "receive buffer overflowed"),
+        }
+    }
 }
 
 impl Display for Status {
@@ -56,6 +79,7 @@ impl Display for Status {
             Self::Disconnected(_) => write!(f, "Disconnected gracefully"),
             Self::Closed => write!(f, "Closed"),
             Self::Dropped(e) => write!(f, "Dropped with error {}", e),
+            Self::Kicked(reason) => write!(f, "Kicked because the {}", reason),
         }
     }
 }
@@ -68,12 +92,11 @@ impl Status {
 
     /// Turns this into an option with the disconnect message.
     ///
-    /// ### Panics
-    /// Panics if the generic parameter `D` isn't the disconnect message type (the same `D` that
-    /// you passed into `MsgTable::build`).
+    /// Returns `None` if the generic parameter `D` isn't the disconnect message type (the same `D`
+    /// that you passed into `MsgTable::build`).
     pub fn disconnected<D: Any + Send + Sync>(&self) -> Option<&D> {
         match self {
-            Status::Disconnected(d) => Some(d.downcast_ref().expect("The generic parameter `D` must be the disconnection message type (the same `D` that you passed into `MsgTable::build`).")),
+            Status::Disconnected(d) => d.downcast_ref(),
             _ => None,
         }
     }
@@ -98,6 +121,67 @@ impl Status {
     pub fn closed(&self) -> bool {
         matches!(self, Status::Closed)
     }
+
+    /// Turns this into an option with the kick reason.
+    pub fn kicked(&self) -> Option<KickReason> {
+        match self {
+            Status::Kicked(reason) => Some(*reason),
+            _ => None,
+        }
+    }
+}
+
+/// A borrowed [`Status`] that knows the disconnect message type `D`.
+///
+/// Derefs to the [`Status`]. Unlike [`Status::disconnected()`], [`disconnected()`](Self::disconnected)
+/// can not be called with the wrong type.
+pub struct TypedStatus<'s, D> {
+    status: &'s Status,
+    _d: PhantomData<fn() -> D>,
+}
+
+impl<'s, D: Any + Send + Sync> TypedStatus<'s, D> {
+    /// Creates a new [`TypedStatus`].
+    pub(crate) fn new(status: &'s Status) -> Self {
+        TypedStatus {
+            status,
+            _d: PhantomData,
+        }
+    }
+
+    /// Turns this into an option with the disconnect message.
+    pub fn disconnected(&self) -> Option<&'s D> {
+        self.status.disconnected()
+    }
+}
+
+// Derive would require `D: Clone`.
+impl<D> Clone for TypedStatus<'_, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<D> Copy for TypedStatus<'_, D> {}
+
+impl<D> Debug for TypedStatus<'_, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.status, f)
+    }
+}
+
+impl<D> Display for TypedStatus<'_, D> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self.status, f)
+    }
+}
+
+impl<D> Deref for TypedStatus<'_, D> {
+    type Target = Status;
+
+    fn deref(&self) -> &Self::Target {
+        self.status
+    }
 }
 
 /// Message ID.
@@ -165,6 +249,69 @@ pub struct Config {
     /// Any attempts to send messages over this size will be discarded. Keep in mind, there is
     /// still a soft limit for UDP messages (`MAX_SAFE_MSG_SIZE`)
     pub max_msg_size: usize,
+    /// The default send rate limit of each connection. `None` means unlimited.
+    ///
+    /// The server can change the limit of individual connections with
+    /// `Server::set_bandwidth_limit`.
+    pub bandwidth: Option<BandwidthLimit>,
+    /// The send rate limit of all connections of the server together. `None` means unlimited.
+    ///
+    /// Messages need to fit in both their connection's budget and this one. The client ignores
+    /// this. The server can change it with `Server::set_total_bandwidth_limit`.
+    pub total_bandwidth: Option<BandwidthLimit>,
+    /// Whether UDP messages are held back until `send_queued()` is called, instead of being sent
+    /// right away.
+    ///
+    /// This lets the highest priority messages of every frame be sent first (see
+    /// [`MsgOptions`](crate::MsgOptions)), which matters when the connection has a bandwidth limit.
+    pub schedule_udp: bool,
+    /// Whether the UDP messages sent in a frame are packed together into as few datagrams as
+    /// possible, when `send_queued()` is called.
+    ///
+    /// This implies [`schedule_udp`](Self::schedule_udp). Both peers need to support batching,
+    /// which all versions of carrier-pigeon that have this option do.
+    pub batch_udp: bool,
+    /// The minimum payload size in bytes for a message to be compressed.
+    ///
+    /// Only message types that opted in with [`MsgOptions::compress`](crate::MsgOptions::compress)
+    /// are compressed, and only when the `compression` feature is enabled.
+    pub compress_threshold: usize,
+    /// Whether every message carries the current simulation tick of the sender in its header.
+    ///
+    /// The tick is set with `set_tick()` on the client or server, which the
+    /// [`TickRunner`](crate::tick::TickRunner) does automatically. Received messages have it in
+    /// [`NetMsg::tick`]. This adds 4 bytes to every message, which count towards
+    /// [`max_msg_size`](Self::max_msg_size). Both peers need to use the same value.
+    pub tick_header: bool,
+    /// How long a remote procedure call waits for its response before timing out.
+    pub call_timeout: Duration,
+    /// The maximum number of received messages of one type that the server buffers.
+    /// `None` means unlimited.
+    ///
+    /// When full, [`overflow`](Self::overflow) decides what happens. Only used by the server.
+    pub max_buffered_per_mid: Option<usize>,
+    /// The maximum number of received messages from one connection that the server buffers.
+    /// `None` means unlimited.
+    ///
+    /// When full, [`overflow`](Self::overflow) decides what happens. Only used by the server.
+    pub max_buffered_per_cid: Option<usize>,
+    /// What the server does with a received message when a receive buffer is full.
+    pub overflow: OverflowPolicy,
+    /// The maximum rate that the server receives messages from each connection. `None` means
+    /// unlimited.
+    ///
+    /// See the [`rate_limit`](crate::rate_limit) module.
+    pub recv_rate_limit: Option<RateLimit>,
+    /// The maximum number of connections to the server. `None` means unlimited.
+    ///
+    /// When the server is full, new connections are rejected without calling the hook of
+    /// `handle_new_cons()`, with the response set with `Server::set_full_response()`.
+    pub max_connections: Option<usize>,
+    /// The number of the [`max_connections`](Self::max_connections) slots that are reserved.
+    ///
+    /// Only new connections that pass the filter set with `Server::set_reserved_filter()` can use
+    /// the reserved slots, for example admins.
+    pub reserved_slots: usize,
 }
 
 impl Config {
@@ -174,6 +321,19 @@ impl Config {
             timeout,
             max_con_handle,
             max_msg_size,
+            bandwidth: None,
+            total_bandwidth: None,
+            schedule_udp: false,
+            batch_udp: false,
+            compress_threshold: 128,
+            tick_header: false,
+            call_timeout: Duration::from_secs(10),
+            max_buffered_per_mid: None,
+            max_buffered_per_cid: None,
+            overflow: OverflowPolicy::DropNewest,
+            recv_rate_limit: None,
+            max_connections: None,
+            reserved_slots: 0,
         }
     }
 }
@@ -184,10 +344,42 @@ impl Default for Config {
             timeout: Duration::from_millis(5_000),
             max_con_handle: 4,
             max_msg_size: 2048,
+            bandwidth: None,
+            total_bandwidth: None,
+            schedule_udp: false,
+            batch_udp: false,
+            compress_threshold: 128,
+            tick_header: false,
+            call_timeout: Duration::from_secs(10),
+            max_buffered_per_mid: None,
+            max_buffered_per_cid: None,
+            overflow: OverflowPolicy::DropNewest,
+            recv_rate_limit: None,
+            max_connections: None,
+            reserved_slots: 0,
         }
     }
 }
 
+/// What to do with a received message when a receive buffer is full.
+///
+/// The receive buffers are limited by [`Config::max_buffered_per_mid`] and
+/// [`Config::max_buffered_per_cid`]. Messages are removed from the buffers with `clear_msgs()`,
+/// `drain()` or `take()`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered message to make room for the new one.
+    ///
+    /// When the sender is over [`Config::max_buffered_per_cid`], one of its own messages is
+    /// dropped, so that other connections don't lose messages because of it.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Drop the new message, and disconnect the connection that sent it with
+    /// [`KickReason::BufferOverflow`].
+    Disconnect,
+}
+
 /// An untyped network message containing the message content, along with the metadata associated.
 #[derive(Debug)]
 pub(crate) struct ErasedNetMsg {
@@ -197,20 +389,118 @@ pub(crate) struct ErasedNetMsg {
     ///
     /// This is always `Some` if the message was sent with UDP, and always `None` if sent with TCP.
     pub(crate) time: Option<u32>,
+    /// The simulation tick that the message was sent on.
+    ///
+    /// This is `Some` if [`Config::tick_header`] is set.
+    pub(crate) tick: Option<u32>,
+    /// The order that the message arrived in. Increases by one for every received message.
+    pub(crate) seq: u64,
     /// The actual message.
     pub(crate) msg: Box<dyn Any + Send + Sync>,
 }
 
 impl ErasedNetMsg {
     /// Converts this to NetMsg, borrowed from this.
-    pub(crate) fn to_typed<T: Any + Send + Sync>(&self) -> Option<NetMsg<T>> {
+    pub(crate) fn to_typed<T: Any + Send + Sync>(&self) -> Option<NetMsg<'_, T>> {
         let msg = self.msg.downcast_ref()?;
         Some(NetMsg {
             cid: self.cid,
             time: self.time,
+            tick: self.tick,
             m: msg,
         })
     }
+
+    /// Converts this to an [`OwnedNetMsg`], consuming this.
+    pub(crate) fn into_typed<T: Any + Send + Sync>(self) -> Option<OwnedNetMsg<T>> {
+        let msg = self.msg.downcast().ok()?;
+        Some(OwnedNetMsg {
+            cid: self.cid,
+            time: self.time,
+            tick: self.tick,
+            m: *msg,
+        })
+    }
+
+    /// Converts this to an [`AnyNetMsg`], borrowed from this.
+    pub(crate) fn to_any(&self, mid: MId) -> AnyNetMsg<'_> {
+        AnyNetMsg {
+            cid: self.cid,
+            mid,
+            seq: self.seq,
+            time: self.time,
+            tick: self.tick,
+            m: &*self.msg,
+        }
+    }
+}
+
+/// Gets all the messages in `msg_buff` in the order they arrived.
+pub(crate) fn ordered_msgs(msg_buff: &[VecDeque<ErasedNetMsg>]) -> Vec<AnyNetMsg<'_>> {
+    let mut msgs: Vec<_> = msg_buff
+        .iter()
+        .enumerate()
+        .flat_map(|(mid, buff)| buff.iter().map(move |msg| msg.to_any(mid)))
+        .collect();
+    msgs.sort_by_key(|msg| msg.seq);
+    msgs
+}
+
+/// A network message of any type, containing the message content, along with the metadata
+/// associated.
+///
+/// Use [`downcast()`](Self::downcast) to get the typed [`NetMsg`].
+#[derive(Copy, Clone, Debug)]
+pub struct AnyNetMsg<'n> {
+    /// The [`CId`] that the message was sent from.
+    pub cid: CId,
+    /// The [`MId`] of the message type.
+    pub mid: MId,
+    /// The order that the message arrived in.
+    ///
+    /// This increases by one for every message received by the client or server, across all
+    /// message types.
+    pub seq: u64,
+    /// The timestamp that the message was sent in unix millis.
+    ///
+    /// This is always `Some` if the message was sent with UDP, and always `None` if sent with TCP.
+    pub time: Option<u32>,
+    /// The simulation tick that the message was sent on.
+    ///
+    /// This is `Some` if [`Config::tick_header`] is set.
+    pub tick: Option<u32>,
+    /// The actual message.
+    ///
+    /// Borrowed from the client or server.
+    pub m: &'n (dyn Any + Send + Sync),
+}
+
+impl<'n> AnyNetMsg<'n> {
+    /// Returns whether the message is of type `T`.
+    pub fn is<T: Any + Send + Sync>(&self) -> bool {
+        self.m.is::<T>()
+    }
+
+    /// Gets the [`TypeId`] of the message type.
+    pub fn type_id(&self) -> TypeId {
+        let m: &dyn Any = self.m;
+        m.type_id()
+    }
+
+    /// Converts this to a [`NetMsg<T>`]. Returns `None` if the message is not of type `T`.
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<NetMsg<'n, T>> {
+        Some(NetMsg {
+            cid: self.cid,
+            time: self.time,
+            tick: self.tick,
+            m: self.m.downcast_ref()?,
+        })
+    }
+
+    /// Gets a reference to the message, if it is of type `T`.
+    pub fn downcast_ref<T: Any + Send + Sync>(&self) -> Option<&'n T> {
+        self.m.downcast_ref()
+    }
 }
 
 /// A network message containing the message content, along with the metadata associated.
@@ -222,6 +512,10 @@ pub struct NetMsg<'n, T: Any + Send + Sync> {
     ///
     /// This is always `Some` if the message was sent with UDP, and always `None` if sent with TCP.
     pub time: Option<u32>,
+    /// The simulation tick that the message was sent on.
+    ///
+    /// This is `Some` if [`Config::tick_header`] is set.
+    pub tick: Option<u32>,
     /// The actual message.
     ///
     /// Borrowed from the client or server.
@@ -235,3 +529,44 @@ impl<'n, T: Any + Send + Sync> Deref for NetMsg<'n, T> {
         self.m
     }
 }
+
+/// A network message containing the owned message content, along with the metadata associated.
+///
+/// Unlike [`NetMsg`], this does not borrow from the client or server. It is returned by the
+/// `drain()` and `take()` methods, which remove the messages from the client or server.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct OwnedNetMsg<T: Any + Send + Sync> {
+    /// The [`CId`] that the message was sent from.
+    pub cid: CId,
+    /// The timestamp that the message was sent in unix millis.
+    ///
+    /// This is always `Some` if the message was sent with UDP, and always `None` if sent with TCP.
+    pub time: Option<u32>,
+    /// The simulation tick that the message was sent on.
+    ///
+    /// This is `Some` if [`Config::tick_header`] is set.
+    pub tick: Option<u32>,
+    /// The actual message.
+    pub m: T,
+}
+
+impl<T: Any + Send + Sync> OwnedNetMsg<T> {
+    /// Gets the message, discarding the metadata.
+    pub fn into_inner(self) -> T {
+        self.m
+    }
+}
+
+impl<T: Any + Send + Sync> Deref for OwnedNetMsg<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.m
+    }
+}
+
+impl<T: Any + Send + Sync> DerefMut for OwnedNetMsg<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.m
+    }
+}
@@ -0,0 +1,300 @@
+//! Client-side prediction and server reconciliation.
+//!
+//! The client applies its inputs to its own copy of the state right away, instead of waiting for
+//! the server. Every input is sent to the server tagged with a sequence number by a
+//! [`Predictor<I>`]. The server processes the inputs with an [`InputTracker<I>`], which remembers
+//! the last processed input of each client, and sends that back with the authoritative state.
+//!
+//! When the client receives the authoritative state, the [`Predictor`] discards the inputs that
+//! the server already processed, and replays the rest on top of that state to get the predicted
+//! state.
+//!
+//! The [`Input<I>`] and [`State<S>`] messages need to be registered on both sides, using
+//! [`register_prediction`] or [`register_prediction_sorted`].
+
+use crate::message_table::MsgRegError;
+use crate::net::CId;
+use crate::{Client, MsgTable, Server, SortedMsgTable, Transport};
+use hashbrown::HashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+/// An input of type `I`, tagged with a sequence number.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Input<I> {
+    /// The sequence number of the input. Increases by one for every input a client sends.
+    pub seq: u32,
+    /// The input.
+    pub input: I,
+}
+
+/// An authoritative state of type `S`, along with the last input of the receiving client that
+/// was processed.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct State<S> {
+    /// The sequence number of the last processed input, or `None` if no inputs were processed yet.
+    pub last_input: Option<u32>,
+    /// The state.
+    pub state: S,
+}
+
+/// Registers the [`Input<I>`] and [`State<S>`] messages, both using `transport`.
+pub fn register_prediction<I, S>(
+    table: &mut MsgTable,
+    transport: Transport,
+) -> Result<(), MsgRegError>
+where
+    I: Any + Send + Sync + DeserializeOwned + Serialize,
+    S: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    let mut prediction_table = MsgTable::new();
+    prediction_table.register::<Input<I>>(transport)?;
+    prediction_table.register::<State<S>>(transport)?;
+    table.join(&prediction_table)
+}
+
+/// Registers the [`Input<I>`] and [`State<S>`] messages, both using `transport`.
+///
+/// They are registered with the identifiers `"{identifier}::input"` and `"{identifier}::state"`.
+pub fn register_prediction_sorted<I, S>(
+    table: &mut SortedMsgTable,
+    transport: Transport,
+    identifier: &str,
+) -> Result<(), MsgRegError>
+where
+    I: Any + Send + Sync + DeserializeOwned + Serialize,
+    S: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    let mut prediction_table = SortedMsgTable::new();
+    prediction_table.register::<Input<I>>(transport, &format!("{}::input", identifier))?;
+    prediction_table.register::<State<S>>(transport, &format!("{}::state", identifier))?;
+    table.join(&prediction_table)
+}
+
+/// The client side of prediction. Tags inputs with sequence numbers, and keeps the inputs that
+/// the server has not processed yet.
+///
+/// See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct Predictor<I> {
+    next_seq: u32,
+    /// The inputs that the server has not acknowledged, oldest first.
+    pending: VecDeque<Input<I>>,
+}
+
+impl<I> Default for Predictor<I> {
+    fn default() -> Self {
+        Predictor {
+            next_seq: 0,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<I> Predictor<I>
+where
+    I: Any + Send + Sync + DeserializeOwned + Serialize + Clone,
+{
+    /// Creates a new [`Predictor`].
+    pub fn new() -> Self {
+        Predictor::default()
+    }
+
+    /// Tags `input` with the next sequence number and sends it to the server.
+    ///
+    /// The input is kept until the server acknowledges it. Returns the sequence number.
+    pub fn send_input(&mut self, client: &Client, input: I) -> crate::Result<u32> {
+        let input = self.tag(input);
+        let seq = input.seq;
+        client.send(&input)?;
+        self.pending.push_back(input);
+        Ok(seq)
+    }
+
+    /// Tags `input` with the next sequence number.
+    fn tag(&mut self, input: I) -> Input<I> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        Input { seq, input }
+    }
+
+    /// Gets the inputs that the server has not acknowledged yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &Input<I>> {
+        self.pending.iter()
+    }
+
+    /// Reconciles with the latest [`State<S>`] that the client received, if any.
+    ///
+    /// Discards the inputs the server already processed, then replays the remaining inputs on a
+    /// copy of the authoritative state with `apply`. Returns the predicted state.
+    ///
+    /// Make sure to call [`Client::recv_msgs()`] before calling this.
+    pub fn reconcile<S>(&mut self, client: &Client, apply: impl FnMut(&mut S, &I)) -> Option<S>
+    where
+        S: Any + Send + Sync + Clone,
+    {
+        // UDP is unordered, so use the state that acknowledges the most inputs.
+        let latest = client
+            .recv::<State<S>>()
+            .max_by_key(|msg| msg.last_input.map(|seq| seq as u64 + 1))?;
+        Some(self.reconcile_with(latest.m, apply))
+    }
+
+    /// Reconciles with `state`. See [`reconcile()`](Self::reconcile).
+    pub fn reconcile_with<S>(&mut self, state: &State<S>, mut apply: impl FnMut(&mut S, &I)) -> S
+    where
+        S: Clone,
+    {
+        if let Some(last_input) = state.last_input {
+            while self
+                .pending
+                .front()
+                .is_some_and(|input| input.seq <= last_input)
+            {
+                self.pending.pop_front();
+            }
+        }
+
+        let mut predicted = state.state.clone();
+        for input in self.pending.iter() {
+            apply(&mut predicted, &input.input);
+        }
+        predicted
+    }
+}
+
+/// The server side of prediction. Keeps track of the last processed input of each client.
+///
+/// See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct InputTracker<I> {
+    last_input: HashMap<CId, u32>,
+    _i: PhantomData<fn() -> I>,
+}
+
+impl<I> Default for InputTracker<I> {
+    fn default() -> Self {
+        InputTracker {
+            last_input: HashMap::new(),
+            _i: PhantomData,
+        }
+    }
+}
+
+impl<I> InputTracker<I>
+where
+    I: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    /// Creates a new [`InputTracker`].
+    pub fn new() -> Self {
+        InputTracker::default()
+    }
+
+    /// Gets the new inputs that the server received, and marks them as processed.
+    ///
+    /// The inputs of each client are yielded in sequence order. Inputs that are older than the
+    /// last processed input of their client (duplicates or late UDP messages) are skipped.
+    ///
+    /// Make sure to call [`Server::recv_msgs()`] before calling this.
+    pub fn recv<'s>(&mut self, server: &'s Server) -> Vec<(CId, &'s I)> {
+        let mut inputs: Vec<_> = server.recv::<Input<I>>().collect();
+        inputs.sort_by_key(|msg| (msg.cid, msg.seq));
+        inputs
+            .into_iter()
+            .filter(|msg| self.process(msg.cid, msg.seq))
+            .map(|msg| (msg.cid, &msg.m.input))
+            .collect()
+    }
+
+    /// Marks the input `seq` of `cid` as processed. Returns `false` if it is not newer than the
+    /// last processed input.
+    fn process(&mut self, cid: CId, seq: u32) -> bool {
+        match self.last_input.get(&cid) {
+            Some(&last) if seq <= last => false,
+            _ => {
+                self.last_input.insert(cid, seq);
+                true
+            }
+        }
+    }
+
+    /// Gets the sequence number of the last processed input of `cid`.
+    pub fn last_input(&self, cid: CId) -> Option<u32> {
+        self.last_input.get(&cid).copied()
+    }
+
+    /// Forgets everything about `cid`. Call this when `cid` disconnects.
+    pub fn remove(&mut self, cid: CId) {
+        self.last_input.remove(&cid);
+    }
+
+    /// Sends `state` to `cid`, along with the last processed input of `cid`.
+    pub fn send_state<S>(&self, server: &Server, cid: CId, state: &S) -> crate::Result<()>
+    where
+        S: Any + Send + Sync + Clone,
+    {
+        let state = State {
+            last_input: self.last_input(cid),
+            state: state.clone(),
+        };
+        server.send_to(cid, &state)
+    }
+
+    /// Sends `state` to all connected clients, each along with their last processed input.
+    pub fn broadcast_state<S>(&self, server: &Server, state: &S) -> crate::Result<()>
+    where
+        S: Any + Send + Sync + Clone,
+    {
+        for cid in server.cids() {
+            self.send_state(server, cid, state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prediction::{InputTracker, Predictor, State};
+
+    #[test]
+    fn reconcile() {
+        let mut predictor = Predictor::<i32>::new();
+        for input in [1, 2, 3, 4] {
+            let input = predictor.tag(input);
+            predictor.pending.push_back(input);
+        }
+
+        // Nothing processed yet.
+        let state = State {
+            last_input: None,
+            state: 100,
+        };
+        assert_eq!(predictor.reconcile_with(&state, |s, i| *s += i), 110);
+
+        // The first two inputs were processed.
+        let state = State {
+            last_input: Some(1),
+            state: 103,
+        };
+        assert_eq!(predictor.reconcile_with(&state, |s, i| *s += i), 110);
+        let pending: Vec<_> = predictor.pending().map(|i| i.seq).collect();
+        assert_eq!(pending, vec![2, 3]);
+    }
+
+    #[test]
+    fn tracker() {
+        let mut tracker = InputTracker::<i32>::new();
+        assert!(tracker.process(1, 0));
+        assert!(tracker.process(1, 2));
+        // Late.
+        assert!(!tracker.process(1, 1));
+        assert!(tracker.process(2, 0));
+
+        assert_eq!(tracker.last_input(1), Some(2));
+        assert_eq!(tracker.last_input(2), Some(0));
+        assert_eq!(tracker.last_input(3), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! Rate limiting for incoming messages.
+//!
+//! The server can limit the number of messages that each connection sends. There are two kinds of
+//! limits, which are both checked for every received message:
+//!
+//! - A limit on all the messages of a connection, set with
+//!   [`Config::recv_rate_limit`](crate::net::Config::recv_rate_limit).
+//! - A limit on the messages of one type from a connection, set with
+//!   [`MsgOptions::recv_rate_limit`](crate::MsgOptions::recv_rate_limit).
+//!
+//! Both are token buckets that are refilled at [`RateLimit::msgs_per_sec`], up to a maximum of
+//! [`RateLimit::burst`] messages.
+//!
+//! When a message goes over a limit, the server calls the hook set with
+//! [`Server::set_violation_hook()`](crate::Server::set_violation_hook), which decides what to do
+//! with the message. Without a hook, the message is dropped.
+
+use crate::bandwidth::TokenBucket;
+use crate::message_table::MsgTableParts;
+use crate::net::CId;
+use crate::MId;
+use serde::{Deserialize, Serialize};
+
+/// A limit on the number of received messages.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// The sustained number of messages per second.
+    pub msgs_per_sec: u32,
+    /// The maximum number of messages that can be received at once, after the connection was
+    /// idle.
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Creates a new [`RateLimit`].
+    pub fn new(msgs_per_sec: u32, burst: u32) -> Self {
+        RateLimit {
+            msgs_per_sec,
+            burst,
+        }
+    }
+}
+
+/// Which limit was broken.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LimitKind {
+    /// The limit on all the messages of the connection.
+    Connection,
+    /// The limit on the messages of the type.
+    MsgType,
+}
+
+/// A message that went over a rate limit.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Violation {
+    /// The [`CId`] of the connection that sent the message.
+    pub cid: CId,
+    /// The [`MId`] of the message.
+    pub mid: MId,
+    /// Which limit was broken.
+    pub kind: LimitKind,
+}
+
+/// What to do with a message that went over a rate limit.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ViolationAction {
+    /// Log a warning, but keep the message.
+    Warn,
+    /// Drop the message.
+    Drop,
+    /// Drop the message, and disconnect the connection that sent it.
+    Disconnect,
+}
+
+/// The receive side rate limiter of a connection.
+#[derive(Clone, Debug)]
+pub(crate) struct RecvLimiter {
+    /// The bucket for all messages.
+    con: Option<TokenBucket>,
+    /// The bucket for each message type.
+    types: Vec<Option<TokenBucket>>,
+}
+
+impl RecvLimiter {
+    /// Creates a new [`RecvLimiter`] with the connection limit `limit`, and the per type limits
+    /// from `parts`.
+    pub(crate) fn new(limit: Option<RateLimit>, parts: &MsgTableParts) -> Self {
+        let bucket = |limit: RateLimit| TokenBucket::new(limit.msgs_per_sec, limit.burst);
+        RecvLimiter {
+            con: limit.map(bucket),
+            types: parts
+                .options
+                .iter()
+                .map(|options| options.recv_rate_limit.map(bucket))
+                .collect(),
+        }
+    }
+
+    /// Takes a message of type `mid` from the buckets.
+    ///
+    /// Returns the limit that was broken, if any.
+    pub(crate) fn check(&mut self, mid: MId) -> Option<LimitKind> {
+        // Always take from both buckets, so a broken limit still counts towards the other.
+        let con = Self::try_take(&mut self.con);
+        let ty = Self::try_take(&mut self.types[mid]);
+        if !con {
+            Some(LimitKind::Connection)
+        } else if !ty {
+            Some(LimitKind::MsgType)
+        } else {
+            None
+        }
+    }
+
+    /// Takes a token from `bucket`, if there is one. Returns whether it is within the limit.
+    fn try_take(bucket: &mut Option<TokenBucket>) -> bool {
+        match bucket {
+            Some(bucket) => {
+                bucket.refill();
+                bucket.try_take(1)
+            }
+            None => true,
+        }
+    }
+}
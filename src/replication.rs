@@ -0,0 +1,521 @@
+//! Replication of entity state from the server to the clients.
+//!
+//! The server keeps the authoritative state of every replicated entity in a
+//! [`ReplicationServer<T>`], where `T` is the replicated component. Changing a component through
+//! [`ReplicationServer::get_mut()`] or [`ReplicationServer::set()`] marks it dirty. Calling
+//! [`ReplicationServer::sync()`] sends the changes to every client:
+//!
+//! - A spawn message the first time an entity is relevant to a client.
+//! - An update message when a dirty entity is relevant to a client that already knows it.
+//! - A despawn message when an entity is despawned, or stops being relevant to a client.
+//!
+//! On the client, a [`ReplicatedWorld<T>`] applies these messages and exposes the replicated
+//! entities for reading.
+//!
+//! Dirty tracking is done per component. To avoid sending a large component when only part of it
+//! changes, split it into multiple replicated components with the same [`NetId`]s.
+//!
+//! The [`Replicate<T>`] message needs to be registered on both sides, using
+//! [`register_replicated`] or [`register_replicated_sorted`]. It is sent over TCP, so that spawns
+//! and despawns are never lost or reordered.
+
+use crate::interest::Position;
+use crate::message_table::MsgRegError;
+use crate::net::CId;
+use crate::{Client, Error, MsgTable, Server, SortedMsgTable, Transport};
+use hashbrown::{HashMap, HashSet};
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+
+/// A network ID. Identifies a replicated entity across the server and all clients.
+pub type NetId = u32;
+
+/// A replication message for components of type `T`.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Replicate<T> {
+    /// The entity became known to the client.
+    Spawn(NetId, T),
+    /// The component of an entity that the client knows changed.
+    Update(NetId, T),
+    /// The entity was despawned, or is no longer relevant to the client.
+    Despawn(NetId),
+}
+
+/// Registers the [`Replicate<T>`] message for components of type `T`.
+pub fn register_replicated<T>(table: &mut MsgTable) -> Result<(), MsgRegError>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    table.register::<Replicate<T>>(Transport::TCP)?;
+    Ok(())
+}
+
+/// Registers the [`Replicate<T>`] message for components of type `T`, with the identifier
+/// `identifier`.
+pub fn register_replicated_sorted<T>(
+    table: &mut SortedMsgTable,
+    identifier: &str,
+) -> Result<(), MsgRegError>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize,
+{
+    table.register::<Replicate<T>>(Transport::TCP, identifier)
+}
+
+/// What a client has received so far.
+#[derive(Clone, Debug, Default)]
+struct ClientState {
+    /// The entities that the client knows about.
+    ///
+    /// A known entity that no longer exists still needs to be despawned.
+    known: HashSet<NetId>,
+    /// The known entities that changed since they were last sent to the client.
+    dirty: HashSet<NetId>,
+}
+
+/// The server side of the replication of components of type `T`.
+///
+/// See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct ReplicationServer<T> {
+    entities: BTreeMap<NetId, T>,
+    /// The state of each client. Only updated once a message was sent successfully.
+    clients: HashMap<CId, ClientState>,
+    next_id: NetId,
+}
+
+impl<T> Default for ReplicationServer<T> {
+    fn default() -> Self {
+        ReplicationServer {
+            entities: BTreeMap::new(),
+            clients: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T> ReplicationServer<T>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize + Clone,
+{
+    /// Creates a new, empty, [`ReplicationServer`].
+    pub fn new() -> Self {
+        ReplicationServer::default()
+    }
+
+    /// Spawns a new entity with the component `value`, and returns its [`NetId`].
+    pub fn spawn(&mut self, value: T) -> NetId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.insert(id, value);
+        id
+    }
+
+    /// Adds the component `value` to the entity `id`, replacing the old one if there is one.
+    ///
+    /// This is useful when replicating multiple components of the same entities. Use the
+    /// [`NetId`] returned by another [`ReplicationServer`]'s [`spawn()`](Self::spawn).
+    pub fn insert(&mut self, id: NetId, value: T) {
+        self.entities.insert(id, value);
+        self.mark_dirty(id);
+    }
+
+    /// Despawns the entity `id`. Returns the component if the entity existed.
+    pub fn despawn(&mut self, id: NetId) -> Option<T> {
+        self.entities.remove(&id)
+    }
+
+    /// Gets the component of the entity `id`.
+    pub fn get(&self, id: NetId) -> Option<&T> {
+        self.entities.get(&id)
+    }
+
+    /// Gets the component of the entity `id` mutably, marking it dirty.
+    pub fn get_mut(&mut self, id: NetId) -> Option<&mut T> {
+        if !self.entities.contains_key(&id) {
+            return None;
+        }
+        self.mark_dirty(id);
+        self.entities.get_mut(&id)
+    }
+
+    /// Sets the component of an existing entity `id`, marking it dirty.
+    ///
+    /// Returns the old value, or `None` if the entity does not exist. In that case nothing
+    /// is changed.
+    pub fn set(&mut self, id: NetId, value: T) -> Option<T> {
+        let old = std::mem::replace(self.entities.get_mut(&id)?, value);
+        self.mark_dirty(id);
+        Some(old)
+    }
+
+    /// Marks the entity `id` dirty, so that it is sent on the next sync.
+    pub fn mark_dirty(&mut self, id: NetId) {
+        for client in self.clients.values_mut() {
+            if client.known.contains(&id) {
+                client.dirty.insert(id);
+            }
+        }
+    }
+
+    /// Gets an iterator over all entities and their components.
+    pub fn iter(&self) -> impl Iterator<Item = (NetId, &T)> {
+        self.entities.iter().map(|(id, value)| (*id, value))
+    }
+
+    /// Gets the number of entities.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Gets whether there are no entities.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Sends the changes since the last sync to all connected clients.
+    pub fn sync(&mut self, server: &Server) -> crate::Result<()> {
+        self.sync_filtered(server, |_, _, _| true)
+    }
+
+    /// Sends the changes since the last sync to all connected clients, for the entities that are
+    /// relevant to them.
+    ///
+    /// `relevant` is called with a [`CId`] and an entity, and returns whether that client should
+    /// know about that entity.
+    ///
+    /// If sending to a client fails, the changes are still sent to the other clients, and the
+    /// first error is returned. The changes that were not sent to a client are sent again on the
+    /// next sync. Clients that don't have the [`Replicate<T>`] message type are skipped.
+    pub fn sync_filtered(
+        &mut self,
+        server: &Server,
+        relevant: impl FnMut(CId, NetId, &T) -> bool,
+    ) -> crate::Result<()> {
+        let cids: Vec<_> = server.cids().collect();
+        let mut first_err = None;
+        // The client that failed, so that nothing more is sent to it.
+        let mut failed = None;
+        for (cid, msg) in self.changes(&cids, relevant) {
+            if failed == Some(cid) {
+                continue;
+            }
+            match server.send_to(cid, &msg) {
+                Ok(()) => self.sent(cid, &msg),
+                Err(Error::DisabledType(_)) => failed = Some(cid),
+                Err(e) => {
+                    error!("Failed to send a replication message to {}. {}", cid, e);
+                    failed = Some(cid);
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the changes since the last sync to all connected clients, for the entities that are
+    /// relevant to them according to their [`Interest`](crate::interest::Interest).
+    ///
+    /// `position` is called with an entity, and returns its position in the world.
+    pub fn sync_relevant(
+        &mut self,
+        server: &Server,
+        mut position: impl FnMut(NetId, &T) -> Position,
+    ) -> crate::Result<()> {
+        self.sync_filtered(server, |cid, id, value| {
+            server.is_relevant(cid, position(id, value))
+        })
+    }
+
+    /// Gets the messages to send to each of `cids` to bring them up to date.
+    ///
+    /// Nothing is marked as sent, that is done by [`sent()`](Self::sent) for each message that
+    /// was sent successfully. Clients that are not in `cids` are forgotten.
+    fn changes(
+        &mut self,
+        cids: &[CId],
+        mut relevant: impl FnMut(CId, NetId, &T) -> bool,
+    ) -> Vec<(CId, Replicate<T>)> {
+        self.clients.retain(|cid, _| cids.contains(cid));
+
+        let mut msgs = vec![];
+        for &cid in cids {
+            let client = self.clients.entry(cid).or_default();
+
+            let mut despawned: Vec<_> = client
+                .known
+                .iter()
+                .filter(|id| !self.entities.contains_key(id))
+                .copied()
+                .collect();
+            despawned.sort_unstable();
+            msgs.extend(
+                despawned
+                    .into_iter()
+                    .map(|id| (cid, Replicate::Despawn(id))),
+            );
+
+            for (&id, value) in self.entities.iter() {
+                let is_relevant = relevant(cid, id, value);
+                let is_known = client.known.contains(&id);
+                match (is_relevant, is_known) {
+                    (true, false) => msgs.push((cid, Replicate::Spawn(id, value.clone()))),
+                    (true, true) if client.dirty.contains(&id) => {
+                        msgs.push((cid, Replicate::Update(id, value.clone())));
+                    }
+                    (false, true) => msgs.push((cid, Replicate::Despawn(id))),
+                    _ => {}
+                }
+            }
+        }
+        msgs
+    }
+
+    /// Marks the message `msg` as sent to the client `cid`.
+    fn sent(&mut self, cid: CId, msg: &Replicate<T>) {
+        let client = match self.clients.get_mut(&cid) {
+            Some(client) => client,
+            None => return,
+        };
+        match msg {
+            Replicate::Spawn(id, _) => {
+                client.known.insert(*id);
+                client.dirty.remove(id);
+            }
+            Replicate::Update(id, _) => {
+                client.dirty.remove(id);
+            }
+            Replicate::Despawn(id) => {
+                client.known.remove(id);
+                client.dirty.remove(id);
+            }
+        }
+    }
+}
+
+/// The client side of the replication of components of type `T`.
+///
+/// Holds the replicated entities that are relevant to this client. See the
+/// [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct ReplicatedWorld<T> {
+    entities: BTreeMap<NetId, T>,
+    spawned: Vec<NetId>,
+    updated: Vec<NetId>,
+    despawned: Vec<NetId>,
+}
+
+impl<T> Default for ReplicatedWorld<T> {
+    fn default() -> Self {
+        ReplicatedWorld {
+            entities: BTreeMap::new(),
+            spawned: vec![],
+            updated: vec![],
+            despawned: vec![],
+        }
+    }
+}
+
+impl<T> ReplicatedWorld<T>
+where
+    T: Any + Send + Sync + DeserializeOwned + Serialize + Clone,
+{
+    /// Creates a new, empty, [`ReplicatedWorld`].
+    pub fn new() -> Self {
+        ReplicatedWorld::default()
+    }
+
+    /// Applies all the [`Replicate<T>`] messages that the client received.
+    ///
+    /// This replaces the lists of spawned, updated and despawned entities. Make sure to call
+    /// [`Client::recv_msgs()`] before calling this.
+    ///
+    /// Returns the number of messages applied.
+    pub fn apply(&mut self, client: &Client) -> u32 {
+        self.spawned.clear();
+        self.updated.clear();
+        self.despawned.clear();
+
+        let mut count = 0;
+        for msg in client.recv::<Replicate<T>>() {
+            self.apply_msg(msg.m);
+            count += 1;
+        }
+        count
+    }
+
+    /// Applies a single replication message.
+    fn apply_msg(&mut self, msg: &Replicate<T>) {
+        match msg {
+            Replicate::Spawn(id, value) => {
+                self.entities.insert(*id, value.clone());
+                self.spawned.push(*id);
+            }
+            Replicate::Update(id, value) => {
+                if let Some(old) = self.entities.get_mut(id) {
+                    *old = value.clone();
+                    self.updated.push(*id);
+                }
+            }
+            Replicate::Despawn(id) => {
+                if self.entities.remove(id).is_some() {
+                    self.despawned.push(*id);
+                }
+            }
+        }
+    }
+
+    /// Gets the component of the entity `id`.
+    pub fn get(&self, id: NetId) -> Option<&T> {
+        self.entities.get(&id)
+    }
+
+    /// Gets an iterator over all entities and their components.
+    pub fn iter(&self) -> impl Iterator<Item = (NetId, &T)> {
+        self.entities.iter().map(|(id, value)| (*id, value))
+    }
+
+    /// Gets the number of entities.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Gets whether there are no entities.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Gets the entities spawned by the last [`apply()`](Self::apply).
+    pub fn spawned(&self) -> &[NetId] {
+        &self.spawned
+    }
+
+    /// Gets the entities updated by the last [`apply()`](Self::apply).
+    pub fn updated(&self) -> &[NetId] {
+        &self.updated
+    }
+
+    /// Gets the entities despawned by the last [`apply()`](Self::apply).
+    pub fn despawned(&self) -> &[NetId] {
+        &self.despawned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::CId;
+    use crate::replication::{NetId, Replicate, ReplicatedWorld, ReplicationServer};
+
+    /// Gets the changes, and marks them all as sent.
+    fn send_all(
+        server: &mut ReplicationServer<i32>,
+        cids: &[CId],
+        relevant: impl FnMut(CId, NetId, &i32) -> bool,
+    ) -> Vec<(CId, Replicate<i32>)> {
+        let msgs = server.changes(cids, relevant);
+        for (cid, msg) in msgs.iter() {
+            server.sent(*cid, msg);
+        }
+        msgs
+    }
+
+    #[test]
+    fn changes() {
+        let mut server = ReplicationServer::<i32>::new();
+        let a = server.spawn(1);
+        let b = server.spawn(2);
+
+        // Only even values are relevant to CId 2.
+        let relevant = |cid, _, value: &i32| cid == 1 || value % 2 == 0;
+
+        let msgs = send_all(&mut server, &[1, 2], relevant);
+        assert_eq!(
+            msgs,
+            vec![
+                (1, Replicate::Spawn(a, 1)),
+                (1, Replicate::Spawn(b, 2)),
+                (2, Replicate::Spawn(b, 2)),
+            ]
+        );
+
+        // Nothing changed.
+        assert!(send_all(&mut server, &[1, 2], relevant).is_empty());
+
+        *server.get_mut(a).unwrap() = 4;
+        server.set(b, 3);
+        let msgs = send_all(&mut server, &[1, 2], relevant);
+        assert_eq!(
+            msgs,
+            vec![
+                (1, Replicate::Update(a, 4)),
+                (1, Replicate::Update(b, 3)),
+                (2, Replicate::Spawn(a, 4)),
+                (2, Replicate::Despawn(b)),
+            ]
+        );
+
+        server.despawn(a);
+        let msgs = send_all(&mut server, &[1, 2], relevant);
+        assert_eq!(
+            msgs,
+            vec![(1, Replicate::Despawn(a)), (2, Replicate::Despawn(a))]
+        );
+    }
+
+    #[test]
+    fn unsent() {
+        let mut server = ReplicationServer::<i32>::new();
+        let a = server.spawn(1);
+        let b = server.spawn(2);
+
+        // Only the spawn of `a` reaches CId 1.
+        let msgs = server.changes(&[1], |_, _, _| true);
+        server.sent(1, &msgs[0].1);
+        assert_eq!(
+            server.changes(&[1], |_, _, _| true),
+            vec![(1, Replicate::Spawn(b, 2))]
+        );
+
+        // The update is kept until it was sent.
+        server.set(a, 3);
+        assert_eq!(
+            server.changes(&[1], |_, _, _| true),
+            vec![(1, Replicate::Update(a, 3)), (1, Replicate::Spawn(b, 2))]
+        );
+        send_all(&mut server, &[1], |_, _, _| true);
+
+        // And so is the despawn.
+        server.despawn(a);
+        assert_eq!(
+            server.changes(&[1], |_, _, _| true),
+            vec![(1, Replicate::Despawn(a))]
+        );
+        assert_eq!(
+            send_all(&mut server, &[1], |_, _, _| true),
+            vec![(1, Replicate::Despawn(a))]
+        );
+        assert!(server.changes(&[1], |_, _, _| true).is_empty());
+    }
+
+    #[test]
+    fn world() {
+        let mut world = ReplicatedWorld::<i32>::new();
+        world.apply_msg(&Replicate::Spawn(0, 1));
+        world.apply_msg(&Replicate::Spawn(1, 2));
+        world.apply_msg(&Replicate::Update(1, 3));
+        // Unknown entities are ignored.
+        world.apply_msg(&Replicate::Update(5, 3));
+        world.apply_msg(&Replicate::Despawn(0));
+
+        assert_eq!(world.iter().collect::<Vec<_>>(), vec![(1, &3)]);
+        assert_eq!(world.spawned(), &[0, 1]);
+        assert_eq!(world.updated(), &[1]);
+        assert_eq!(world.despawned(), &[0]);
+    }
+}
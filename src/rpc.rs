@@ -0,0 +1,112 @@
+//! Remote procedure calls.
+//!
+//! A request type is linked to its response type by implementing [`Request`]. The pair is
+//! registered with [`MsgTable::register_rpc()`](crate::MsgTable::register_rpc), which registers
+//! the [`RpcRequest<Req>`] and [`RpcResponse<Req>`] wrappers that carry the call id.
+//!
+//! The client starts a call with [`Client::call()`](crate::Client::call), which returns a
+//! [`CallHandle`]. The server answers calls with
+//! [`Server::handle_calls()`](crate::Server::handle_calls). The handle resolves when the response
+//! with the matching id arrives, or times out after
+//! [`Config::call_timeout`](crate::net::Config::call_timeout).
+//!
+//! The client keeps the responses by their call id until they are polled, so they are not
+//! received with [`Client::recv()`](crate::Client::recv).
+
+use crate::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+/// A request that can be sent with [`Client::call()`](crate::Client::call).
+pub trait Request: Any + Send + Sync + DeserializeOwned + Serialize {
+    /// The type that the server responds with.
+    type Response: Any + Send + Sync + DeserializeOwned + Serialize;
+}
+
+/// The message that carries a request.
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RpcRequest<Req> {
+    /// The id of the call. Unique per client.
+    pub(crate) id: u32,
+    /// The request.
+    pub(crate) req: Req,
+}
+
+/// The message that carries the response to a request of type `Req`.
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct RpcResponse<Req: Request> {
+    /// The id of the call that this responds to.
+    pub(crate) id: u32,
+    /// The response.
+    pub(crate) resp: Req::Response,
+}
+
+/// Gets the call id of an [`RpcResponse<Req>`].
+pub(crate) type CallIdFn = fn(&(dyn Any + Send + Sync)) -> u32;
+
+/// Gets the call id of `msg`, which is an [`RpcResponse<Req>`].
+pub(crate) fn call_id<Req: Request>(msg: &(dyn Any + Send + Sync)) -> u32 {
+    msg.downcast_ref::<RpcResponse<Req>>().unwrap().id
+}
+
+/// The state of a call.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CallStatus<Resp> {
+    /// The response has not arrived yet.
+    Pending,
+    /// The response arrived.
+    ///
+    /// The response is taken from the client, so this is only returned once.
+    Done(Resp),
+    /// The response did not arrive in time.
+    TimedOut,
+}
+
+/// A handle to a call started with [`Client::call()`](crate::Client::call).
+#[derive(Debug)]
+pub struct CallHandle<Req> {
+    id: u32,
+    deadline: Instant,
+    _req: PhantomData<fn() -> Req>,
+}
+
+// Derive would require `Req: Clone`.
+impl<Req> Clone for CallHandle<Req> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Req> Copy for CallHandle<Req> {}
+
+impl<Req: Request> CallHandle<Req> {
+    /// Creates a new [`CallHandle`].
+    pub(crate) fn new(id: u32, deadline: Instant) -> Self {
+        CallHandle {
+            id,
+            deadline,
+            _req: PhantomData,
+        }
+    }
+
+    /// Gets the id of the call.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Checks whether the response to this call arrived.
+    ///
+    /// Make sure to call [`Client::recv_msgs()`] before calling this. The response does not need
+    /// to be polled in the frame that it arrived in, but it is only yielded once.
+    pub fn poll(&self, client: &Client) -> CallStatus<Req::Response> {
+        match client.take_response::<Req>(self.id) {
+            Some(resp) => CallStatus::Done(resp),
+            None if Instant::now() > self.deadline => CallStatus::TimedOut,
+            None => CallStatus::Pending,
+        }
+    }
+}
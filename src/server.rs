@@ -1,19 +1,54 @@
+use crate::accept::{AcceptGuard, AcceptPolicy};
+use crate::bandwidth::{BandwidthLimit, BandwidthStats, Limiter, SharedBudget};
+use crate::compression::{compress, decompress};
+use crate::header::{check_size, prepend_tick, split_tick, TCP_HEADER_LEN, UDP_HEADER_LEN};
+use crate::interest::{Interest, Position};
 use crate::message_table::{
-    MsgTableParts, CONNECTION_TYPE_MID, DISCONNECT_TYPE_MID, RESPONSE_TYPE_MID,
+    MsgHandle, MsgTableParts, TypedParts, CONNECTION_TYPE_MID, DISCONNECT_TYPE_MID,
+    RESPONSE_TYPE_MID,
 };
-use crate::net::{CId, CIdSpec, Config, DeserFn, ErasedNetMsg, NetMsg, Status, Transport};
+use crate::negotiate::{response_payload, split_manifest, Negotiated};
+use crate::net::{
+    ordered_msgs, AnyNetMsg, CId, CIdSpec, Config, ErasedNetMsg, KickReason, NetMsg,
+    OverflowPolicy, OwnedNetMsg, Status, Transport, TypedStatus,
+};
+use crate::rate_limit::{RecvLimiter, Violation, ViolationAction};
+use crate::rpc::{Request, RpcRequest, RpcResponse};
 use crate::tcp::TcpCon;
 use crate::udp::UdpCon;
-use crate::MId;
+use crate::version::Version;
+use crate::{Error, MId, Result};
 use hashbrown::HashMap;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 use std::any::{type_name, Any, TypeId};
 use std::collections::VecDeque;
 use std::io;
-use std::io::ErrorKind::{InvalidData, WouldBlock};
-use std::io::{Error, ErrorKind};
+use std::io::ErrorKind;
+use std::marker::PhantomData;
 use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
-use std::time::{Duration, Instant};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// The hook that decides what to do with a message that went over a rate limit.
+type ViolationHook = Box<dyn FnMut(Violation) -> ViolationAction + Send + Sync>;
+
+/// Decides whether a new connection can use a reserved slot, based on its connection message.
+type ReservedFilter = Box<dyn FnMut(&(dyn Any + Send + Sync)) -> bool + Send + Sync>;
+
+/// What to do with a pending connection that is done.
+enum Pending<R> {
+    /// Accepted by the hook. Holds the message types that were matched with the connection.
+    Accepted(R, Negotiated),
+    /// Rejected by the hook.
+    Rejected(R),
+    /// Rejected because the server is full.
+    Full,
+    /// Refused because the message types could not be matched.
+    NotMatched,
+    /// Errored out.
+    Dead,
+}
 
 /// A server.
 ///
@@ -28,16 +63,41 @@ pub struct Server {
     current_cid: CId,
     /// The configuration of the server.
     config: Config,
+    /// The current simulation tick. Sent with every message if [`Config::tick_header`] is set.
+    tick: u32,
     /// The received message buffer.
     ///
     /// Each [`MId`] has its own vector.
-    msg_buff: Vec<Vec<ErasedNetMsg>>,
+    msg_buff: Vec<VecDeque<ErasedNetMsg>>,
+    /// The sequence number to give the next received message.
+    next_seq: u64,
+    /// The number of buffered messages from each connection.
+    buffered: HashMap<CId, usize>,
+    /// The [`MId`] and sequence number of the buffered messages from each connection, in the
+    /// order they arrived.
+    ///
+    /// Messages that were already removed from the buffer are only skipped when they are at the
+    /// front, so this can contain more entries than `buffered`.
+    arrivals: HashMap<CId, VecDeque<(MId, u64)>>,
+    /// The number of received messages that were dropped because a buffer was full, for each
+    /// connection.
+    ///
+    /// Removed with the TCP connections.
+    dropped: HashMap<CId, u64>,
+    /// The total number of received messages that were dropped because a buffer was full.
+    dropped_total: u64,
 
     /// The pending connections (Connections that are established but have
     /// not sent a connection message yet).
     new_cons: Vec<(TcpCon, CId, Instant)>,
     /// Disconnected connections.
     disconnected: VecDeque<(CId, Status)>,
+    /// Decides which incoming connections become pending connections.
+    accept: AcceptGuard,
+    /// The response to send to new connections when the server is full.
+    full_response: Option<Box<dyn Any + Send + Sync>>,
+    /// Decides which new connections can use the reserved slots.
+    reserved_filter: Option<ReservedFilter>,
     /// The listener for new connections.
     listener: TcpListener,
     /// The TCP connection for this client.
@@ -57,6 +117,26 @@ pub struct Server {
     /// connections. Because of these things, ***ALWAYS*** use the `add_tcp_con` and `rm_tcp_con`
     /// functions to mutate these maps.
     addr_cid: HashMap<SocketAddr, CId>,
+    /// The bandwidth limiter of each connection.
+    ///
+    /// Added and removed with the TCP connections.
+    limiters: HashMap<CId, Mutex<Limiter>>,
+    /// The bandwidth budget that is shared by all connections.
+    total_budget: SharedBudget,
+    /// The interest of each connection.
+    ///
+    /// Added and removed with the TCP connections.
+    interests: HashMap<CId, Interest>,
+    /// The receive rate limiter of each connection.
+    ///
+    /// Added and removed with the TCP connections.
+    recv_limiters: HashMap<CId, RecvLimiter>,
+    /// The message types that were matched with each connection.
+    ///
+    /// Added and removed with the TCP connections.
+    negotiated: HashMap<CId, Negotiated>,
+    /// The hook that decides what to do with messages that go over a rate limit.
+    violation_hook: Option<ViolationHook>,
 
     /// The [`MsgTableParts`] to use for sending messages.
     parts: MsgTableParts,
@@ -70,7 +150,7 @@ impl Server {
         listen_addr: A,
         parts: MsgTableParts,
         config: Config,
-    ) -> io::Result<Self> {
+    ) -> Result<Self> {
         let listener = TcpListener::bind(listen_addr)?;
         let listen_addr = listener.local_addr().unwrap();
         listener.set_nonblocking(true)?;
@@ -81,35 +161,91 @@ impl Server {
         let mid_count = parts.tid_map.len();
         let mut msg_buff = Vec::with_capacity(mid_count);
         for _i in 0..mid_count {
-            msg_buff.push(vec![]);
+            msg_buff.push(VecDeque::new());
         }
 
         Ok(Server {
             current_cid: 0,
             config,
+            tick: 0,
             msg_buff,
+            next_seq: 0,
+            buffered: HashMap::new(),
+            arrivals: HashMap::new(),
+            dropped: HashMap::new(),
+            dropped_total: 0,
             new_cons: vec![],
             disconnected: VecDeque::new(),
+            accept: AcceptGuard::default(),
+            full_response: None,
+            reserved_filter: None,
             listener,
             tcp: HashMap::new(),
             udp,
             cid_addr: Default::default(),
             addr_cid: Default::default(),
+            limiters: Default::default(),
+            total_budget: SharedBudget::new(config.total_bandwidth),
+            interests: Default::default(),
+            recv_limiters: Default::default(),
+            negotiated: Default::default(),
+            violation_hook: None,
             parts,
         })
     }
 
+    /// Creates a new [`Server`] with [`TypedParts`], listening on the address `listen_addr`.
+    ///
+    /// Unlike [`new()`](Self::new), the types of the connection, response and disconnect
+    /// messages are checked by the compiler. See [`TypedServer`].
+    pub fn listen<C, R, D, A>(
+        listen_addr: A,
+        parts: TypedParts<C, R, D>,
+        config: Config,
+    ) -> Result<TypedServer<C, R, D>>
+    where
+        C: Any + Send + Sync,
+        R: Any + Send + Sync,
+        D: Any + Send + Sync,
+        A: ToSocketAddrs,
+    {
+        Ok(TypedServer {
+            server: Self::new(listen_addr, parts.erase(), config)?,
+            _types: PhantomData,
+        })
+    }
+
     /// Gets the config of the server.
     pub fn config(&self) -> &Config {
         &self.config
     }
 
+    /// Sets the current simulation tick.
+    ///
+    /// If [`Config::tick_header`] is set, this is sent with every message.
+    pub fn set_tick(&mut self, tick: u32) {
+        self.tick = tick;
+    }
+
+    /// Gets the current simulation tick.
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// Gets the tick to put in the header of outgoing messages.
+    fn header_tick(&self) -> Option<u32> {
+        self.config.tick_header.then_some(self.tick)
+    }
+
     /// Disconnects from the given `cid`. You should always disconnect all clients before dropping
     /// the server to let the clients know that you intentionally disconnected. The `discon_msg`
     /// allows you to give a reason for the disconnect.
-    pub fn disconnect<T: Any + Send + Sync>(&mut self, discon_msg: &T, cid: CId) -> io::Result<()> {
+    pub fn disconnect<T: Any + Send + Sync>(&mut self, discon_msg: &T, cid: CId) -> Result<()> {
+        if !self.parts.is_type::<T>(DISCONNECT_TYPE_MID) {
+            return Err(Error::WrongType(type_name::<T>()));
+        }
         if !self.alive(cid) {
-            return Err(io::Error::new(ErrorKind::InvalidData, "Invalid CId."));
+            return Err(Error::InvalidCId(cid));
         }
         debug!("Disconnecting CId {}", cid);
         self.send_to(cid, discon_msg)?;
@@ -125,78 +261,16 @@ impl Server {
     /// The hook function should return `(should_accept, response_msg)`.
     ///
     /// Types `C` and `R` need to match the `C` and `R` types that you passed into
-    /// [`MsgTable::build()`](MsgTable::build).
+    /// [`MsgTable::build()`](crate::MsgTable::build). If they don't, an error is logged and no
+    /// connections are handled.
     ///
     /// Returns whether a connection was handled.
     pub fn handle_new_con<C: Any + Send + Sync, R: Any + Send + Sync>(
         &mut self,
-        mut hook: impl FnMut(CId, C) -> (bool, R),
+        hook: impl FnMut(CId, C) -> (bool, R),
     ) -> bool {
-        // Start handling incoming connections.
-        self.start_incoming();
-
-        // If we have no active connections, we don't have to continue.
-        if self.new_cons.is_empty() {
-            return false;
-        }
-
-        // Handle the new connections.
-        let deser_fn = self.parts.deser[CONNECTION_TYPE_MID];
-
-        // List of accepted connections.
-        let mut accepted = vec![];
-        // List of rejected connections.
-        let mut rejected = vec![];
-        // List of connections that errored out.
-        let mut dead = vec![];
-
-        for (idx, (con, cid, time)) in self.new_cons.iter_mut().enumerate() {
-            match Self::handle_con_helper::<C>(deser_fn, con, self.config.timeout, time) {
-                // Done connecting.
-                Ok(c) => {
-                    // Call hook
-                    let (acc, resp) = hook(*cid, c);
-                    if acc {
-                        accepted.push((idx, resp));
-                    } else {
-                        rejected.push((idx, resp));
-                    }
-                    break; // Only handle 1 connection max.
-                }
-                // Not done yet.
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
-                // Error in connecting.
-                Err(e) => {
-                    error!(
-                        "IO error occurred while handling a pending connection. {}",
-                        e
-                    );
-                    dead.push(idx);
-                }
-            }
-        }
-
-        // Dead connections do not count as handled; they do not call the hook.
-        let handled = !(accepted.is_empty() && rejected.is_empty());
-
-        // Handle accepted.
-        for (idx, resp) in accepted {
-            let (con, cid, _) = self.new_cons.remove(idx);
-            self.accept_incoming(cid, con, &resp);
-        }
-
-        // Handle rejected.
-        for (idx, resp) in rejected {
-            let (con, cid, _) = self.new_cons.remove(idx);
-            self.reject_incoming(cid, con, &resp);
-        }
-
-        // Handle dead.
-        for idx in dead {
-            self.new_cons.remove(idx);
-        }
-
-        handled
+        // Only handle 1 connection max.
+        self.handle_pending(hook, 1) != 0
     }
 
     /// Handles all available new connection attempts in a loop, calling the given hook for each.
@@ -204,13 +278,44 @@ impl Server {
     /// The hook function should return `(should_accept, response_msg)`.
     ///
     /// Types `C` and `R` need to match the `C` and `R` types that you passed into
-    /// [`MsgTable::build()`](MsgTable::build).
+    /// [`MsgTable::build()`](crate::MsgTable::build). If they don't, an error is logged and no
+    /// connections are handled.
     ///
     /// Returns the number of handled connections.
     pub fn handle_new_cons<C: Any + Send + Sync, R: Any + Send + Sync>(
+        &mut self,
+        hook: impl FnMut(CId, C) -> (bool, R),
+    ) -> u32 {
+        self.handle_pending(hook, u32::MAX)
+    }
+
+    /// Handles up to `max` pending connections that sent their connection message.
+    ///
+    /// When the server is full (see [`Config::max_connections`]), connections are rejected with
+    /// the response set with [`set_full_response()`](Self::set_full_response), without calling
+    /// the hook.
+    ///
+    /// Returns the number of connections that the hook was called for.
+    fn handle_pending<C: Any + Send + Sync, R: Any + Send + Sync>(
         &mut self,
         mut hook: impl FnMut(CId, C) -> (bool, R),
+        max: u32,
     ) -> u32 {
+        if !self.parts.is_type::<C>(CONNECTION_TYPE_MID) {
+            error!(
+                "Can't handle new connections: {} is not the connection message type.",
+                type_name::<C>()
+            );
+            return 0;
+        }
+        if !self.parts.is_type::<R>(RESPONSE_TYPE_MID) {
+            error!(
+                "Can't handle new connections: {} is not the response message type.",
+                type_name::<R>()
+            );
+            return 0;
+        }
+
         // Start handling incoming connections.
         self.start_incoming();
 
@@ -219,111 +324,187 @@ impl Server {
             return 0;
         }
 
-        // Handle the new connections.
-        let deser_fn = self.parts.deser[CONNECTION_TYPE_MID];
-
-        // List of accepted connections.
-        let mut accepted = vec![];
-        // List of rejected connections.
-        let mut rejected = vec![];
-        // List of connections that errored out.
-        let mut dead = vec![];
+        // The connections that are done, by index.
+        let mut done = vec![];
+        // The number of connections that the hook was called for.
+        let mut handled = 0;
+        // The number of connections after the accepted ones are added.
+        let mut con_count = self.tcp.len();
 
         for (idx, (con, cid, time)) in self.new_cons.iter_mut().enumerate() {
-            match Self::handle_con_helper::<C>(deser_fn, con, self.config.timeout, time) {
+            if handled >= max {
+                break;
+            }
+            match Self::handle_con_helper::<C>(&self.parts, con, &self.config, time) {
                 // Done connecting.
-                Ok(c) => {
+                Ok((c, negotiated)) => {
+                    if !Self::has_slot(&self.config, &mut self.reserved_filter, con_count, &c) {
+                        done.push((idx, Pending::Full));
+                        continue;
+                    }
+
                     // Call hook
+                    handled += 1;
                     let (acc, resp) = hook(*cid, c);
                     if acc {
-                        accepted.push((idx, resp));
+                        con_count += 1;
+                        done.push((idx, Pending::Accepted(resp, negotiated)));
                     } else {
-                        rejected.push((idx, resp));
+                        done.push((idx, Pending::Rejected(resp)));
                     }
                 }
                 // Not done yet.
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) if e.is_would_block() => {}
+                // The message types don't match. Tell the client, so that it knows why.
+                Err(e) if e.is_negotiation() => {
+                    warn!("Refused a new connection. {}", e);
+                    done.push((idx, Pending::NotMatched));
+                }
                 // Error in connecting.
                 Err(e) => {
-                    error!(
-                        "IO error occurred while handling a pending connection. {}",
-                        e
-                    );
-                    dead.push(idx);
+                    error!("Error occurred while handling a pending connection. {}", e);
+                    done.push((idx, Pending::Dead));
                 }
             }
         }
 
-        // Dead connections do not count as handled; they do not call the hook.
-        let handled = accepted.len() + rejected.len();
-
-        // Handle accepted.
-        for (idx, resp) in accepted {
+        // Remove back to front, so that the indices stay valid.
+        for (idx, pending) in done.into_iter().rev() {
             let (con, cid, _) = self.new_cons.remove(idx);
-            self.accept_incoming(cid, con, &resp);
+            match pending {
+                Pending::Accepted(resp, negotiated) => {
+                    self.accept_incoming(cid, con, &resp, negotiated)
+                }
+                Pending::Rejected(resp) => self.reject_incoming(cid, con, &resp),
+                Pending::Full => match &self.full_response {
+                    Some(resp) => self.reject_incoming(cid, con, &**resp),
+                    None => debug!("Rejected new connection {}, the server is full.", cid),
+                },
+                Pending::NotMatched => self.refuse_incoming(cid, con),
+                Pending::Dead => {}
+            }
         }
 
-        // Handle rejected.
-        for (idx, resp) in rejected {
-            let (con, cid, _) = self.new_cons.remove(idx);
-            self.reject_incoming(cid, con, &resp);
+        handled
+    }
+
+    /// Checks whether there is a free slot for a new connection with the connection message
+    /// `con_msg`, when there are `con_count` connections.
+    fn has_slot<C: Any + Send + Sync>(
+        config: &Config,
+        reserved_filter: &mut Option<ReservedFilter>,
+        con_count: usize,
+        con_msg: &C,
+    ) -> bool {
+        let max = match config.max_connections {
+            Some(max) => max,
+            None => return true,
+        };
+        if con_count >= max {
+            return false;
         }
+        if con_count < max.saturating_sub(config.reserved_slots) {
+            return true;
+        }
+        // Only the reserved slots are left.
+        match reserved_filter {
+            Some(filter) => filter(con_msg),
+            None => false,
+        }
+    }
 
-        // Handle dead.
-        for idx in dead {
-            self.new_cons.remove(idx);
+    /// Sets the response that is sent to new connections when the server is full.
+    ///
+    /// Type `R` needs to match the `R` type that you passed into
+    /// [`MsgTable::build()`](crate::MsgTable::build), otherwise an [`Error::WrongType`] is returned.
+    /// Without a response, the connections are closed without one. See
+    /// [`Config::max_connections`].
+    pub fn set_full_response<R: Any + Send + Sync>(&mut self, resp: R) -> Result<()> {
+        if !self.parts.is_type::<R>(RESPONSE_TYPE_MID) {
+            return Err(Error::WrongType(type_name::<R>()));
         }
+        self.full_response = Some(Box::new(resp));
+        Ok(())
+    }
 
-        handled as u32
+    /// Sets the filter that decides which new connections can use the reserved slots, based on
+    /// their connection message.
+    ///
+    /// Type `C` needs to match the `C` type that you passed into
+    /// [`MsgTable::build()`](crate::MsgTable::build). Without a filter, the reserved slots are never
+    /// used. See [`Config::reserved_slots`].
+    pub fn set_reserved_filter<C: Any + Send + Sync>(
+        &mut self,
+        mut filter: impl FnMut(&C) -> bool + Send + Sync + 'static,
+    ) {
+        self.reserved_filter = Some(Box::new(move |con_msg| match con_msg.downcast_ref() {
+            Some(con_msg) => filter(con_msg),
+            None => {
+                error!(
+                    "The reserved slot filter takes a {}, which is not the connection message type.",
+                    type_name::<C>()
+                );
+                false
+            }
+        }));
     }
 
     /// Encapsulates new connection handling logic by trying to read the connection message.
     ///
-    /// If there is an error in connection (including timeout) this will return `Err(e)`. If the
-    /// connection opened successfully, it will return `Ok(c)`.
+    /// If there is an error in connection (including timeout and mismatched message types) this
+    /// will return `Err(e)`. If the connection opened successfully, it will return
+    /// `Ok((c, negotiated))`, with the message types that were matched with the connection.
     ///
-    /// If this returns an error other than a `WouldBlock` error, it should be removed from the
-    /// list of pending connections. If it returns `Ok(c)` it should also be removed, as it has
+    /// If this returns an error that is not a `WouldBlock` error, it should be removed from the
+    /// list of pending connections. If it returns `Ok(_)` it should also be removed, as it has
     /// finished connecting successfully.
     fn handle_con_helper<C: Any + Send + Sync>(
-        deser_fn: DeserFn,
+        parts: &MsgTableParts,
         con: &mut TcpCon,
-        timeout: Duration,
+        config: &Config,
         time: &Instant,
-    ) -> io::Result<C> {
-        if time.elapsed() > timeout {
-            return Err(Error::new(
-                ErrorKind::TimedOut,
-                "The new connection did not send a connection message in time.",
-            ));
+    ) -> Result<(C, Negotiated)> {
+        if time.elapsed() > config.timeout {
+            // The new connection did not send a connection message in time.
+            return Err(Error::Timeout);
         }
 
-        let (mid, msg) = con.recv()?;
+        let (mid, msg) = con.recv().map_err(|e| e.with_name(parts))?;
+        let (_tick, msg) = split_tick(config.tick_header, msg)?;
 
         if mid != CONNECTION_TYPE_MID {
-            let e_msg = format!("Expected MId {}, got MId {}.", CONNECTION_TYPE_MID, mid);
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+            return Err(Error::InvalidMId {
+                mid,
+                name: parts.name(mid),
+            });
         }
 
-        let con_msg = deser_fn(msg).map_err(|o| {
-            Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "Encountered a deserialization error when handling a new connection. {}",
-                    o
-                ),
-            )
-        })?;
+        let (manifest, msg) = split_manifest(msg)?;
+        let negotiated = Negotiated::new(parts, &manifest)?;
+        let con_msg = (parts.deser[CONNECTION_TYPE_MID])(msg)?;
 
-        let con_msg = *con_msg.downcast::<C>().unwrap();
-        Ok(con_msg)
+        match con_msg.downcast::<C>() {
+            Ok(con_msg) => Ok((*con_msg, negotiated)),
+            Err(_) => Err(Error::WrongType(type_name::<C>())),
+        }
     }
 
     /// Helper function that start handling the incoming tcp connections.
+    ///
+    /// Connections that are rejected by the [`AcceptPolicy`] are closed right away.
     fn start_incoming(&mut self) {
         while self.new_cons.len() < self.config.max_con_handle {
-            if let Ok((stream, _addr)) = self.listener.accept() {
-                debug!("New connection attempt.");
+            if let Ok((stream, addr)) = self.listener.accept() {
+                debug!("New connection attempt from {}.", addr);
+                let pending = self
+                    .new_cons
+                    .iter()
+                    .filter(|(con, _, _)| con.peer_addr().ok().map(|a| a.ip()) == Some(addr.ip()))
+                    .count();
+                if self.accept.check(addr, pending).is_err() {
+                    // Dropping the stream closes it.
+                    continue;
+                }
                 stream.set_nonblocking(true).unwrap();
                 let tcp_con = TcpCon::from_stream(stream, self.config.max_msg_size);
                 let cid = self.new_cid();
@@ -334,35 +515,48 @@ impl Server {
         }
     }
 
+    /// Sets the [`AcceptPolicy`] that decides which incoming connections are handled.
+    ///
+    /// This resets the connection attempt counts of
+    /// [`AcceptPolicy::attempt_limit`](AcceptPolicy::attempt_limit).
+    pub fn set_accept_policy(&mut self, policy: AcceptPolicy) {
+        self.accept.set_policy(policy);
+    }
+
+    /// Gets the [`AcceptPolicy`] that decides which incoming connections are handled.
+    pub fn accept_policy(&self) -> &AcceptPolicy {
+        &self.accept.policy
+    }
+
+    /// Gets the number of incoming connections that were rejected by the [`AcceptPolicy`].
+    pub fn rejected_attempts(&self) -> u64 {
+        self.accept.rejected()
+    }
+
     /// A helper function that accepts the incoming connection.
-    fn accept_incoming<R: Any + Send + Sync>(&mut self, cid: CId, con: TcpCon, resp: &R) {
+    fn accept_incoming<R: Any + Send + Sync>(
+        &mut self,
+        cid: CId,
+        con: TcpCon,
+        resp: &R,
+        negotiated: Negotiated,
+    ) {
         let addr = con.peer_addr().unwrap();
-        self.add_tcp_con_cid(cid, con);
-        if let Err(e) = self.send_to(cid, resp) {
+        if let Err(e) = self.respond(&con, resp) {
             error!(
                 "IO error occurred while responding to a pending connection. {} at {}",
                 e, addr
             );
-        } else {
-            debug!("Accepted new connection {} at {}.", cid, addr);
+            return;
         }
+        self.add_tcp_con_cid(cid, con, negotiated);
+        debug!("Accepted new connection {} at {}.", cid, addr);
     }
 
     /// A helper function that rejects the incoming connection.
-    fn reject_incoming<R: Any + Send + Sync>(&self, cid: CId, con: TcpCon, resp: &R) {
-        // Get items necessary to send.
-        let ser = self.parts.ser[RESPONSE_TYPE_MID];
-        let payload = match ser(resp) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("{}", e);
-                return;
-            }
-        };
-
-        // Send.
+    fn reject_incoming(&self, cid: CId, con: TcpCon, resp: &(dyn Any + Send + Sync)) {
         let addr = con.peer_addr().unwrap();
-        if let Err(e) = con.send(RESPONSE_TYPE_MID, &payload) {
+        if let Err(e) = self.respond(&con, resp) {
             error!(
                 "IO error occurred while responding to a pending connection. {} at {}",
                 e, addr
@@ -372,6 +566,38 @@ impl Server {
         }
     }
 
+    /// A helper function that refuses the incoming connection, because the message types could
+    /// not be matched.
+    ///
+    /// Only the message types of the server are sent back, so that the client can find out which
+    /// ones don't match.
+    fn refuse_incoming(&self, cid: CId, con: TcpCon) {
+        let addr = con.peer_addr().unwrap();
+        let result = response_payload(&self.parts.manifest(), None).and_then(|payload| {
+            con.send(
+                RESPONSE_TYPE_MID,
+                &prepend_tick(self.header_tick(), &payload),
+            )
+        });
+        match result {
+            Ok(()) => debug!("Refused new connection {} at {}.", cid, addr),
+            Err(e) => error!(
+                "IO error occurred while responding to a pending connection. {} at {}",
+                e, addr
+            ),
+        }
+    }
+
+    /// Sends the response message `resp` to a new connection, along with the message types of the
+    /// server.
+    fn respond(&self, con: &TcpCon, resp: &(dyn Any + Send + Sync)) -> Result<()> {
+        let resp = (self.parts.ser[RESPONSE_TYPE_MID])(resp)?;
+        let payload = response_payload(&self.parts.manifest(), Some(&resp))?;
+        let payload = prepend_tick(self.header_tick(), &payload);
+        con.send(RESPONSE_TYPE_MID, &payload)
+            .map_err(|e| e.with_name(&self.parts))
+    }
+
     /// Handles a single disconnect, if there is one available to handle.
     ///
     /// If there is no disconnects to handle, `hook` will not be called.
@@ -417,52 +643,192 @@ impl Server {
     }
 
     /// A function that encapsulates the sending logic for the TCP transport.
-    fn send_tcp(&self, cid: CId, mid: MId, payload: &[u8]) -> io::Result<()> {
-        let tcp = match self.tcp.get(&cid) {
-            Some(tcp) => tcp,
-            None => return Err(Error::new(ErrorKind::InvalidData, "Invalid CId.")),
+    fn send_tcp(&self, cid: CId, mid: MId, payload: &[u8]) -> Result<()> {
+        let (tcp, negotiated) = match (self.tcp.get(&cid), self.negotiated.get(&cid)) {
+            (Some(tcp), Some(negotiated)) => (tcp, negotiated),
+            _ => return Err(Error::InvalidCId(cid)),
         };
 
-        tcp.send(mid, payload)
+        let options = self.parts.options[mid];
+        let wire_mid = negotiated.wire_mid(&self.parts, mid)?;
+        let (header_mid, payload) =
+            compress(wire_mid, payload, options, self.config.compress_threshold);
+        let payload = prepend_tick(self.header_tick(), &payload);
+        check_size(
+            mid,
+            self.parts.name(mid),
+            &payload,
+            self.config.max_msg_size,
+        )?;
+        tcp.send(header_mid, &payload)?;
+        if let Some(limiter) = self.limiters.get(&cid) {
+            limiter
+                .lock()
+                .unwrap()
+                .record_tcp(payload.len() + TCP_HEADER_LEN);
+        }
+        Ok(())
     }
 
     /// A function that encapsulates the sending logic for the UDP transport.
-    fn send_udp(&self, cid: CId, mid: MId, payload: &[u8]) -> io::Result<()> {
-        let addr = match self.cid_addr.get(&cid) {
-            Some(addr) => *addr,
-            None => return Err(Error::new(ErrorKind::InvalidData, "Invalid CId.")),
+    fn send_udp(&self, cid: CId, mid: MId, payload: &[u8]) -> Result<()> {
+        let (addr, limiter, negotiated) = match (
+            self.cid_addr.get(&cid),
+            self.limiters.get(&cid),
+            self.negotiated.get(&cid),
+        ) {
+            (Some(addr), Some(limiter), Some(negotiated)) => (*addr, limiter, negotiated),
+            _ => return Err(Error::InvalidCId(cid)),
         };
 
-        self.udp.send_to(addr, mid, payload)
+        let options = self.parts.options[mid];
+        let wire_mid = negotiated.wire_mid(&self.parts, mid)?;
+        let (header_mid, payload) =
+            compress(wire_mid, payload, options, self.config.compress_threshold);
+        let payload = prepend_tick(self.header_tick(), &payload);
+        check_size(
+            mid,
+            self.parts.name(mid),
+            &payload,
+            self.config.max_msg_size,
+        )?;
+        limiter.lock().unwrap().send_udp(
+            header_mid,
+            &payload,
+            payload.len() + UDP_HEADER_LEN,
+            options,
+            |mid, payload| self.udp.send_to(addr, mid, payload),
+        )
+    }
+
+    /// Sends the queued UDP messages of all connections, as far as their bandwidth limits allow.
+    ///
+    /// UDP messages are queued if [`Config::schedule_udp`] is set, or if the connection's
+    /// [`BandwidthLimit`] uses [`OverBudget::Queue`](crate::bandwidth::OverBudget::Queue).
+    /// Queued messages are sent from the highest to the lowest priority, and expired messages are
+    /// discarded. If [`Config::batch_udp`] is set, the messages are packed together into as few
+    /// datagrams as possible. This should be called once every frame.
+    ///
+    /// Returns the number of messages sent. If sending fails for a connection, the queues of the
+    /// other connections are still sent, and the first error is returned.
+    pub fn send_queued(&self) -> Result<u32> {
+        let mut i = 0;
+        let mut first_err = None;
+        for (cid, limiter) in self.limiters.iter() {
+            let addr = self.cid_addr[cid];
+            let sent = limiter.lock().unwrap().flush(UDP_HEADER_LEN, |msgs| {
+                if self.config.batch_udp {
+                    self.udp.send_batch_to(addr, msgs)
+                } else {
+                    msgs.iter()
+                        .try_for_each(|(mid, payload)| self.udp.send_to(addr, *mid, payload))
+                }
+            });
+            match sent {
+                Ok(n) => i += n,
+                Err(e) => {
+                    error!(
+                        "UDP({}): IO error occurred while sending queued messages. {}",
+                        cid, e
+                    );
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(i),
+        }
+    }
+
+    /// Sets the bandwidth limit of the connection with [`CId`] `cid`, overriding the limit
+    /// from the [`Config`]. `None` removes the limit.
+    pub fn set_bandwidth_limit(&mut self, cid: CId, limit: Option<BandwidthLimit>) -> Result<()> {
+        match self.limiters.get_mut(&cid) {
+            Some(limiter) => {
+                limiter.get_mut().unwrap().set_limit(limit);
+                Ok(())
+            }
+            None => Err(Error::InvalidCId(cid)),
+        }
+    }
+
+    /// Gets the bandwidth limit of the connection with [`CId`] `cid`.
+    ///
+    /// Returns `None` if the connection is not limited, or if `cid` is not connected.
+    pub fn bandwidth_limit(&self, cid: CId) -> Option<BandwidthLimit> {
+        self.limiters.get(&cid)?.lock().unwrap().limit()
+    }
+
+    /// Gets the state of the bandwidth limiter of the connection with [`CId`] `cid`.
+    ///
+    /// Returns `None` if `cid` is not connected.
+    pub fn bandwidth_stats(&self, cid: CId) -> Option<BandwidthStats> {
+        Some(self.limiters.get(&cid)?.lock().unwrap().stats())
+    }
+
+    /// Sets the total bandwidth limit of all connections together, replacing
+    /// [`Config::total_bandwidth`]. `None` removes the limit.
+    pub fn set_total_bandwidth_limit(&mut self, limit: Option<BandwidthLimit>) {
+        self.total_budget.set_limit(limit);
+    }
+
+    /// Gets the total bandwidth limit of all connections together.
+    pub fn total_bandwidth_limit(&self) -> Option<BandwidthLimit> {
+        self.total_budget.limit()
+    }
+
+    /// Gets the state of the bandwidth limiters of all connections together.
+    ///
+    /// [`available`](BandwidthStats::available) is the budget left of the total limit. The other
+    /// stats are the sums over the connections that are currently connected.
+    pub fn total_bandwidth_stats(&self) -> BandwidthStats {
+        let mut total = BandwidthStats {
+            available: self.total_budget.available(),
+            ..BandwidthStats::default()
+        };
+        for limiter in self.limiters.values() {
+            let stats = limiter.lock().unwrap().stats();
+            total.sent_bytes += stats.sent_bytes;
+            total.sent_msgs += stats.sent_msgs;
+            total.dropped_msgs += stats.dropped_msgs;
+            total.expired_msgs += stats.expired_msgs;
+            total.queued_msgs += stats.queued_msgs;
+        }
+        total
     }
 
     /// A function that encapsulates the receiving logic for the TCP transport.
     ///
-    /// Any errors in receiving are returned. An error of type [`WouldBlock`] means no more
-    /// messages can be yielded without blocking.
-    fn recv_tcp(&mut self, cid: CId) -> io::Result<(MId, ErasedNetMsg)> {
+    /// Any errors in receiving are returned. An error that [`is_would_block()`](Error::is_would_block)
+    /// means no more messages can be yielded without blocking.
+    fn recv_tcp(&mut self, cid: CId) -> Result<(MId, ErasedNetMsg)> {
         let tcp = match self.tcp.get_mut(&cid) {
             Some(tcp) => tcp,
-            None => return Err(Error::new(ErrorKind::InvalidData, "Invalid CId.")),
+            None => return Err(Error::InvalidCId(cid)),
         };
 
         let (mid, bytes) = tcp.recv()?;
+        let (tick, bytes) = split_tick(self.config.tick_header, bytes)?;
+        let (mid, bytes) = decompress(mid, bytes, self.config.max_msg_size)?;
 
-        if !self.parts.valid_mid(mid) {
-            let e_msg = format!(
-                "TCP: Got a message specifying MId {}, but the maximum MId is {}.",
-                mid,
-                self.parts.mid_count()
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
-        }
+        let version = match self.negotiated.get(&cid) {
+            Some(negotiated) if negotiated.is_enabled(mid) => negotiated.versions()[mid],
+            _ => {
+                return Err(Error::InvalidMId {
+                    mid,
+                    name: self.parts.name(mid),
+                })
+            }
+        };
 
-        let deser_fn = self.parts.deser[mid];
-        let msg = deser_fn(bytes)?;
+        let msg = self.parts.deser_version(mid, version, &bytes)?;
 
         let net_msg = ErasedNetMsg {
             cid,
             time: None,
+            tick,
+            seq: 0,
             msg,
         };
 
@@ -471,37 +837,43 @@ impl Server {
 
     /// A function that encapsulates the receiving logic for the `UDP` transport.
     ///
-    /// Any errors in receiving are returned. An error of type [`WouldBlock`] means no more
-    /// messages can be yielded without blocking. [`InvalidData`] likely means carrier-pigeon
-    /// got bad data.
-    fn recv_udp(&mut self) -> io::Result<(MId, ErasedNetMsg)> {
+    /// Any errors in receiving are returned. An error that [`is_would_block()`](Error::is_would_block)
+    /// means no more messages can be yielded without blocking.
+    fn recv_udp(&mut self) -> Result<(MId, ErasedNetMsg)> {
         let (from, mid, time, bytes) = self.udp.recv_from()?;
+        let (tick, bytes) = split_tick(self.config.tick_header, bytes)?;
+        let (mid, bytes) = decompress(mid, bytes, self.config.max_msg_size)?;
+
+        // Only live connections have negotiated message types.
+        let (cid, negotiated) = match self
+            .addr_cid
+            .get(&from)
+            .and_then(|&cid| Some((cid, self.negotiated.get(&cid)?)))
+        {
+            Some(found) => found,
+            None => {
+                return Err(Error::Io(io::Error::new(
+                    ErrorKind::NotConnected,
+                    "Received data from a address that is not connected.",
+                )))
+            }
+        };
 
-        if !self.parts.valid_mid(mid) {
-            let e_msg = format!(
-                "TCP: Got a message specifying MId {}, but the maximum MId is {}.",
+        if !negotiated.is_enabled(mid) {
+            return Err(Error::InvalidMId {
                 mid,
-                self.parts.mid_count()
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+                name: self.parts.name(mid),
+            });
         }
+        let version = negotiated.versions()[mid];
 
-        let deser_fn = self.parts.deser[mid];
-        let msg = deser_fn(bytes)?;
-
-        let cid = match self.addr_cid.get(&from) {
-            Some(&cid) if self.alive(cid) => cid,
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    "Received data from a address that is not connected.",
-                ))
-            }
-        };
+        let msg = self.parts.deser_version(mid, version, &bytes)?;
 
         let net_msg = ErasedNetMsg {
             cid,
             time: Some(time),
+            tick,
+            seq: 0,
             msg,
         };
 
@@ -509,22 +881,59 @@ impl Server {
     }
 
     /// Sends a message to the [`CId`] `cid`.
-    pub fn send_to<T: Any + Send + Sync>(&self, cid: CId, msg: &T) -> io::Result<()> {
+    ///
+    /// Returns an [`Error::DisabledType`] if the client doesn't have `T`.
+    pub fn send_to<T: Any + Send + Sync>(&self, cid: CId, msg: &T) -> Result<()> {
         let tid = TypeId::of::<T>();
         if !self.valid_tid(tid) {
-            return Err(io::Error::new(
-                ErrorKind::InvalidData,
-                "Type not registered.",
-            ));
+            return Err(Error::UnregisteredType(type_name::<T>()));
         }
         let mid = self.parts.tid_map[&tid];
+        self.send_mid(cid, mid, msg)
+    }
+
+    /// Sends a message to the [`CId`] `cid`, using the [`MsgHandle`] of `T`.
+    ///
+    /// This skips looking up the [`MId`] of `T`. Returns an [`Error::WrongType`] if `handle` is
+    /// not from this server's [`MsgTableParts`].
+    pub fn send_to_with<T: Any + Send + Sync>(
+        &self,
+        cid: CId,
+        handle: MsgHandle<T>,
+        msg: &T,
+    ) -> Result<()> {
+        if !self.parts.is_type::<T>(handle.mid()) {
+            return Err(Error::WrongType(type_name::<T>()));
+        }
+        self.send_mid(cid, handle.mid(), msg)
+    }
+
+    /// Broadcasts a message to all connected clients, using the [`MsgHandle`] of `T`.
+    ///
+    /// See [`send_to_with()`](Self::send_to_with).
+    pub fn broadcast_with<T: Any + Send + Sync>(
+        &self,
+        handle: MsgHandle<T>,
+        msg: &T,
+    ) -> Result<()> {
+        for cid in self.cids() {
+            skip_disabled(self.send_to_with(cid, handle, msg))?;
+        }
+        Ok(())
+    }
+
+    /// Sends the message `msg` with [`MId`] `mid` to the [`CId`] `cid`.
+    fn send_mid(&self, cid: CId, mid: MId, msg: &(dyn Any + Send + Sync)) -> Result<()> {
+        let version = match self.negotiated.get(&cid) {
+            Some(negotiated) => negotiated.versions()[mid],
+            None => return Err(Error::InvalidCId(cid)),
+        };
         let transport = self.parts.transports[mid];
-        let ser_fn = self.parts.ser[mid];
-        let b = ser_fn(msg)?;
+        let b = self.parts.ser_version(mid, version, msg)?;
 
         trace!(
-            "Sending message of MId {}, len {}, to CId {}",
-            mid,
+            "Sending message of type {}, len {}, to CId {}",
+            self.parts.names[mid],
             b.len(),
             cid
         );
@@ -537,21 +946,75 @@ impl Server {
     }
 
     /// Broadcasts a message to all connected clients.
-    pub fn broadcast<T: Any + Send + Sync>(&self, msg: &T) -> io::Result<()> {
+    ///
+    /// The clients that don't have `T` are skipped. See the [`negotiate`](crate::negotiate)
+    /// module.
+    pub fn broadcast<T: Any + Send + Sync>(&self, msg: &T) -> Result<()> {
         for cid in self.cids() {
-            self.send_to(cid, msg)?;
+            skip_disabled(self.send_to(cid, msg))?;
         }
         Ok(())
     }
 
     /// Sends a message to all [`CId`]s that match `spec`.
-    pub fn send_spec<T: Any + Send + Sync>(&self, spec: CIdSpec, msg: &T) -> io::Result<()> {
+    pub fn send_spec<T: Any + Send + Sync>(&self, spec: CIdSpec, msg: &T) -> Result<()> {
         for cid in self.cids().filter(|cid| spec.matches(*cid)) {
-            self.send_to(cid, msg)?;
+            skip_disabled(self.send_to(cid, msg))?;
         }
         Ok(())
     }
 
+    /// Sends a message to all [`CId`]s that `pos` is relevant to.
+    ///
+    /// See the [`interest`](crate::interest) module for more.
+    pub fn broadcast_relevant<T: Any + Send + Sync>(&self, pos: Position, msg: &T) -> Result<()> {
+        for cid in self.relevant_cids(pos) {
+            skip_disabled(self.send_to(cid, msg))?;
+        }
+        Ok(())
+    }
+
+    /// Sends a message to all [`CId`]s that match `spec`, and that `pos` is relevant to.
+    pub fn send_spec_relevant<T: Any + Send + Sync>(
+        &self,
+        spec: CIdSpec,
+        pos: Position,
+        msg: &T,
+    ) -> Result<()> {
+        for cid in self.relevant_cids(pos).filter(|cid| spec.matches(*cid)) {
+            skip_disabled(self.send_to(cid, msg))?;
+        }
+        Ok(())
+    }
+
+    /// Answers all the remote procedure calls of type `Req` that were received, by calling `hook`
+    /// for each and sending the response back.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// Returns the number of calls answered.
+    ///
+    /// ### Panics
+    /// Panics if `Req` was not registered with `register_rpc()`.
+    pub fn handle_calls<Req, Resp>(&self, mut hook: impl FnMut(CId, &Req) -> Resp) -> u32
+    where
+        Req: Request<Response = Resp>,
+        Resp: Send + Sync,
+    {
+        let mut i = 0;
+        for call in self.recv::<RpcRequest<Req>>() {
+            let resp = RpcResponse::<Req> {
+                id: call.id,
+                resp: hook(call.cid, &call.req),
+            };
+            if let Err(e) = self.send_to(call.cid, &resp) {
+                error!("Failed to respond to a call from CId {}. {}", call.cid, e);
+            }
+            i += 1;
+        }
+        i
+    }
+
     /// Gets an iterator for the messages of type `T`.
     ///
     /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
@@ -559,7 +1022,7 @@ impl Server {
     /// ### Panics
     /// Panics if the type `T` was not registered.
     /// For a non-panicking version, see [try_recv()](Self::try_recv).
-    pub fn recv<T: Any + Send + Sync>(&self) -> impl Iterator<Item = NetMsg<T>> {
+    pub fn recv<T: Any + Send + Sync>(&self) -> impl Iterator<Item = NetMsg<'_, T>> {
         let tid = TypeId::of::<T>();
         if !self.parts.valid_tid(tid) {
             panic!("Type ({}) not registered.", type_name::<T>());
@@ -571,12 +1034,33 @@ impl Server {
             .map(|m| m.to_typed::<T>().unwrap())
     }
 
+    /// Gets an iterator for the messages of type `T`, using the [`MsgHandle`] of `T`.
+    ///
+    /// This skips looking up the [`MId`] of `T`.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// Like [`send_to_with()`](Self::send_to_with) returns an [`Error::WrongType`], this yields
+    /// no messages if `handle` is not from this server's [`MsgTableParts`].
+    pub fn recv_with<T: Any + Send + Sync>(
+        &self,
+        handle: MsgHandle<T>,
+    ) -> impl Iterator<Item = NetMsg<'_, T>> {
+        let is_type = self.parts.is_type::<T>(handle.mid());
+        self.msg_buff
+            .get(handle.mid())
+            .filter(|_| is_type)
+            .into_iter()
+            .flatten()
+            .map(|m| m.to_typed::<T>().unwrap())
+    }
+
     /// Gets an iterator for the messages of type `T`.
     ///
     /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
     ///
     /// Returns `None` if the type `T` was not registered.
-    pub fn try_recv<T: Any + Send + Sync>(&self) -> Option<impl Iterator<Item = NetMsg<T>>> {
+    pub fn try_recv<T: Any + Send + Sync>(&self) -> Option<impl Iterator<Item = NetMsg<'_, T>>> {
         let tid = TypeId::of::<T>();
         let mid = *self.parts.tid_map.get(&tid)?;
 
@@ -598,7 +1082,7 @@ impl Server {
     pub fn recv_spec<T: Any + Send + Sync>(
         &self,
         spec: CIdSpec,
-    ) -> impl Iterator<Item = NetMsg<T>> + '_ {
+    ) -> impl Iterator<Item = NetMsg<'_, T>> + '_ {
         let tid = TypeId::of::<T>();
         if !self.parts.valid_tid(tid) {
             panic!("Type ({}) not registered.", type_name::<T>());
@@ -620,7 +1104,7 @@ impl Server {
     pub fn try_recv_spec<T: Any + Send + Sync>(
         &self,
         spec: CIdSpec,
-    ) -> Option<impl Iterator<Item = NetMsg<T>> + '_> {
+    ) -> Option<impl Iterator<Item = NetMsg<'_, T>> + '_> {
         let tid = TypeId::of::<T>();
         let mid = *self.parts.tid_map.get(&tid)?;
 
@@ -632,6 +1116,61 @@ impl Server {
         )
     }
 
+    /// Removes all the messages of type `T`, and gets an iterator for them.
+    ///
+    /// Unlike [`recv()`](Self::recv), the messages are owned, so they can be moved elsewhere
+    /// without cloning them. Messages that are not consumed from the iterator are still removed.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// ### Panics
+    /// Panics if the type `T` was not registered.
+    pub fn drain<T: Any + Send + Sync>(&mut self) -> impl Iterator<Item = OwnedNetMsg<T>> + '_ {
+        let tid = TypeId::of::<T>();
+        if !self.parts.valid_tid(tid) {
+            panic!("Type ({}) not registered.", type_name::<T>());
+        }
+        let mid = self.parts.tid_map[&tid];
+
+        for net_msg in self.msg_buff[mid].iter() {
+            Self::unbuffer(&mut self.buffered, &mut self.arrivals, net_msg.cid);
+        }
+        self.msg_buff[mid]
+            .drain(..)
+            .map(|m| m.into_typed::<T>().unwrap())
+    }
+
+    /// Removes the oldest message of type `T`, and returns it.
+    ///
+    /// Returns `None` if there are no messages of type `T`.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    ///
+    /// ### Panics
+    /// Panics if the type `T` was not registered.
+    pub fn take<T: Any + Send + Sync>(&mut self) -> Option<OwnedNetMsg<T>> {
+        let tid = TypeId::of::<T>();
+        if !self.parts.valid_tid(tid) {
+            panic!("Type ({}) not registered.", type_name::<T>());
+        }
+        let mid = self.parts.tid_map[&tid];
+
+        if self.msg_buff[mid].is_empty() {
+            return None;
+        }
+        Some(self.remove_msg(mid, 0).into_typed::<T>().unwrap())
+    }
+
+    /// Gets an iterator for all the received messages, in the order they arrived.
+    ///
+    /// Unlike [`recv()`](Self::recv), this keeps the relative order of messages of different
+    /// types. Use [`AnyNetMsg::downcast()`] to get the typed messages.
+    ///
+    /// Make sure to call [`recv_msgs()`](Self::recv_msgs) before calling this.
+    pub fn recv_all_ordered(&self) -> impl Iterator<Item = AnyNetMsg<'_>> {
+        ordered_msgs(&self.msg_buff).into_iter()
+    }
+
     /// Receives the messages from the connections. This should be done before calling `recv<T>()`.
     ///
     /// When done in a game loop, you should call `clear_msgs()`, then `recv_msgs()` before default
@@ -642,7 +1181,7 @@ impl Server {
         // TCP
         for cid in self.cids().collect::<Vec<_>>() {
             loop {
-                let msg = self.recv_tcp(cid);
+                let msg = self.recv_tcp(cid).map_err(|e| e.with_name(&self.parts));
                 if self.handle_tcp_msg(&mut i, cid, msg) {
                     // Done yielding messages.
                     break;
@@ -652,7 +1191,7 @@ impl Server {
 
         // UDP
         loop {
-            let msg = self.recv_udp();
+            let msg = self.recv_udp().map_err(|e| e.with_name(&self.parts));
             if self.handle_udp_msg(&mut i, msg) {
                 // Done yielding messages.
                 break;
@@ -673,10 +1212,10 @@ impl Server {
         &mut self,
         count: &mut u32,
         cid: CId,
-        msg: io::Result<(MId, ErasedNetMsg)>,
+        msg: Result<(MId, ErasedNetMsg)>,
     ) -> bool {
         match msg {
-            Err(e) if e.kind() == WouldBlock => true,
+            Err(e) if e.is_would_block() => true,
             // Other error occurred.
             Err(e) => {
                 error!(
@@ -696,8 +1235,8 @@ impl Server {
                     return true;
                 }
 
-                self.msg_buff[mid].push(net_msg);
-                false
+                // Stop reading from the connection if it was kicked.
+                self.accept_msg(mid, net_msg)
             }
         }
     }
@@ -709,9 +1248,9 @@ impl Server {
     /// When getting an error, this will log and ignore it. Otherwise it adds it to the msg buffer.
     ///
     /// returns weather the udp connection is done yielding messages.
-    fn handle_udp_msg(&mut self, count: &mut u32, msg: io::Result<(MId, ErasedNetMsg)>) -> bool {
+    fn handle_udp_msg(&mut self, count: &mut u32, msg: Result<(MId, ErasedNetMsg)>) -> bool {
         match msg {
-            Err(e) if e.kind() == WouldBlock => true,
+            Err(e) if e.is_would_block() => true,
             // Other error occurred.
             Err(e) => {
                 error!("UDP: IO error occurred while receiving data. {}", e);
@@ -720,17 +1259,234 @@ impl Server {
             // Got a message.
             Ok((mid, net_msg)) => {
                 *count += 1;
-                self.msg_buff[mid].push(net_msg);
+                self.accept_msg(mid, net_msg);
+                false
+            }
+        }
+    }
+
+    /// Checks a received message against the rate limits of its sender, then adds it to the
+    /// buffer.
+    ///
+    /// Returns whether the sender was kicked.
+    fn accept_msg(&mut self, mid: MId, net_msg: ErasedNetMsg) -> bool {
+        let cid = net_msg.cid;
+        trace!(
+            "Received message of type {} from CId {}",
+            self.parts.names[mid],
+            cid
+        );
+        let kind = match self.recv_limiters.get_mut(&cid) {
+            Some(limiter) => limiter.check(mid),
+            None => None,
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return self.push_msg(mid, net_msg),
+        };
+
+        let violation = Violation { cid, mid, kind };
+        let action = match &mut self.violation_hook {
+            Some(hook) => hook(violation),
+            None => ViolationAction::Drop,
+        };
+        match action {
+            ViolationAction::Warn => {
+                warn!(
+                    "CId {} went over the {:?} rate limit of {}.",
+                    cid, kind, self.parts.names[mid]
+                );
+                self.push_msg(mid, net_msg)
+            }
+            ViolationAction::Drop => {
+                trace!(
+                    "Dropped a message of type {} from CId {} that went over a rate limit.",
+                    self.parts.names[mid],
+                    cid
+                );
                 false
             }
+            ViolationAction::Disconnect => {
+                self.kick(cid, KickReason::RateLimit);
+                true
+            }
         }
     }
 
+    /// Adds a received message to the buffer, giving it the next sequence number.
+    ///
+    /// When a buffer is full, this applies [`Config::overflow`].
+    ///
+    /// Returns whether the sender was kicked because of it.
+    fn push_msg(&mut self, mid: MId, mut net_msg: ErasedNetMsg) -> bool {
+        let cid = net_msg.cid;
+        let mid_full = self
+            .config
+            .max_buffered_per_mid
+            .is_some_and(|max| self.msg_buff[mid].len() >= max);
+        let cid_full = self
+            .config
+            .max_buffered_per_cid
+            .is_some_and(|max| self.buffered.get(&cid).copied().unwrap_or(0) >= max);
+
+        if mid_full || cid_full {
+            match self.config.overflow {
+                OverflowPolicy::DropNewest => {
+                    self.count_dropped(cid);
+                    return false;
+                }
+                OverflowPolicy::Disconnect => {
+                    self.count_dropped(cid);
+                    self.kick(cid, KickReason::BufferOverflow);
+                    return true;
+                }
+                OverflowPolicy::DropOldest => {
+                    // The sender makes room for itself, so that other clients don't lose messages.
+                    // If both are full, its message of this type makes room in both.
+                    if cid_full {
+                        let own = mid_full
+                            .then(|| self.msg_buff[mid].iter().position(|msg| msg.cid == cid))
+                            .flatten()
+                            .map(|idx| (mid, idx));
+                        match own.or_else(|| self.oldest_from(cid)) {
+                            Some((old_mid, idx)) => {
+                                self.remove_msg(old_mid, idx);
+                                self.count_dropped(cid);
+                            }
+                            // A limit of 0, so there is nothing to make room in.
+                            None => {
+                                self.count_dropped(cid);
+                                return false;
+                            }
+                        }
+                    }
+                    // Still full if the sender's message was of another type.
+                    let mid_full = self
+                        .config
+                        .max_buffered_per_mid
+                        .is_some_and(|max| self.msg_buff[mid].len() >= max);
+                    if mid_full {
+                        if self.msg_buff[mid].is_empty() {
+                            // A limit of 0, so there is nothing to make room in.
+                            self.count_dropped(cid);
+                            return false;
+                        }
+                        let old = self.remove_msg(mid, 0);
+                        self.count_dropped(old.cid);
+                    }
+                }
+            }
+        }
+
+        net_msg.seq = self.next_seq;
+        self.next_seq += 1;
+        let buffered = self.buffered.entry(cid).or_default();
+        *buffered += 1;
+        let arrivals = self.arrivals.entry(cid).or_default();
+        arrivals.push_back((mid, net_msg.seq));
+        // Forget the messages that were removed, once they make up most of the entries.
+        if arrivals.len() > 2 * *buffered {
+            let msg_buff = &self.msg_buff;
+            arrivals.retain(|(mid, seq)| find_seq(&msg_buff[*mid], *seq).is_some());
+        }
+        self.msg_buff[mid].push_back(net_msg);
+        false
+    }
+
+    /// Removes the message at `idx` from the buffer of `mid`.
+    fn remove_msg(&mut self, mid: MId, idx: usize) -> ErasedNetMsg {
+        let net_msg = self.msg_buff[mid].remove(idx).unwrap();
+        Self::unbuffer(&mut self.buffered, &mut self.arrivals, net_msg.cid);
+        net_msg
+    }
+
+    /// Decrements the buffered message count of `cid`.
+    fn unbuffer(
+        buffered: &mut HashMap<CId, usize>,
+        arrivals: &mut HashMap<CId, VecDeque<(MId, u64)>>,
+        cid: CId,
+    ) {
+        if let Some(count) = buffered.get_mut(&cid) {
+            *count -= 1;
+            if *count == 0 {
+                buffered.remove(&cid);
+                arrivals.remove(&cid);
+            }
+        }
+    }
+
+    /// Finds the [`MId`] and index of the oldest buffered message from `cid`.
+    fn oldest_from(&mut self, cid: CId) -> Option<(MId, usize)> {
+        let arrivals = self.arrivals.get_mut(&cid)?;
+        while let Some(&(mid, seq)) = arrivals.front() {
+            if let Some(idx) = find_seq(&self.msg_buff[mid], seq) {
+                return Some((mid, idx));
+            }
+            // This message was already removed.
+            arrivals.pop_front();
+        }
+        None
+    }
+
+    /// Counts a received message from `cid` that was dropped because a buffer was full.
+    fn count_dropped(&mut self, cid: CId) {
+        self.dropped_total += 1;
+        if let Some(dropped) = self.dropped.get_mut(&cid) {
+            *dropped += 1;
+        }
+    }
+
+    /// Closes the connection of `cid` for misbehaving, if it is not already disconnecting.
+    ///
+    /// The disconnect is handled with the others in `handle_disconnects()`, with
+    /// [`Status::Kicked`].
+    fn kick(&mut self, cid: CId, reason: KickReason) {
+        if self.disconnected.iter().any(|(other, _)| *other == cid) {
+            return;
+        }
+        warn!("Kicking CId {}: {}.", cid, reason);
+        if let Some(tcp) = self.tcp.get_mut(&cid) {
+            if let Err(e) = tcp.close() {
+                debug!("Failed to close the TCP connection of CId {}. {}", cid, e);
+            }
+        }
+        self.disconnected.push_back((cid, Status::Kicked(reason)));
+    }
+
+    /// Sets the hook that decides what to do with a received message that went over a rate limit.
+    ///
+    /// Without a hook, these messages are dropped. See the [`rate_limit`](crate::rate_limit)
+    /// module.
+    pub fn set_violation_hook(
+        &mut self,
+        hook: impl FnMut(Violation) -> ViolationAction + Send + Sync + 'static,
+    ) {
+        self.violation_hook = Some(Box::new(hook));
+    }
+
+    /// Gets the total number of received messages that were dropped because a receive buffer was
+    /// full.
+    ///
+    /// See [`Config::overflow`].
+    pub fn dropped_msgs(&self) -> u64 {
+        self.dropped_total
+    }
+
+    /// Gets the number of received messages from `cid` that were dropped because a receive buffer
+    /// was full.
+    ///
+    /// Returns `0` if `cid` is not connected.
+    pub fn dropped_msgs_from(&self, cid: CId) -> u64 {
+        self.dropped.get(&cid).copied().unwrap_or(0)
+    }
+
     /// Clears messages from the buffer.
     pub fn clear_msgs(&mut self) {
         for buff in self.msg_buff.iter_mut() {
             buff.clear();
         }
+        self.buffered.clear();
+        self.arrivals.clear();
     }
 
     /// Gets the address that the server is listening on.
@@ -748,6 +1504,68 @@ impl Server {
         self.cid_addr.contains_key(&cid)
     }
 
+    /// Gets the version of each message type that was picked with the connection with [`CId`]
+    /// `cid`, in [`MId`] order.
+    ///
+    /// Returns `None` if `cid` is not connected. See the [`version`](crate::version) module.
+    pub fn versions(&self, cid: CId) -> Option<&[Version]> {
+        self.negotiated.get(&cid).map(|n| n.versions())
+    }
+
+    /// Gets the message types that were matched with the connection with [`CId`] `cid`.
+    ///
+    /// Returns `None` if `cid` is not connected. See the [`negotiate`](crate::negotiate) module.
+    pub fn negotiated(&self, cid: CId) -> Option<&Negotiated> {
+        self.negotiated.get(&cid)
+    }
+
+    /// Returns whether `T` can be sent to the connection with [`CId`] `cid`. This is `false` if
+    /// `T` is not registered, or if it is disabled because the client doesn't have it.
+    pub fn is_enabled<T: Any + Send + Sync>(&self, cid: CId) -> bool {
+        match (
+            self.parts.tid_map.get(&TypeId::of::<T>()),
+            self.negotiated.get(&cid),
+        ) {
+            (Some(&mid), Some(negotiated)) => negotiated.is_enabled(mid),
+            _ => false,
+        }
+    }
+
+    /// Sets the [`Interest`] of the connection with [`CId`] `cid`.
+    ///
+    /// New connections start with [`Interest::All`].
+    pub fn set_interest(&mut self, cid: CId, interest: Interest) -> Result<()> {
+        match self.interests.get_mut(&cid) {
+            Some(old) => {
+                *old = interest;
+                Ok(())
+            }
+            None => Err(Error::InvalidCId(cid)),
+        }
+    }
+
+    /// Gets the [`Interest`] of the connection with [`CId`] `cid`.
+    pub fn interest(&self, cid: CId) -> Option<&Interest> {
+        self.interests.get(&cid)
+    }
+
+    /// Returns whether `pos` is relevant to the connection with [`CId`] `cid`.
+    ///
+    /// Always `false` if `cid` is not connected.
+    pub fn is_relevant(&self, cid: CId, pos: Position) -> bool {
+        self.interests
+            .get(&cid)
+            .is_some_and(|interest| interest.is_relevant(pos))
+    }
+
+    /// An iterator of the [`CId`]s that `pos` is relevant to.
+    pub fn relevant_cids(&self, pos: Position) -> impl Iterator<Item = CId> + '_ {
+        self.interests
+            .iter()
+            .filter(move |(_, interest)| interest.is_relevant(pos))
+            .map(|(cid, _)| *cid)
+    }
+
     /// Returns whether a message of type `tid` can be sent.
     pub fn valid_tid(&self, tid: TypeId) -> bool {
         self.parts.valid_tid(tid)
@@ -777,20 +1595,126 @@ impl Server {
 
     /// Adds a `TCP` connection with the [`CId`] `cid`. The cid needs to be unique, generate one
     /// with `new_cid()`.
-    fn add_tcp_con_cid(&mut self, cid: CId, con: TcpCon) {
+    fn add_tcp_con_cid(&mut self, cid: CId, con: TcpCon, negotiated: Negotiated) {
         let peer_addr = con.peer_addr().unwrap();
         self.tcp.insert(cid, con);
         self.addr_cid.insert(peer_addr, cid);
         self.cid_addr.insert(cid, peer_addr);
+        self.limiters.insert(
+            cid,
+            Mutex::new(Limiter::new(
+                self.config.bandwidth,
+                self.config.schedule_udp || self.config.batch_udp,
+                self.total_budget.clone(),
+            )),
+        );
+        self.interests.insert(cid, Interest::All);
+        self.dropped.insert(cid, 0);
+        self.recv_limiters.insert(
+            cid,
+            RecvLimiter::new(self.config.recv_rate_limit, &self.parts),
+        );
+        self.negotiated.insert(cid, negotiated);
     }
 
     /// Removes a `TCP` connection.
-    fn rm_tcp_con(&mut self, cid: CId) -> io::Result<()> {
-        self.tcp
-            .remove(&cid)
-            .ok_or_else(|| Error::new(InvalidData, "Invalid CId."))?;
+    fn rm_tcp_con(&mut self, cid: CId) -> Result<()> {
+        self.tcp.remove(&cid).ok_or(Error::InvalidCId(cid))?;
         let addr = self.cid_addr.remove(&cid).unwrap();
         self.addr_cid.remove(&addr);
+        self.limiters.remove(&cid);
+        self.interests.remove(&cid);
+        self.dropped.remove(&cid);
+        self.recv_limiters.remove(&cid);
+        self.negotiated.remove(&cid);
         Ok(())
     }
 }
+
+/// A [`Server`] that knows the connection (`C`), response (`R`) and disconnect (`D`) message
+/// types.
+///
+/// Created with [`Server::listen()`]. The methods that use these types can not be called with
+/// the wrong types. All other methods are available through [`Deref`] to the [`Server`].
+pub struct TypedServer<C, R, D> {
+    server: Server,
+    #[allow(clippy::type_complexity)]
+    _types: PhantomData<fn() -> (C, R, D)>,
+}
+
+impl<C, R, D> TypedServer<C, R, D>
+where
+    C: Any + Send + Sync,
+    R: Any + Send + Sync,
+    D: Any + Send + Sync,
+{
+    /// Disconnects from the given `cid`. See [`Server::disconnect()`].
+    pub fn disconnect(&mut self, discon_msg: &D, cid: CId) -> Result<()> {
+        self.server.disconnect(discon_msg, cid)
+    }
+
+    /// Handles a new connection attempt, if there is one. See [`Server::handle_new_con()`].
+    pub fn handle_new_con(&mut self, hook: impl FnMut(CId, C) -> (bool, R)) -> bool {
+        self.server.handle_new_con(hook)
+    }
+
+    /// Handles all available new connection attempts. See [`Server::handle_new_cons()`].
+    pub fn handle_new_cons(&mut self, hook: impl FnMut(CId, C) -> (bool, R)) -> u32 {
+        self.server.handle_new_cons(hook)
+    }
+
+    /// Sets the response that is sent to new connections when the server is full. See
+    /// [`Server::set_full_response()`].
+    pub fn set_full_response(&mut self, resp: R) {
+        self.server
+            .set_full_response(resp)
+            .expect("the response type of the TypedParts is always the response type");
+    }
+
+    /// Handles a disconnect, if there is one. See [`Server::handle_disconnect()`].
+    pub fn handle_disconnect(&mut self, mut hook: impl FnMut(CId, TypedStatus<D>)) -> bool {
+        self.server
+            .handle_disconnect(|cid, status| hook(cid, TypedStatus::new(&status)))
+    }
+
+    /// Handles all disconnects. See [`Server::handle_disconnects()`].
+    pub fn handle_disconnects(&mut self, mut hook: impl FnMut(CId, TypedStatus<D>)) -> u32 {
+        self.server
+            .handle_disconnects(|cid, status| hook(cid, TypedStatus::new(&status)))
+    }
+
+    /// Forgets the message types, giving back the [`Server`].
+    pub fn erase(self) -> Server {
+        self.server
+    }
+}
+
+impl<C, R, D> Deref for TypedServer<C, R, D> {
+    type Target = Server;
+
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
+impl<C, R, D> DerefMut for TypedServer<C, R, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.server
+    }
+}
+
+/// Turns an [`Error::DisabledType`] into `Ok(())`, for sending to many connections where some
+/// don't have the message type.
+fn skip_disabled(result: Result<()>) -> Result<()> {
+    match result {
+        Err(Error::DisabledType(_)) => Ok(()),
+        result => result,
+    }
+}
+
+/// Finds the index of the message with sequence number `seq` in `buff`.
+///
+/// The messages in a buffer are always in the order they arrived, so this is a binary search.
+fn find_seq(buff: &VecDeque<ErasedNetMsg>, seq: u64) -> Option<usize> {
+    buff.binary_search_by_key(&seq, |net_msg| net_msg.seq).ok()
+}
@@ -0,0 +1,276 @@
+//! Snapshot interpolation on the client.
+//!
+//! State sent over UDP arrives with jitter, out of order, or not at all. A [`SnapshotBuffer<T>`]
+//! collects the snapshots by the time they were sent ([`NetMsg::time`]), and is sampled at a
+//! render time that is a configurable delay behind the current time. As long as the delay is
+//! larger than the jitter, there is a snapshot on both sides of the render time to interpolate
+//! between. When there isn't, that is an underrun, and the latest snapshot is used instead.
+//!
+//! The timestamps are the unix millis of the sender, so this assumes that the clocks of the client
+//! and server are roughly in sync.
+
+use crate::net::NetMsg;
+use crate::time::unix_millis;
+use crate::Client;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A type that can be interpolated between two values.
+pub trait Interpolate {
+    /// Gets the value `t` of the way from `self` to `other`, where `t` is between `0.0` and `1.0`.
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self + (other - self) * t as f64
+    }
+}
+
+impl<T: Interpolate, const N: usize> Interpolate for [T; N] {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].interpolate(&other[i], t))
+    }
+}
+
+/// The state of a [`SnapshotBuffer`] at a render time.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sample<'s, T> {
+    /// There are no snapshots.
+    Empty,
+    /// The render time is before the oldest snapshot. Holds the oldest snapshot.
+    Before(&'s T),
+    /// The render time is between two snapshots.
+    Between {
+        /// The latest snapshot before the render time.
+        from: &'s T,
+        /// The first snapshot after the render time.
+        to: &'s T,
+        /// How far the render time is from `from` to `to`, between `0.0` and `1.0`.
+        t: f32,
+    },
+    /// The render time is after the newest snapshot. Holds the newest snapshot.
+    Underrun(&'s T),
+}
+
+impl<'s, T> Sample<'s, T> {
+    /// Gets the latest snapshot before the render time.
+    pub fn latest_before(&self) -> Option<&'s T> {
+        match *self {
+            Sample::Empty | Sample::Before(_) => None,
+            Sample::Between { from, .. } => Some(from),
+            Sample::Underrun(latest) => Some(latest),
+        }
+    }
+
+    /// Gets the state at the render time, interpolating between snapshots if possible.
+    ///
+    /// Outside of the range of the snapshots, the closest snapshot is used.
+    pub fn interpolated(&self) -> Option<T>
+    where
+        T: Interpolate + Clone,
+    {
+        match *self {
+            Sample::Empty => None,
+            Sample::Before(snapshot) | Sample::Underrun(snapshot) => Some(snapshot.clone()),
+            Sample::Between { from, to, t } => Some(from.interpolate(to, t)),
+        }
+    }
+
+    /// Whether the render time was after the newest snapshot.
+    pub fn is_underrun(&self) -> bool {
+        matches!(self, Sample::Underrun(_))
+    }
+}
+
+/// A buffer of timestamped snapshots of type `T`.
+///
+/// See the [module level docs](self) for more.
+#[derive(Clone, Debug)]
+pub struct SnapshotBuffer<T> {
+    /// The snapshots, sorted by time.
+    snapshots: VecDeque<(u32, T)>,
+    delay: Duration,
+    capacity: usize,
+    underruns: u64,
+}
+
+impl<T> SnapshotBuffer<T> {
+    /// Creates a new [`SnapshotBuffer`] that renders `delay` behind the current time, and holds
+    /// at most `capacity` snapshots.
+    pub fn new(delay: Duration, capacity: usize) -> Self {
+        SnapshotBuffer {
+            snapshots: VecDeque::with_capacity(capacity),
+            delay,
+            capacity,
+            underruns: 0,
+        }
+    }
+
+    /// Gets the delay of the render time.
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Sets the delay of the render time.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Adds a snapshot that was sent at `time` (in unix millis).
+    ///
+    /// Snapshots with the same time as one already in the buffer are ignored. When the buffer is
+    /// full, the oldest snapshot is discarded.
+    pub fn push(&mut self, time: u32, snapshot: T) {
+        let idx = self.snapshots.partition_point(|(t, _)| *t < time);
+        if self.snapshots.get(idx).is_some_and(|(t, _)| *t == time) {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            if idx == 0 {
+                // Older than everything in a full buffer.
+                return;
+            }
+            self.snapshots.pop_front();
+            self.snapshots.insert(idx - 1, (time, snapshot));
+        } else {
+            self.snapshots.insert(idx, (time, snapshot));
+        }
+    }
+
+    /// Adds a [`NetMsg`]. Messages without a timestamp (sent over TCP) are ignored.
+    pub fn push_msg(&mut self, msg: NetMsg<'_, T>)
+    where
+        T: Any + Send + Sync + Clone,
+    {
+        if let Some(time) = msg.time {
+            self.push(time, msg.m.clone());
+        }
+    }
+
+    /// Adds all the messages of type `T` that the client received.
+    ///
+    /// Make sure to call [`Client::recv_msgs()`] before calling this.
+    ///
+    /// ### Panics
+    /// Panics if the type `T` was not registered.
+    pub fn recv(&mut self, client: &Client)
+    where
+        T: Any + Send + Sync + Clone,
+    {
+        for msg in client.recv::<T>() {
+            self.push_msg(msg);
+        }
+    }
+
+    /// Gets the current render time in unix millis.
+    pub fn render_time(&self) -> u32 {
+        unix_millis().wrapping_sub(self.delay.as_millis() as u32)
+    }
+
+    /// Samples the buffer at the current [`render_time()`](Self::render_time).
+    pub fn sample(&mut self) -> Sample<'_, T> {
+        let render_time = self.render_time();
+        self.sample_at(render_time)
+    }
+
+    /// Samples the buffer at `render_time`.
+    ///
+    /// Snapshots that are too old to be needed for any later render time are discarded. If
+    /// `render_time` is after the newest snapshot, an underrun is counted.
+    pub fn sample_at(&mut self, render_time: u32) -> Sample<'_, T> {
+        // The number of snapshots at or before the render time.
+        let before = self.snapshots.partition_point(|(t, _)| *t <= render_time);
+        if before > 1 {
+            self.snapshots.drain(..before - 1);
+        }
+
+        match (before, self.snapshots.len()) {
+            (_, 0) => Sample::Empty,
+            (0, _) => Sample::Before(&self.snapshots[0].1),
+            (_, 1) => {
+                self.underruns += 1;
+                Sample::Underrun(&self.snapshots[0].1)
+            }
+            _ => {
+                let (from_time, from) = &self.snapshots[0];
+                let (to_time, to) = &self.snapshots[1];
+                let t = (render_time - from_time) as f32 / (to_time - from_time) as f32;
+                Sample::Between { from, to, t }
+            }
+        }
+    }
+
+    /// Gets the number of underruns so far.
+    ///
+    /// A growing count means that the delay is too small for the connection.
+    pub fn underruns(&self) -> u64 {
+        self.underruns
+    }
+
+    /// Gets the number of snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Gets whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Removes all snapshots.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snapshot::{Sample, SnapshotBuffer};
+    use std::time::Duration;
+
+    #[test]
+    fn sample() {
+        let mut buffer = SnapshotBuffer::new(Duration::from_millis(100), 4);
+        assert_eq!(buffer.sample_at(0), Sample::Empty);
+
+        // Out of order, with a duplicate.
+        buffer.push(1100, 10.0);
+        buffer.push(1000, 0.0);
+        buffer.push(1200, 20.0);
+        buffer.push(1100, 99.0);
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.sample_at(900), Sample::Before(&0.0));
+        assert_eq!(buffer.sample_at(1050).interpolated(), Some(5.0));
+        assert_eq!(buffer.sample_at(1150).latest_before(), Some(&10.0));
+        // The first snapshot is not needed anymore.
+        assert_eq!(buffer.len(), 2);
+
+        assert_eq!(buffer.underruns(), 0);
+        let sample = buffer.sample_at(1300);
+        assert!(sample.is_underrun());
+        assert_eq!(sample.interpolated(), Some(20.0));
+        assert_eq!(buffer.underruns(), 1);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut buffer = SnapshotBuffer::new(Duration::from_millis(100), 2);
+        buffer.push(1000, [0.0, 0.0]);
+        buffer.push(1100, [1.0, 2.0]);
+        buffer.push(1200, [2.0, 4.0]);
+        // Older than everything in a full buffer.
+        buffer.push(900, [9.0, 9.0]);
+
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.sample_at(1150).interpolated(), Some([1.5, 3.0]));
+    }
+}
@@ -1,7 +1,6 @@
-use crate::header::TCP_HEADER_LEN;
+use crate::header::{COMPRESSED_FLAG, TCP_HEADER_LEN};
 use crate::net::TcpHeader;
-use crate::MId;
-use io::Error;
+use crate::{Error, MId, Result};
 use log::trace;
 use std::io;
 use std::io::{ErrorKind, Read, Write};
@@ -38,19 +37,17 @@ impl TcpCon {
     /// Sends the payload `payload` to the peer.
     ///
     /// This constructs a header, and builds the message, and sends it.
-    pub fn send(&self, mid: MId, payload: &[u8]) -> io::Result<()> {
+    pub fn send(&self, mid: MId, payload: &[u8]) -> Result<()> {
         let total_len = payload.len() + TCP_HEADER_LEN;
         let mut buff = vec![0; total_len];
         // Check if the message is valid, and should be sent.
         if total_len > self.buff_size() {
-            let e_msg = format!(
-                "TCP: Outgoing message size is greater than the maximum message size ({}). \
-				MId: {}, size: {}. Discarding message.",
-                self.max_msg_size(),
+            return Err(Error::MessageTooLarge {
                 mid,
-                total_len
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+                name: None,
+                size: payload.len(),
+                max: self.max_msg_size(),
+            });
         }
         // Message can be sent!
 
@@ -66,7 +63,7 @@ impl TcpCon {
         }
 
         // Send
-        trace!("TCP: Sending message with MId: {}, len: {}", mid, total_len);
+        trace!("TCP: Sending message, len: {}", total_len);
         let mut tcp = self.tcp.write().unwrap();
         tcp.write_all(&buff[..total_len])?;
         Ok(())
@@ -76,49 +73,43 @@ impl TcpCon {
     ///
     /// If a message is not available yet, this will yield an error with the kind `WouldBlock`.
     /// All other errors returned are actual IO errors.
-    pub fn recv(&mut self) -> io::Result<(MId, &[u8])> {
+    pub fn recv(&mut self) -> Result<(MId, &[u8])> {
         // Peak the header.
         let mut tcp = self.tcp.write().unwrap();
         match tcp.peek(&mut self.buff[..TCP_HEADER_LEN])? {
             TCP_HEADER_LEN => {} // Success
             0 => {
-                return Err(Error::new(
+                return Err(Error::Io(io::Error::new(
                     ErrorKind::ConnectionAborted,
                     "The connection was closed.",
-                ))
+                )))
             }
             _ => {
-                return Err(Error::new(
+                return Err(Error::Io(io::Error::new(
                     ErrorKind::WouldBlock,
                     "Data for entire message has not arrived yet.",
-                ))
+                )))
             }
         }
         let header = TcpHeader::from_be_bytes(&self.buff[..TCP_HEADER_LEN]);
         let total_expected_len = header.len + TCP_HEADER_LEN;
 
         if header.len > self.max_msg_size() {
-            let e_msg = format!(
-                "The header of a received message indicates a size of {},\
-	                but the max allowed message size is {}.\
-					carrier-pigeon never sends a message greater than this; \
-					this message was likely not sent by carrier-pigeon. \
-	                This will cause issues when trying to read; \
-	                Discarding this message and closing connection.",
-                header.len,
-                self.max_msg_size()
-            );
+            // carrier-pigeon never sends a message greater than this; this message was likely not
+            // sent by carrier-pigeon. This will cause issues when trying to read, so the
+            // connection is closed.
             tcp.shutdown(Shutdown::Both)?;
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+            return Err(Error::MessageTooLarge {
+                mid: header.mid & !COMPRESSED_FLAG,
+                name: None,
+                size: header.len,
+                max: self.max_msg_size(),
+            });
         }
 
         // Read data. The header will be read again as it was peaked earlier.
         tcp.read_exact(&mut self.buff[..header.len + TCP_HEADER_LEN])?;
-        trace!(
-            "TCP: Received msg of MId {}, len {}",
-            header.mid,
-            total_expected_len,
-        );
+        trace!("TCP: Received msg, len {}", total_expected_len);
 
         Ok((
             header.mid,
@@ -127,24 +118,24 @@ impl TcpCon {
     }
 
     /// Moves the internal [`TcpStream`] into or out of nonblocking mode.
-    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
-        self.tcp.read().unwrap().set_nonblocking(nonblocking)
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        Ok(self.tcp.read().unwrap().set_nonblocking(nonblocking)?)
     }
 
     /// Closes the connection by flushing then shutting down the [`TcpStream`].
-    pub fn close(&mut self) -> io::Result<()> {
+    pub fn close(&mut self) -> Result<()> {
         let mut tcp = self.tcp.write().unwrap();
         tcp.flush()?;
-        tcp.shutdown(Shutdown::Both)
+        Ok(tcp.shutdown(Shutdown::Both)?)
     }
 
     /// Returns the socket address of the remote peer of this TCP connection.
-    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.tcp.read().unwrap().peer_addr()
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.tcp.read().unwrap().peer_addr()?)
     }
 
     /// Returns the socket address of the local half of this TCP connection.
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.tcp.read().unwrap().local_addr()
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.tcp.read().unwrap().local_addr()?)
     }
 }
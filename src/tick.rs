@@ -0,0 +1,245 @@
+//! A fixed tick rate network loop.
+//!
+//! Instead of writing the same game loop for every project (`clear_msgs()`, `recv_msgs()`,
+//! `handle_disconnects()`, `handle_new_cons()`, update, `send_queued()`, sleep), implement
+//! [`ServerHandler`] or [`ClientHandler`], and let a [`TickRunner`] drive the server or client at
+//! a fixed tick rate.
+//!
+//! If a tick takes too long, the following ticks are run back to back to catch up, up to
+//! [`TickRunner::max_catch_up()`] ticks at once. When the runner falls further behind than that,
+//! the missed ticks are skipped.
+//!
+//! The runner sets the tick of the server or client before running each tick, so with
+//! [`Config::tick_header`](crate::net::Config::tick_header) every message is stamped with the tick
+//! it was sent on.
+
+use crate::net::{CId, Status};
+use crate::{Client, Server};
+use log::warn;
+use std::any::Any;
+use std::ops::ControlFlow;
+use std::time::{Duration, Instant};
+
+/// A tick number. The first tick is tick `0`.
+pub type Tick = u32;
+
+/// The callbacks of a [`Server`] driven by a [`TickRunner`].
+pub trait ServerHandler {
+    /// The connection message type. Needs to match the `C` passed into `MsgTable::build()`.
+    type Connection: Any + Send + Sync;
+    /// The response message type. Needs to match the `R` passed into `MsgTable::build()`.
+    type Response: Any + Send + Sync;
+
+    /// Called for every new connection. Returns `(should_accept, response_msg)`.
+    fn on_connect(&mut self, cid: CId, con_msg: Self::Connection) -> (bool, Self::Response);
+
+    /// Called for every connection that disconnected.
+    fn on_disconnect(&mut self, _cid: CId, _status: Status) {}
+
+    /// Called every tick after the messages were received, to handle them.
+    fn on_messages(&mut self, _server: &mut Server, _tick: Tick) {}
+
+    /// Called every tick, after [`on_messages()`](Self::on_messages), to update the game.
+    ///
+    /// Returning [`ControlFlow::Break`] stops [`TickRunner::run_server()`].
+    fn on_tick(&mut self, server: &mut Server, tick: Tick) -> ControlFlow<()>;
+}
+
+/// The callbacks of a [`Client`] driven by a [`TickRunner`].
+pub trait ClientHandler {
+    /// Called when the connection closes, for whatever reason. No more ticks are run after this.
+    fn on_disconnect(&mut self, _status: &Status) {}
+
+    /// Called every tick after the messages were received, to handle them.
+    fn on_messages(&mut self, _client: &mut Client, _tick: Tick) {}
+
+    /// Called every tick, after [`on_messages()`](Self::on_messages), to update the game.
+    ///
+    /// Returning [`ControlFlow::Break`] stops [`TickRunner::run_client()`].
+    fn on_tick(&mut self, client: &mut Client, tick: Tick) -> ControlFlow<()>;
+}
+
+/// Drives a [`Server`] or [`Client`] at a fixed tick rate.
+///
+/// See the [module level docs](self) for more.
+#[derive(Copy, Clone, Debug)]
+pub struct TickRunner {
+    tick_duration: Duration,
+    max_catch_up: u32,
+    /// The next tick to run.
+    tick: Tick,
+    /// When the next tick is due.
+    next_tick: Option<Instant>,
+}
+
+impl TickRunner {
+    /// Creates a new [`TickRunner`] that runs `tick_rate` ticks per second.
+    ///
+    /// ### Panics
+    /// Panics if `tick_rate` is `0`.
+    pub fn new(tick_rate: u32) -> Self {
+        assert_ne!(tick_rate, 0, "The tick rate must be greater than 0.");
+        TickRunner {
+            tick_duration: Duration::from_secs(1) / tick_rate,
+            max_catch_up: 5,
+            tick: 0,
+            next_tick: None,
+        }
+    }
+
+    /// Gets the duration of a tick.
+    pub fn tick_duration(&self) -> Duration {
+        self.tick_duration
+    }
+
+    /// Gets the maximum number of ticks that are run back to back to catch up.
+    pub fn max_catch_up(&self) -> u32 {
+        self.max_catch_up
+    }
+
+    /// Sets the maximum number of ticks that are run back to back to catch up.
+    ///
+    /// The default is `5`. A value of `1` disables catching up.
+    pub fn set_max_catch_up(&mut self, max_catch_up: u32) {
+        self.max_catch_up = max_catch_up.max(1);
+    }
+
+    /// Gets the number of the next tick to run. This is also the number of ticks run so far.
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    /// Sleeps until the next tick is due, and returns the number of ticks to run.
+    fn wait(&mut self) -> u32 {
+        if let Some(next_tick) = self.next_tick {
+            let now = Instant::now();
+            if next_tick > now {
+                std::thread::sleep(next_tick - now);
+            }
+        }
+        self.due_ticks(Instant::now())
+    }
+
+    /// Gets the number of ticks that are due at `now`, and schedules the next tick after them.
+    fn due_ticks(&mut self, now: Instant) -> u32 {
+        let next_tick = match self.next_tick {
+            Some(next_tick) if next_tick <= now => next_tick,
+            Some(_) => return 0,
+            // The first tick is due right away.
+            None => now,
+        };
+
+        let behind = (now - next_tick).as_nanos() / self.tick_duration.as_nanos();
+        let due = behind as u32 + 1;
+        if due > self.max_catch_up {
+            warn!(
+                "Tick runner is {} ticks behind. Skipping {} ticks.",
+                due,
+                due - self.max_catch_up
+            );
+            self.next_tick = Some(now + self.tick_duration);
+            return self.max_catch_up;
+        }
+
+        self.next_tick = Some(next_tick + self.tick_duration * due);
+        due
+    }
+
+    /// Waits for the next tick, then runs all due ticks on `server`.
+    ///
+    /// Every tick receives the messages, handles disconnects and new connections, calls
+    /// [`ServerHandler::on_messages()`] and [`ServerHandler::on_tick()`], then sends the queued
+    /// messages.
+    pub fn step_server<H: ServerHandler>(
+        &mut self,
+        server: &mut Server,
+        handler: &mut H,
+    ) -> ControlFlow<()> {
+        for _ in 0..self.wait() {
+            server.clear_msgs();
+            server.recv_msgs();
+            server.handle_disconnects(|cid, status| handler.on_disconnect(cid, status));
+            server.handle_new_cons(|cid, con_msg| handler.on_connect(cid, con_msg));
+
+            let tick = self.tick;
+            self.tick += 1;
+            server.set_tick(tick);
+            handler.on_messages(server, tick);
+            let flow = handler.on_tick(server, tick);
+            if let Err(e) = server.send_queued() {
+                warn!("Failed to send queued messages. {}", e);
+            }
+            flow?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Runs `server` until [`ServerHandler::on_tick()`] returns [`ControlFlow::Break`].
+    pub fn run_server<H: ServerHandler>(&mut self, server: &mut Server, handler: &mut H) {
+        while self.step_server(server, handler).is_continue() {}
+    }
+
+    /// Waits for the next tick, then runs all due ticks on `client`.
+    ///
+    /// Every tick receives the messages, calls [`ClientHandler::on_messages()`] and
+    /// [`ClientHandler::on_tick()`], then sends the queued messages. Breaks if the connection
+    /// closes.
+    pub fn step_client<H: ClientHandler>(
+        &mut self,
+        client: &mut Client,
+        handler: &mut H,
+    ) -> ControlFlow<()> {
+        for _ in 0..self.wait() {
+            client.clear_msgs();
+            client.recv_msgs();
+            if !client.open() {
+                handler.on_disconnect(client.status());
+                return ControlFlow::Break(());
+            }
+
+            let tick = self.tick;
+            self.tick += 1;
+            client.set_tick(tick);
+            handler.on_messages(client, tick);
+            let flow = handler.on_tick(client, tick);
+            if let Err(e) = client.send_queued() {
+                warn!("Failed to send queued messages. {}", e);
+            }
+            flow?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Runs `client` until [`ClientHandler::on_tick()`] returns [`ControlFlow::Break`], or the
+    /// connection closes.
+    pub fn run_client<H: ClientHandler>(&mut self, client: &mut Client, handler: &mut H) {
+        while self.step_client(client, handler).is_continue() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tick::TickRunner;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn catch_up() {
+        let mut runner = TickRunner::new(10);
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+
+        // The first tick is due right away.
+        assert_eq!(runner.due_ticks(start), 1);
+        assert_eq!(runner.due_ticks(start + ms(50)), 0);
+        assert_eq!(runner.due_ticks(start + ms(100)), 1);
+        // Two ticks behind, plus the current one.
+        assert_eq!(runner.due_ticks(start + ms(420)), 3);
+        assert_eq!(runner.due_ticks(start + ms(450)), 0);
+        assert_eq!(runner.due_ticks(start + ms(500)), 1);
+
+        // Too far behind.
+        assert_eq!(runner.due_ticks(start + ms(2000)), 5);
+        assert_eq!(runner.due_ticks(start + ms(2050)), 0);
+        assert_eq!(runner.due_ticks(start + ms(2100)), 1);
+    }
+}
@@ -1,9 +1,11 @@
-use crate::header::{UdpHeader, TCP_HEADER_LEN, UDP_HEADER_LEN};
-use crate::net::MAX_SAFE_MESSAGE_SIZE;
-use crate::MId;
+use crate::header::{
+    UdpHeader, TCP_HEADER_LEN, UDP_BATCH_ENTRY_LEN, UDP_BATCH_MID, UDP_HEADER_LEN,
+};
+use crate::net::{TcpHeader, MAX_SAFE_MESSAGE_SIZE};
+use crate::{Error, MId, Result};
 use log::{debug, error, trace};
 use std::io;
-use std::io::{Error, ErrorKind};
+use std::io::ErrorKind;
 use std::net::{SocketAddr, UdpSocket};
 
 /// A type wrapping a [`UdpSocket`].
@@ -12,17 +14,25 @@ use std::net::{SocketAddr, UdpSocket};
 pub struct UdpCon {
     /// Used for receiving only. This way send calls can take immutable refs.
     buff: Vec<u8>,
+    /// The messages of a batched datagram in `buff` that have not been yielded yet.
+    batch: Option<Batch>,
     udp: UdpSocket,
 }
 
+/// The position of the remaining messages of a batched datagram.
+struct Batch {
+    from: SocketAddr,
+    time: u32,
+    /// The start of the next message in the buffer.
+    pos: usize,
+    /// The end of the datagram in the buffer.
+    end: usize,
+}
+
 impl UdpCon {
     /// Creates a new [`UdpCon`] by creating a new [`UdpSocket`] that connects to `peer`. Sets the
     /// socket to non-blocking.
-    pub fn new(
-        local: SocketAddr,
-        peer: Option<SocketAddr>,
-        max_msg_size: usize,
-    ) -> io::Result<Self> {
+    pub fn new(local: SocketAddr, peer: Option<SocketAddr>, max_msg_size: usize) -> Result<Self> {
         let udp = UdpSocket::bind(local)?;
         if let Some(peer) = peer {
             udp.connect(peer)?;
@@ -30,6 +40,7 @@ impl UdpCon {
         udp.set_nonblocking(true)?;
         Ok(UdpCon {
             buff: vec![0; max_msg_size + UDP_HEADER_LEN],
+            batch: None,
             udp,
         })
     }
@@ -44,16 +55,16 @@ impl UdpCon {
         self.buff.len() - TCP_HEADER_LEN
     }
 
-    pub fn send_to(&self, addr: SocketAddr, mid: MId, payload: &[u8]) -> io::Result<()> {
+    /// Gets the maximum size of a batched datagram.
+    fn max_batch_size(&self) -> usize {
+        self.buff_size().min(MAX_SAFE_MESSAGE_SIZE)
+    }
+
+    pub fn send_to(&self, addr: SocketAddr, mid: MId, payload: &[u8]) -> Result<()> {
         let buff = self.send_shared(mid, payload)?;
         let len = buff.len();
 
-        trace!(
-            "UDP: Sending message with MId: {}, len: {} to {}.",
-            mid,
-            len,
-            addr
-        );
+        trace!("UDP: Sending message, len: {} to {}.", len, addr);
         let n = self.udp.send_to(&buff[..len], addr)?;
 
         // Make sure it sent correctly.
@@ -68,11 +79,11 @@ impl UdpCon {
         Ok(())
     }
 
-    pub fn send(&self, mid: MId, payload: &[u8]) -> io::Result<()> {
+    pub fn send(&self, mid: MId, payload: &[u8]) -> Result<()> {
         let buff = self.send_shared(mid, payload)?;
         let len = buff.len();
 
-        trace!("UDP: Sending message with MId: {}, len: {}.", mid, len);
+        trace!("UDP: Sending message, len: {}.", len);
         let n = self.udp.send(&buff[..len])?;
 
         // Make sure it sent correctly.
@@ -87,27 +98,67 @@ impl UdpCon {
         Ok(())
     }
 
+    /// Sends all the messages in `msgs` to `addr`, packed into as few datagrams as possible.
+    ///
+    /// Messages that are too big to share a datagram are sent on their own.
+    pub fn send_batch_to(&self, addr: SocketAddr, msgs: &[(MId, Vec<u8>)]) -> Result<()> {
+        for buff in self.batch_shared(msgs) {
+            let len = buff.len();
+            trace!("UDP: Sending batch, len: {} to {}.", len, addr);
+            let n = self.udp.send_to(&buff, addr)?;
+
+            if n != len {
+                error!(
+                    "UDP: Couldn't send all the bytes of a batch. \
+                    Wanted to send {} but could only send {}. This will likely \
+                    cause issues on the other side.",
+                    len, n
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends all the messages in `msgs` to the peer, packed into as few datagrams as possible.
+    ///
+    /// Messages that are too big to share a datagram are sent on their own.
+    pub fn send_batch(&self, msgs: &[(MId, Vec<u8>)]) -> Result<()> {
+        for buff in self.batch_shared(msgs) {
+            let len = buff.len();
+            trace!("UDP: Sending batch, len: {}.", len);
+            let n = self.udp.send(&buff)?;
+
+            if n != len {
+                error!(
+                    "UDP: Couldn't send all the bytes of a batch. \
+                    Wanted to send {} but could only send {}. This will likely \
+                    cause issues on the other side.",
+                    len, n
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// The shared code for sending a message. Produces a buffer given the payload
-    fn send_shared(&self, mid: MId, payload: &[u8]) -> io::Result<Vec<u8>> {
+    fn send_shared(&self, mid: MId, payload: &[u8]) -> Result<Vec<u8>> {
         let total_len = payload.len() + UDP_HEADER_LEN;
         let mut buff = vec![0; total_len];
         // Check if the message is valid, and should be sent.
         if total_len > self.buff_size() {
-            let e_msg = format!(
-                "UDP: Outgoing message size is greater than the maximum message size ({}). \
-                MId: {}, size: {}. Discarding message.",
-                self.max_msg_size(),
+            return Err(Error::MessageTooLarge {
                 mid,
-                total_len
-            );
-            return Err(Error::new(ErrorKind::InvalidData, e_msg));
+                name: None,
+                size: payload.len(),
+                max: self.max_msg_size(),
+            });
         }
 
         if total_len > MAX_SAFE_MESSAGE_SIZE {
             debug!(
-                "UDP: Outgoing message size is greater than the maximum SAFE message size.\
-                MId: {}, size: {}. Sending message anyway.",
-                mid, total_len
+                "UDP: Outgoing message size is greater than the maximum SAFE message size. \
+                Size: {}. Sending message anyway.",
+                total_len
             );
         }
         // Message can be sent!
@@ -124,51 +175,156 @@ impl UdpCon {
         Ok(buff)
     }
 
-    pub fn recv(&mut self) -> io::Result<(MId, u32, &[u8])> {
+    /// The shared code for sending a batch of messages. Packs the messages into datagrams.
+    ///
+    /// Messages that can not be sent are logged and skipped.
+    fn batch_shared(&self, msgs: &[(MId, Vec<u8>)]) -> Vec<Vec<u8>> {
+        let batch_header = UdpHeader::new(UDP_BATCH_MID).to_be_bytes();
+        let max_batch_size = self.max_batch_size();
+
+        let mut datagrams = vec![];
+        let mut current = batch_header.to_vec();
+        for (mid, payload) in msgs {
+            let entry_len = payload.len() + UDP_BATCH_ENTRY_LEN;
+
+            // Too big to share a datagram.
+            if entry_len + UDP_HEADER_LEN > max_batch_size {
+                match self.send_shared(*mid, payload) {
+                    Ok(buff) => datagrams.push(buff),
+                    Err(e) => error!("{}", e),
+                }
+                continue;
+            }
+
+            if current.len() + entry_len > max_batch_size {
+                datagrams.push(current);
+                current = batch_header.to_vec();
+            }
+            current.extend_from_slice(&TcpHeader::new(*mid, payload.len()).to_be_bytes());
+            current.extend_from_slice(payload);
+        }
+
+        if current.len() > UDP_HEADER_LEN {
+            datagrams.push(current);
+        }
+        datagrams
+    }
+
+    pub fn recv(&mut self) -> Result<(MId, u32, &[u8])> {
+        if self.batch.is_some() {
+            let (_from, mid, time, bytes) = self.next_batched()?;
+            return Ok((mid, time, bytes));
+        }
+
         let n = self.udp.recv(&mut self.buff)?;
-        let (mid, time, bytes) = self.recv_shared(n)?;
-        trace!("UDP: Received msg of MId {}, len {}", mid, bytes.len());
-        Ok((mid, time, bytes))
+        let header = self.recv_shared(n)?;
+        if header.mid == UDP_BATCH_MID {
+            let from = self.udp.peer_addr()?;
+            self.start_batch(from, header.time, n);
+            let (_from, mid, time, bytes) = self.next_batched()?;
+            return Ok((mid, time, bytes));
+        }
+
+        let bytes = &self.buff[UDP_HEADER_LEN..n];
+        trace!("UDP: Received msg, len {}", bytes.len());
+        Ok((header.mid, header.time, bytes))
     }
 
-    pub fn recv_from(&mut self) -> io::Result<(SocketAddr, MId, u32, &[u8])> {
+    pub fn recv_from(&mut self) -> Result<(SocketAddr, MId, u32, &[u8])> {
+        if self.batch.is_some() {
+            return self.next_batched();
+        }
+
         let (n, from) = self.udp.recv_from(&mut self.buff)?;
-        let (mid, time, bytes) = self.recv_shared(n)?;
-        trace!(
-            "UDP: Received msg of MId {}, len {}, from {}",
-            mid,
-            bytes.len(),
-            from
-        );
-        Ok((from, mid, time, bytes))
+        let header = self.recv_shared(n)?;
+        if header.mid == UDP_BATCH_MID {
+            self.start_batch(from, header.time, n);
+            return self.next_batched();
+        }
+
+        let bytes = &self.buff[UDP_HEADER_LEN..n];
+        trace!("UDP: Received msg, len {}, from {}", bytes.len(), from);
+        Ok((from, header.mid, header.time, bytes))
     }
 
-    fn recv_shared(&mut self, n: usize) -> io::Result<(MId, u32, &[u8])> {
+    fn recv_shared(&mut self, n: usize) -> Result<UdpHeader> {
         // Data should already be received.
         if n == 0 {
-            return Err(Error::new(
+            return Err(Error::Io(io::Error::new(
                 ErrorKind::NotConnected,
-                "UDP: The connection was dropped.".to_owned(),
+                "UDP: The connection was dropped.",
+            )));
+        }
+        if n < UDP_HEADER_LEN {
+            return Err(Error::deserialize(
+                "UDP: Received a datagram that is too small to contain a header.",
+            ));
+        }
+
+        Ok(UdpHeader::from_be_bytes(&self.buff[..UDP_HEADER_LEN]))
+    }
+
+    /// Starts yielding the messages of the batched datagram of length `n` in the buffer.
+    fn start_batch(&mut self, from: SocketAddr, time: u32, n: usize) {
+        trace!("UDP: Received batch, len {}, from {}", n, from);
+        self.batch = Some(Batch {
+            from,
+            time,
+            pos: UDP_HEADER_LEN,
+            end: n,
+        });
+    }
+
+    /// Yields the next message of the current batched datagram.
+    fn next_batched(&mut self) -> Result<(SocketAddr, MId, u32, &[u8])> {
+        let batch = self
+            .batch
+            .as_mut()
+            .expect("There is no batch to read from.");
+        let entry_start = batch.pos + UDP_BATCH_ENTRY_LEN;
+        if entry_start > batch.end {
+            self.batch = None;
+            return Err(Error::deserialize(
+                "UDP: A batched datagram ended in the middle of a message header.",
             ));
         }
 
-        let header = UdpHeader::from_be_bytes(&self.buff[..UDP_HEADER_LEN]);
+        let header = TcpHeader::from_be_bytes(&self.buff[batch.pos..entry_start]);
+        let entry_end = entry_start + header.len;
+        if entry_end > batch.end {
+            self.batch = None;
+            return Err(Error::deserialize(
+                "UDP: A batched datagram ended in the middle of a message.",
+            ));
+        }
+
+        let (from, time) = (batch.from, batch.time);
+        if entry_end == batch.end {
+            self.batch = None;
+        } else {
+            batch.pos = entry_end;
+        }
 
-        Ok((header.mid, header.time, &self.buff[UDP_HEADER_LEN..n]))
+        trace!(
+            "UDP: Received batched msg, len {}, from {}",
+            header.len,
+            from
+        );
+        Ok((from, header.mid, time, &self.buff[entry_start..entry_end]))
     }
 
     /// Moves the internal [`UdpSocket`] into or out of nonblocking mode.
-    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
-        self.udp.set_nonblocking(nonblocking)
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        Ok(self.udp.set_nonblocking(nonblocking)?)
     }
 
     /// Returns the socket address of the remote peer of this UDP connection.
-    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.udp.peer_addr()
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.udp.peer_addr()?)
     }
 
     /// Returns the socket address of the local half of this UDP connection.
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.udp.local_addr()
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.udp.local_addr()?)
     }
 }
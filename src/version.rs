@@ -0,0 +1,282 @@
+//! Versioned messages, for peers that are not updated at the same time.
+//!
+//! A message type can be registered with a [`Versioned`], which holds its current version number,
+//! and the conversions from and to its older versions. Types that are registered without one
+//! have version 0.
+//!
+//! When connecting, the peers send each other the version of every message type (see the
+//! [`negotiate`](crate::negotiate) module). For each type, both pick the lower of the two
+//! versions. From then on, both sides send and receive that version of the type. The side with
+//! the newer version converts the messages with:
+//! - The upgrade function, when receiving a message of the older version.
+//! - The downgrade function, when sending a message to a peer with the older version.
+//!
+//! An older version is only supported if it has both conversions, as messages are sent both ways.
+//! If the newer side doesn't support the older version, the connection is refused, unless the type
+//! is optional, in which case it is disabled.
+//!
+//! ```
+//! # use carrier_pigeon::version::Versioned;
+//! # use carrier_pigeon::{MsgOptions, MsgTable, Transport};
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Serialize, Deserialize)]
+//! struct ChatV1 {
+//!     text: String,
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Chat {
+//!     text: String,
+//!     color: u32,
+//! }
+//!
+//! let mut table = MsgTable::new();
+//! table
+//!     .register_versioned::<Chat>(
+//!         Transport::TCP,
+//!         MsgOptions::default(),
+//!         Versioned::new(2)
+//!             .upgrade_from(1, |old: ChatV1| Chat { text: old.text, color: 0 })
+//!             .downgrade_to(1, |new: &Chat| ChatV1 { text: new.text.clone() }),
+//!     )
+//!     .unwrap();
+//! ```
+//!
+//! The older versions must be registered at the same position (or with the same identifier for a
+//! [`SortedMsgTable`](crate::SortedMsgTable)) as the current version.
+//!
+//! The connection, response and disconnect types can not be versioned, as the connection message
+//! is sent before the versions are known. They are always sent and received as the types that
+//! were passed to `build()`, so changing them breaks the connection with peers that have the old
+//! types.
+
+use crate::message_table::{MsgRegError, MsgTableParts};
+use crate::{Error, MId, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The version number of a message type.
+pub type Version = u16;
+
+/// Deserializes an older version of a message type, and upgrades it to the current version.
+pub type UpgradeFn = Arc<dyn Fn(&[u8]) -> Result<Box<dyn Any + Send + Sync>> + Send + Sync>;
+/// Downgrades a message to an older version of its type, and serializes it.
+pub type DowngradeFn = Arc<dyn Fn(&(dyn Any + Send + Sync)) -> Result<Vec<u8>> + Send + Sync>;
+
+/// The current version of a message type `T`, and the conversions from and to its older versions.
+///
+/// See the [module level docs](self) for more.
+pub struct Versioned<T> {
+    versions: MsgVersions,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T: Any + Send + Sync> Versioned<T> {
+    /// Creates a new [`Versioned`] with the current version `version`, and no older versions.
+    pub fn new(version: Version) -> Self {
+        Versioned {
+            versions: MsgVersions {
+                current: version,
+                older: vec![],
+            },
+            _t: PhantomData,
+        }
+    }
+
+    /// Adds the upgrade function from version `version`, where the type was `Old`.
+    ///
+    /// This allows receiving `T` from peers with that version. The version is only supported
+    /// once [`downgrade_to()`](Self::downgrade_to) is added for it as well. Registering fails if
+    /// `version` is not older than the current version.
+    pub fn upgrade_from<Old>(mut self, version: Version, upgrade: fn(Old) -> T) -> Self
+    where
+        Old: DeserializeOwned + 'static,
+    {
+        let upgrade: UpgradeFn = Arc::new(move |bytes| {
+            let old = bincode::deserialize::<Old>(bytes).map_err(|e| Error::Deserialize(e))?;
+            Ok(Box::new(upgrade(old)))
+        });
+        self.versions.older_mut(version).upgrade = Some(upgrade);
+        self
+    }
+
+    /// Adds the downgrade function to version `version`, where the type was `Old`.
+    ///
+    /// This allows sending `T` to peers with that version. The version is only supported once
+    /// [`upgrade_from()`](Self::upgrade_from) is added for it as well. Registering fails if
+    /// `version` is not older than the current version.
+    pub fn downgrade_to<Old>(mut self, version: Version, downgrade: fn(&T) -> Old) -> Self
+    where
+        Old: Serialize + 'static,
+    {
+        let downgrade: DowngradeFn = Arc::new(move |m| {
+            let new = m
+                .downcast_ref::<T>()
+                .ok_or(Error::WrongType(std::any::type_name::<T>()))?;
+            bincode::serialize(&downgrade(new)).map_err(|e| Error::Serialize(e))
+        });
+        self.versions.older_mut(version).downgrade = Some(downgrade);
+        self
+    }
+
+    /// Gets the current version.
+    pub fn version(&self) -> Version {
+        self.versions.current
+    }
+
+    /// Turns this into the [`MsgVersions`] that are stored in the [`MsgTableParts`].
+    ///
+    /// Returns an error if one of the older versions is not older than the current version.
+    pub(crate) fn into_versions(self) -> std::result::Result<MsgVersions, MsgRegError> {
+        let current = self.versions.current;
+        match self.versions.older.iter().find(|o| o.version >= current) {
+            Some(o) => Err(MsgRegError::VersionNotOlder {
+                version: o.version,
+                current,
+            }),
+            None => Ok(self.versions),
+        }
+    }
+}
+
+/// The versions of a registered message type.
+#[derive(Clone, Default)]
+pub struct MsgVersions {
+    /// The current version.
+    pub current: Version,
+    /// The conversions from and to the older versions.
+    pub older: Vec<OlderVersion>,
+}
+
+impl Debug for MsgVersions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MsgVersions")
+            .field("current", &self.current)
+            .field(
+                "older",
+                &self.older.iter().map(|o| o.version).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// The conversions from and to an older version of a message type.
+#[derive(Clone)]
+pub struct OlderVersion {
+    /// The older version.
+    pub version: Version,
+    /// The conversion for receiving messages of this version.
+    pub upgrade: Option<UpgradeFn>,
+    /// The conversion for sending messages to peers with this version.
+    pub downgrade: Option<DowngradeFn>,
+}
+
+impl OlderVersion {
+    /// Returns whether messages of this version can be both sent and received.
+    fn is_complete(&self) -> bool {
+        self.upgrade.is_some() && self.downgrade.is_some()
+    }
+}
+
+impl MsgVersions {
+    /// Gets the conversions of the older version `version`, adding them if they don't exist.
+    fn older_mut(&mut self, version: Version) -> &mut OlderVersion {
+        match self.older.iter().position(|o| o.version == version) {
+            Some(idx) => &mut self.older[idx],
+            None => {
+                self.older.push(OlderVersion {
+                    version,
+                    upgrade: None,
+                    downgrade: None,
+                });
+                self.older.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Gets the conversions of the older version `version`.
+    fn older(&self, version: Version) -> Option<&OlderVersion> {
+        self.older.iter().find(|o| o.version == version)
+    }
+
+    /// Returns whether messages of version `version` can be sent and received.
+    ///
+    /// An older version needs both an upgrade and a downgrade function.
+    pub fn supports(&self, version: Version) -> bool {
+        version == self.current || self.older(version).is_some_and(OlderVersion::is_complete)
+    }
+
+    /// Gets the older versions that are [supported](Self::supports).
+    pub(crate) fn supported_older(&self) -> impl Iterator<Item = Version> + '_ {
+        self.older
+            .iter()
+            .filter(|o| o.is_complete())
+            .map(|o| o.version)
+    }
+}
+
+impl MsgTableParts {
+    /// Serializes `msg` with [`MId`] `mid` as version `version` of its type.
+    pub(crate) fn ser_version(
+        &self,
+        mid: MId,
+        version: Version,
+        msg: &(dyn Any + Send + Sync),
+    ) -> Result<Vec<u8>> {
+        let versions = &self.versions[mid];
+        let result = if version == versions.current {
+            (self.ser[mid])(msg)
+        } else {
+            match versions.older(version).and_then(|o| o.downgrade.as_ref()) {
+                Some(downgrade) => downgrade(msg),
+                None => Err(Error::IncompatibleVersion {
+                    name: self.names[mid],
+                    version,
+                }),
+            }
+        };
+        self.name_errors(mid, result)
+    }
+
+    /// Deserializes `bytes` with [`MId`] `mid` as version `version` of its type.
+    pub(crate) fn deser_version(
+        &self,
+        mid: MId,
+        version: Version,
+        bytes: &[u8],
+    ) -> Result<Box<dyn Any + Send + Sync>> {
+        let versions = &self.versions[mid];
+        let result = if version == versions.current {
+            (self.deser[mid])(bytes)
+        } else {
+            match versions.older(version).and_then(|o| o.upgrade.as_ref()) {
+                Some(upgrade) => upgrade(bytes),
+                None => Err(Error::IncompatibleVersion {
+                    name: self.names[mid],
+                    version,
+                }),
+            }
+        };
+        self.name_errors(mid, result)
+    }
+
+    /// Adds the name of the message type with [`MId`] `mid` to (de)serialization errors, so that
+    /// they say which type failed.
+    fn name_errors<T>(&self, mid: MId, result: Result<T>) -> Result<T> {
+        let name = self.names[mid];
+        result.map_err(|e| match e {
+            Error::Serialize(e) => Error::Serialize(format!("{}: {}", name, e).into()),
+            Error::Deserialize(e) => Error::deserialize(format!("{}: {}", name, e)),
+            e => e,
+        })
+    }
+
+    /// Gets the current version of every message type, in [`MId`] order.
+    pub(crate) fn current_versions(&self) -> Vec<Version> {
+        self.versions.iter().map(|v| v.current).collect()
+    }
+}
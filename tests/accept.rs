@@ -0,0 +1,119 @@
+//! Tests the [`AcceptPolicy`].
+use crate::helper::test_messages::{get_table_parts, Connection, Response};
+use crate::helper::ADDR_LOCAL;
+use carrier_pigeon::accept::AcceptPolicy;
+use carrier_pigeon::net::Config;
+use carrier_pigeon::rate_limit::RateLimit;
+use carrier_pigeon::{Client, Error, PendingClient, Result, Server};
+use simple_logger::SimpleLogger;
+use std::net::TcpStream;
+use std::time::Duration;
+
+mod helper;
+
+/// Creates a server with the accept policy `policy`.
+fn create_server(policy: AcceptPolicy) -> Server {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let mut server = Server::new(ADDR_LOCAL, get_table_parts(), Config::default()).unwrap();
+    server.set_accept_policy(policy);
+    server
+}
+
+/// Starts connecting a client to `server`.
+fn connect(server: &Server) -> PendingClient {
+    Client::new(
+        server.listen_addr(),
+        get_table_parts(),
+        Config::default(),
+        Connection::new("John"),
+    )
+}
+
+/// Handles the new connections on `server` until `client` is done connecting.
+fn finish(server: &mut Server, client: PendingClient) -> Result<(Client, Response)> {
+    while !client.done() {
+        server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    client.block::<Response>()
+}
+
+#[test]
+fn banned() {
+    let mut server = create_server(AcceptPolicy {
+        banned: vec!["127.0.0.0/8".parse().unwrap()],
+        ..AcceptPolicy::default()
+    });
+
+    let client = connect(&server);
+    assert!(matches!(
+        finish(&mut server, client),
+        Err(Error::HandshakeRejected)
+    ));
+    assert_eq!(server.rejected_attempts(), 1);
+    assert_eq!(server.cids().count(), 0);
+}
+
+#[test]
+fn allowed() {
+    let mut server = create_server(AcceptPolicy {
+        allowed: vec!["127.0.0.1".parse().unwrap()],
+        ..AcceptPolicy::default()
+    });
+
+    let client = connect(&server);
+    let (_client, resp) = finish(&mut server, client).unwrap();
+    assert_eq!(resp, Response::Accepted);
+    assert_eq!(server.rejected_attempts(), 0);
+}
+
+#[test]
+fn max_pending() {
+    let mut server = create_server(AcceptPolicy {
+        max_pending_per_ip: Some(1),
+        ..AcceptPolicy::default()
+    });
+
+    // A connection that never sends a connection message.
+    let _idle = TcpStream::connect(server.listen_addr()).unwrap();
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(
+        server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted)),
+        0
+    );
+
+    let client = connect(&server);
+    assert!(finish(&mut server, client).is_err());
+    assert_eq!(server.rejected_attempts(), 1);
+}
+
+#[test]
+fn throttle() {
+    let mut server = create_server(AcceptPolicy {
+        attempt_limit: Some(RateLimit::new(1, 1)),
+        ..AcceptPolicy::default()
+    });
+
+    let client = connect(&server);
+    assert!(finish(&mut server, client).is_ok());
+
+    let client = connect(&server);
+    assert!(finish(&mut server, client).is_err());
+    assert_eq!(server.rejected_attempts(), 1);
+}
+
+#[test]
+fn filter() {
+    let mut server = create_server(AcceptPolicy {
+        filter: Some(Box::new(|addr| !addr.ip().is_loopback())),
+        ..AcceptPolicy::default()
+    });
+
+    let client = connect(&server);
+    assert!(finish(&mut server, client).is_err());
+    assert_eq!(server.rejected_attempts(), 1);
+}
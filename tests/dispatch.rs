@@ -0,0 +1,49 @@
+//! Tests the [`Dispatcher`].
+use crate::helper::create_client_server_pair;
+use crate::helper::test_messages::{TcpMsg, UdpMsg};
+use carrier_pigeon::dispatch::Dispatcher;
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+#[test]
+fn dispatch() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (client, mut server) = create_client_server_pair();
+
+    for i in 0..3 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+        client.send(&UdpMsg::new(format!("UDP {}", i))).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 6);
+
+    let mut dispatcher = Dispatcher::<Vec<String>>::new();
+    dispatcher.on::<TcpMsg>(|log, msg| {
+        assert_eq!(msg.cid, 1);
+        log.push(msg.msg.clone());
+    });
+    assert!(dispatcher.is_handled::<TcpMsg>());
+    assert!(!dispatcher.is_handled::<UdpMsg>());
+
+    // Without a catch-all, the UDP messages are not handled.
+    let mut log = vec![];
+    assert_eq!(dispatcher.dispatch_server(&server, &mut log), 3);
+    assert_eq!(log, vec!["TCP 0", "TCP 1", "TCP 2"]);
+
+    dispatcher.on_unhandled(|log, msg| {
+        log.push(msg.downcast_ref::<UdpMsg>().unwrap().msg.clone());
+    });
+    let mut log = vec![];
+    assert_eq!(dispatcher.dispatch_server(&server, &mut log), 6);
+    // TCP messages are received before UDP messages. UDP messages may arrive out of order.
+    assert_eq!(log[..3], ["TCP 0", "TCP 1", "TCP 2"]);
+    assert_eq!(log.len(), 6);
+}
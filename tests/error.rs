@@ -0,0 +1,53 @@
+//! Tests the variants of the [`Error`] type.
+use crate::helper::create_client_server_pair;
+use crate::helper::test_messages::{get_table_parts, Disconnect, TcpMsg, UdpMsg};
+use carrier_pigeon::Error;
+use simple_logger::SimpleLogger;
+use std::any::TypeId;
+
+mod helper;
+
+#[test]
+fn errors() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (mut client, mut server) = create_client_server_pair();
+
+    assert!(matches!(
+        client.send(&"Not registered"),
+        Err(Error::UnregisteredType(name)) if name == "&str"
+    ));
+    assert!(matches!(
+        server.send_to(2, &TcpMsg::new("No such connection")),
+        Err(Error::InvalidCId(2))
+    ));
+    assert!(matches!(
+        server.disconnect(&Disconnect::new("No such connection"), 2),
+        Err(Error::InvalidCId(2))
+    ));
+    assert!(matches!(
+        client.disconnect(&TcpMsg::new("Not the disconnect type")),
+        Err(Error::WrongType(_))
+    ));
+
+    let max = client.config().max_msg_size;
+    let udp_mid = get_table_parts().tid_map[&TypeId::of::<UdpMsg>()];
+    let too_big = UdpMsg::new("a".repeat(max));
+    match client.send(&too_big) {
+        Err(Error::MessageTooLarge {
+            mid,
+            name,
+            size,
+            max: m,
+        }) => {
+            assert_eq!(mid, udp_mid);
+            assert_eq!(name, Some(std::any::type_name::<UdpMsg>()));
+            assert!(size > max);
+            assert_eq!(m, max);
+        }
+        other => panic!("Expected MessageTooLarge, got {:?}", other),
+    }
+}
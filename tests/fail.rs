@@ -17,5 +17,8 @@ fn client_fail() {
     );
     let result = client.block::<Response>();
     println!("Error: {:?}", result);
-    assert_eq!(result.unwrap_err().kind(), ErrorKind::ConnectionRefused);
+    assert_eq!(
+        result.unwrap_err().io_kind(),
+        Some(ErrorKind::ConnectionRefused)
+    );
 }
@@ -0,0 +1,64 @@
+//! Tests sending and receiving with [`MsgHandle`]s.
+use crate::helper::create_client_server_pair_from;
+use crate::helper::test_messages::{Connection, Disconnect, Response, TcpMsg, UdpMsg};
+use carrier_pigeon::net::Config;
+use carrier_pigeon::{Error, MsgTable, SortedMsgTable, Transport};
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+#[test]
+fn send_recv_with() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let mut table = MsgTable::new();
+    let tcp = table.register::<TcpMsg>(Transport::TCP).unwrap();
+    let udp = table.register::<UdpMsg>(Transport::UDP).unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+    assert_eq!(parts.handle::<TcpMsg>(), Some(tcp));
+    assert_eq!(parts.handle::<UdpMsg>(), Some(udp));
+    assert_eq!(parts.handle::<String>(), None);
+
+    let (mut client, mut server) = create_client_server_pair_from(parts, Config::default());
+
+    client.send_with(tcp, &TcpMsg::new("TCP")).unwrap();
+    client.send_with(udp, &UdpMsg::new("UDP")).unwrap();
+    server.broadcast_with(tcp, &TcpMsg::new("Back")).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+    client.recv_msgs();
+
+    let msgs: Vec<_> = server.recv_with(tcp).map(|m| m.m.clone()).collect();
+    assert_eq!(msgs, vec![TcpMsg::new("TCP")]);
+    let msgs: Vec<_> = server.recv_with(udp).map(|m| m.m.clone()).collect();
+    assert_eq!(msgs, vec![UdpMsg::new("UDP")]);
+    let msgs: Vec<_> = client.recv_with(tcp).map(|m| m.m.clone()).collect();
+    assert_eq!(msgs, vec![TcpMsg::new("Back")]);
+
+    // A handle from a table where the MIds are different.
+    let mut other = MsgTable::new();
+    let other_udp = other.register::<UdpMsg>(Transport::UDP).unwrap();
+    assert!(matches!(
+        client.send_with(other_udp, &UdpMsg::new("UDP")),
+        Err(Error::WrongType(_))
+    ));
+    assert_eq!(client.recv_with(other_udp).count(), 0);
+    assert_eq!(server.recv_with(other_udp).count(), 0);
+}
+
+#[test]
+fn sorted_handle() {
+    let mut table = SortedMsgTable::new();
+    table.register::<UdpMsg>(Transport::UDP, "b").unwrap();
+    table.register::<TcpMsg>(Transport::TCP, "a").unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let tcp = parts.handle::<TcpMsg>().unwrap();
+    let udp = parts.handle::<UdpMsg>().unwrap();
+    assert_eq!(tcp.mid(), 3);
+    assert_eq!(udp.mid(), 4);
+}
@@ -3,7 +3,7 @@
 
 use crate::helper::test_messages::{get_table_parts, Connection, Disconnect, Response};
 use carrier_pigeon::net::Config;
-use carrier_pigeon::{Client, Server};
+use carrier_pigeon::{Client, MsgTableParts, Server};
 use log::debug;
 
 pub mod test_messages;
@@ -13,16 +13,26 @@ pub const ADDR_LOCAL: &str = "127.0.0.1:0";
 /// Creates a client and server that are connected to each other.
 /// Panics if any issues occur.
 pub fn create_client_server_pair() -> (Client, Server) {
-    let parts = get_table_parts();
+    create_client_server_pair_with(Config::default())
+}
 
+/// Creates a client and server that are connected to each other, both using `config`.
+/// Panics if any issues occur.
+pub fn create_client_server_pair_with(config: Config) -> (Client, Server) {
+    create_client_server_pair_from(get_table_parts(), config)
+}
+
+/// Creates a client and server that are connected to each other, both using `parts` and `config`.
+/// Panics if any issues occur.
+pub fn create_client_server_pair_from(parts: MsgTableParts, config: Config) -> (Client, Server) {
     debug!("Creating server.");
-    let mut server = Server::new(ADDR_LOCAL, parts.clone(), Config::default()).unwrap();
+    let mut server = Server::new(ADDR_LOCAL, parts.clone(), config).unwrap();
     let addr = server.listen_addr();
     debug!("Server created on addr: {}", addr);
 
     debug!("Creating client.");
     // Start client connection.
-    let client = Client::new(addr, parts, Config::default(), Connection::new("John"));
+    let client = Client::new(addr, parts, config, Connection::new("John"));
 
     // Spin until the connection is handled.
     // Normally this would be done in the game loop
@@ -1,6 +1,7 @@
 #![allow(unused)]
 //! Test messages for use in tests.
 
+use carrier_pigeon::rpc::Request;
 use carrier_pigeon::{MsgTable, MsgTableParts, Transport};
 use serde::{Deserialize, Serialize};
 
@@ -65,10 +66,22 @@ impl Response {
     }
 }
 
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+/// A test request for remote procedure calls.
+pub struct Ping(pub u32);
+impl Request for Ping {
+    type Response = Pong;
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+/// The response to [`Ping`].
+pub struct Pong(pub u32);
+
 /// Builds a table with all these test messages and returns it's parts.
 pub fn get_table_parts() -> MsgTableParts {
     let mut table = MsgTable::new();
     table.register::<TcpMsg>(Transport::TCP).unwrap();
     table.register::<UdpMsg>(Transport::UDP).unwrap();
+    table.register_rpc::<Ping>().unwrap();
     table.build::<Connection, Response, Disconnect>().unwrap()
 }
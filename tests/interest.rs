@@ -0,0 +1,42 @@
+//! Tests interest management.
+use crate::helper::create_client_server_pair;
+use crate::helper::test_messages::TcpMsg;
+use carrier_pigeon::interest::{Interest, Region};
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+#[test]
+fn broadcast_relevant() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (mut client, mut server) = create_client_server_pair();
+
+    // Everything is relevant by default.
+    assert!(server.is_relevant(1, [1000.0, 0.0, 0.0]));
+
+    let region = Region::sphere([0.0, 0.0, 0.0], 10.0);
+    server
+        .set_interest(1, Interest::Regions(vec![region]))
+        .unwrap();
+    // Not connected.
+    assert!(server.set_interest(2, Interest::All).is_err());
+
+    server
+        .broadcast_relevant([1.0, 2.0, 0.0], &TcpMsg::new("Near"))
+        .unwrap();
+    server
+        .broadcast_relevant([100.0, 2.0, 0.0], &TcpMsg::new("Far"))
+        .unwrap();
+
+    // Give the server enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(client.recv_msgs(), 1);
+    let msgs: Vec<_> = client.recv::<TcpMsg>().map(|m| m.m.clone()).collect();
+    assert_eq!(msgs, vec![TcpMsg::new("Near")]);
+}
@@ -0,0 +1,74 @@
+//! Tests [`Config::max_connections`] and [`Config::reserved_slots`].
+use crate::helper::test_messages::{get_table_parts, Connection, Response};
+use crate::helper::ADDR_LOCAL;
+use carrier_pigeon::net::Config;
+use carrier_pigeon::{Client, Server};
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+/// Connects a client with the user name `usr` to `server`, and returns the response.
+///
+/// Returns the number of times that the hook was called along with it. The client is pushed to
+/// `clients` to keep the connection open.
+fn connect(server: &mut Server, clients: &mut Vec<Client>, usr: &str) -> (Response, u32) {
+    let client = Client::new(
+        server.listen_addr(),
+        get_table_parts(),
+        Config::default(),
+        Connection::new(usr),
+    );
+
+    let mut calls = 0;
+    while !client.done() {
+        server.handle_new_cons(|_cid, _con_msg: Connection| {
+            calls += 1;
+            (true, Response::Accepted)
+        });
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let (client, resp) = client.block::<Response>().unwrap();
+    clients.push(client);
+    (resp, calls)
+}
+
+#[test]
+fn max_connections() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        max_connections: Some(2),
+        reserved_slots: 1,
+        ..Config::default()
+    };
+    let mut server = Server::new(ADDR_LOCAL, get_table_parts(), config).unwrap();
+    server
+        .set_full_response(Response::rejected("Server full"))
+        .unwrap();
+    server.set_reserved_filter(|con_msg: &Connection| con_msg.usr == "Admin");
+
+    let mut clients = vec![];
+    assert_eq!(
+        connect(&mut server, &mut clients, "John"),
+        (Response::Accepted, 1)
+    );
+    // Only the reserved slot is left.
+    assert_eq!(
+        connect(&mut server, &mut clients, "Jane"),
+        (Response::rejected("Server full"), 0)
+    );
+    assert_eq!(
+        connect(&mut server, &mut clients, "Admin"),
+        (Response::Accepted, 1)
+    );
+    // No slots are left.
+    assert_eq!(
+        connect(&mut server, &mut clients, "Admin"),
+        (Response::rejected("Server full"), 0)
+    );
+    assert_eq!(server.cids().count(), 2);
+}
@@ -2,9 +2,10 @@
 use crate::helper::test_messages::{Connection, Disconnect, Response, TcpMsg, UdpMsg};
 use carrier_pigeon::MsgRegError::{NonUniqueIdentifier, TypeAlreadyRegistered};
 use carrier_pigeon::Transport::{TCP, UDP};
-use carrier_pigeon::{MsgRegError, MsgTable, SortedMsgTable};
+use carrier_pigeon::{MsgOptions, MsgRegError, MsgTable, SortedMsgTable};
 use hashbrown::HashMap;
-use std::any::TypeId;
+use std::any::{type_name, TypeId};
+use std::time::Duration;
 
 mod helper;
 
@@ -98,6 +99,34 @@ fn parts_gen_sorted() {
     assert_eq!(parts.transports, parts2.transports);
 }
 
+/// Tests that [`MsgOptions`] end up in the [`MsgTableParts`].
+#[test]
+fn options() {
+    let options = MsgOptions::new(5, Some(Duration::from_millis(100)));
+
+    let mut table = MsgTable::new();
+    table.register::<TcpMsg>(TCP).unwrap();
+    table.register_with::<UdpMsg>(UDP, options).unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let mut sorted = SortedMsgTable::new();
+    sorted
+        .register_with::<UdpMsg>(UDP, "tests::UdpMsg", options)
+        .unwrap();
+    sorted.register::<TcpMsg>(TCP, "tests::TcpMsg").unwrap();
+    let sorted_parts = sorted.build::<Connection, Response, Disconnect>().unwrap();
+
+    let expected = vec![
+        MsgOptions::default(),
+        MsgOptions::default(),
+        MsgOptions::default(),
+        MsgOptions::default(),
+        options,
+    ];
+    assert_eq!(parts.options, expected);
+    assert_eq!(sorted_parts.options, expected);
+}
+
 /// Tests [`MsgTable`] joining.
 #[test]
 fn join() {
@@ -189,3 +218,41 @@ fn join_error() {
 
     assert_eq!(table1.join(&table2).unwrap_err(), NonUniqueIdentifier);
 }
+
+/// Tests the names and identifiers in the [`MsgTableParts`], and [`MsgTableParts::describe()`].
+#[test]
+fn describe() {
+    let mut table = SortedMsgTable::new();
+    table.register::<UdpMsg>(UDP, "test::udp").unwrap();
+    table.register::<TcpMsg>(TCP, "test::tcp").unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    assert_eq!(parts.name(3), Some(type_name::<TcpMsg>()));
+    assert_eq!(parts.identifier(3), Some("test::tcp"));
+    assert_eq!(parts.name(4), Some(type_name::<UdpMsg>()));
+    assert_eq!(parts.name(5), None);
+
+    let description = parts.describe();
+    let lines: Vec<_> = description.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(
+        lines[0],
+        format!(
+            "0: {} \"carrier-pigeon::connection\" TCP",
+            type_name::<Connection>()
+        )
+    );
+    assert_eq!(
+        lines[4],
+        format!("4: {} \"test::udp\" UDP", type_name::<UdpMsg>())
+    );
+
+    // Types registered with a MsgTable have no identifiers.
+    let mut table = MsgTable::new();
+    table.register::<TcpMsg>(TCP).unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+    assert_eq!(parts.identifier(3), None);
+    assert!(parts
+        .describe()
+        .ends_with(&format!("3: {} TCP\n", type_name::<TcpMsg>())));
+}
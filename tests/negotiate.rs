@@ -0,0 +1,162 @@
+//! Tests negotiating the message table between peers with different message types.
+use crate::helper::test_messages::{Connection, Disconnect, Ping, Pong, Response, TcpMsg};
+use crate::helper::ADDR_LOCAL;
+use carrier_pigeon::net::Config;
+use carrier_pigeon::{Client, Error, MsgTableParts, Result, Server, SortedMsgTable, Transport};
+use simple_logger::SimpleLogger;
+use std::any::type_name;
+use std::time::Duration;
+
+mod helper;
+
+/// Connects a client with `client_parts` to a server with `server_parts`.
+fn connect(server_parts: MsgTableParts, client_parts: MsgTableParts) -> Result<(Client, Server)> {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let mut server = Server::new(ADDR_LOCAL, server_parts, Config::default()).unwrap();
+    let client = Client::new(
+        server.listen_addr(),
+        client_parts,
+        Config::default(),
+        Connection::new("John"),
+    );
+
+    while !client.done() {
+        server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let (client, _resp) = client.block::<Response>()?;
+    Ok((client, server))
+}
+
+#[test]
+fn optional_types() {
+    // The server has the ping mod.
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    table
+        .register_optional::<Ping>(Transport::TCP, "ping_mod::ping")
+        .unwrap();
+    let server_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    // The client has a different mod, which comes first, so the MIds of the game types differ.
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    table
+        .register_optional::<Pong>(Transport::TCP, "another_mod::pong")
+        .unwrap();
+    let client_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+    assert_ne!(
+        server_parts.handle::<TcpMsg>(),
+        client_parts.handle::<TcpMsg>()
+    );
+
+    let (mut client, mut server) = connect(server_parts, client_parts).unwrap();
+    let cid = server.cids().next().unwrap();
+
+    let negotiated = server.negotiated(cid).unwrap();
+    assert_eq!(negotiated.missing_on_peer(), ["ping_mod::ping"]);
+    assert_eq!(negotiated.missing_locally(), ["another_mod::pong"]);
+    let negotiated = client.negotiated();
+    assert_eq!(negotiated.missing_on_peer(), ["another_mod::pong"]);
+    assert_eq!(negotiated.missing_locally(), ["ping_mod::ping"]);
+
+    // The disabled types can't be sent.
+    assert!(!server.is_enabled::<Ping>(cid));
+    assert!(matches!(
+        server.send_to(cid, &Ping(1)),
+        Err(Error::DisabledType(name)) if name == type_name::<Ping>()
+    ));
+    assert!(matches!(client.send(&Pong(1)), Err(Error::DisabledType(_))));
+    // Broadcasting skips the clients that don't have the type.
+    server.broadcast(&Ping(1)).unwrap();
+
+    // The shared types work both ways.
+    assert!(server.is_enabled::<TcpMsg>(cid));
+    client.send(&TcpMsg::new("Client")).unwrap();
+    server.send_to(cid, &TcpMsg::new("Server")).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+    client.recv_msgs();
+
+    let msgs: Vec<_> = server.recv::<TcpMsg>().map(|m| m.msg.clone()).collect();
+    assert_eq!(msgs, vec!["Client"]);
+    let msgs: Vec<_> = client.recv::<TcpMsg>().map(|m| m.msg.clone()).collect();
+    assert_eq!(msgs, vec!["Server"]);
+    assert!(client.open());
+}
+
+#[test]
+fn missing_type() {
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    table
+        .register::<Ping>(Transport::TCP, "game::ping")
+        .unwrap();
+    let server_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    let client_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    // The client doesn't have a type that is not optional. The server sends back its message
+    // types, so the client knows which one.
+    let result = connect(server_parts, client_parts);
+    assert!(matches!(result, Err(Error::MissingType(name)) if name == "game::ping"));
+}
+
+#[test]
+fn required_transport_mismatch() {
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    let server_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::UDP, "game::msg")
+        .unwrap();
+    let client_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let result = connect(server_parts, client_parts);
+    assert!(matches!(result, Err(Error::TransportMismatch(name)) if name == "game::msg"));
+}
+
+#[test]
+fn transport_mismatch() {
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    table
+        .register_optional::<Ping>(Transport::TCP, "ping_mod::ping")
+        .unwrap();
+    let server_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let mut table = SortedMsgTable::new();
+    table
+        .register::<TcpMsg>(Transport::TCP, "game::msg")
+        .unwrap();
+    table
+        .register_optional::<Ping>(Transport::UDP, "ping_mod::ping")
+        .unwrap();
+    let client_parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    // The optional type is disabled instead of refusing the connection.
+    let (client, server) = connect(server_parts, client_parts).unwrap();
+    let cid = server.cids().next().unwrap();
+    assert!(!server.is_enabled::<Ping>(cid));
+    assert!(matches!(client.send(&Ping(1)), Err(Error::DisabledType(_))));
+}
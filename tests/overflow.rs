@@ -0,0 +1,252 @@
+//! Tests the receive buffer limits.
+use crate::helper::create_client_server_pair_with;
+use crate::helper::test_messages::{get_table_parts, Connection, Response, TcpMsg, UdpMsg};
+use carrier_pigeon::net::{Config, KickReason, OverflowPolicy};
+use carrier_pigeon::{Client, Server};
+use simple_logger::SimpleLogger;
+use std::any::Any;
+use std::time::Duration;
+
+mod helper;
+
+/// Creates a client and server pair, and sends 5 TCP messages from the client to the server.
+fn spam(config: Config) -> (Client, Server) {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (client, mut server) = create_client_server_pair_with(config);
+
+    for i in 0..5 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+    (client, server)
+}
+
+/// Gets the text of the buffered [`TcpMsg`]s.
+fn tcp_msgs(server: &Server) -> Vec<String> {
+    server.recv::<TcpMsg>().map(|msg| msg.msg.clone()).collect()
+}
+
+#[test]
+fn drop_newest() {
+    let config = Config {
+        max_buffered_per_mid: Some(3),
+        overflow: OverflowPolicy::DropNewest,
+        ..Config::default()
+    };
+    let (_client, server) = spam(config);
+
+    assert_eq!(tcp_msgs(&server), vec!["TCP 0", "TCP 1", "TCP 2"]);
+    assert_eq!(server.dropped_msgs(), 2);
+    assert_eq!(server.dropped_msgs_from(1), 2);
+}
+
+#[test]
+fn drop_oldest() {
+    let config = Config {
+        max_buffered_per_mid: Some(3),
+        overflow: OverflowPolicy::DropOldest,
+        ..Config::default()
+    };
+    let (_client, mut server) = spam(config);
+
+    assert_eq!(tcp_msgs(&server), vec!["TCP 2", "TCP 3", "TCP 4"]);
+    assert_eq!(server.dropped_msgs(), 2);
+
+    // Draining makes room again.
+    assert_eq!(server.drain::<TcpMsg>().count(), 3);
+    assert_eq!(server.dropped_msgs(), 2);
+}
+
+#[test]
+fn disconnect() {
+    let config = Config {
+        max_buffered_per_mid: Some(3),
+        overflow: OverflowPolicy::Disconnect,
+        ..Config::default()
+    };
+    let (mut client, mut server) = spam(config);
+
+    assert_eq!(tcp_msgs(&server), vec!["TCP 0", "TCP 1", "TCP 2"]);
+    assert_eq!(server.dropped_msgs(), 1);
+
+    let mut statuses = vec![];
+    server.handle_disconnects(|cid, status| statuses.push((cid, status)));
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].0, 1);
+    assert_eq!(statuses[0].1.kicked(), Some(KickReason::BufferOverflow));
+    assert_eq!(server.cids().count(), 0);
+
+    // Give the disconnect enough time to arrive.
+    std::thread::sleep(Duration::from_millis(100));
+    client.recv_msgs();
+    assert!(!client.open());
+}
+
+#[test]
+fn per_cid() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        max_buffered_per_cid: Some(4),
+        overflow: OverflowPolicy::DropNewest,
+        ..Config::default()
+    };
+    let (client, mut server) = create_client_server_pair_with(config);
+
+    for i in 0..3 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+        client.send(&UdpMsg::new(format!("UDP {}", i))).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 6);
+
+    // TCP messages are received first.
+    assert_eq!(tcp_msgs(&server).len(), 3);
+    assert_eq!(server.recv::<UdpMsg>().count(), 1);
+    assert_eq!(server.dropped_msgs_from(1), 2);
+
+    // Clearing the messages makes room again.
+    server.clear_msgs();
+    client.send(&UdpMsg::new("UDP")).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 1);
+    assert_eq!(server.recv::<UdpMsg>().count(), 1);
+    assert_eq!(server.dropped_msgs(), 2);
+}
+
+#[test]
+fn per_cid_drop_oldest() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        max_buffered_per_cid: Some(4),
+        overflow: OverflowPolicy::DropOldest,
+        ..Config::default()
+    };
+    let (client, mut server) = create_client_server_pair_with(config);
+
+    for i in 0..3 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+        client.send(&UdpMsg::new(format!("UDP {}", i))).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 6);
+
+    // TCP messages are received first, so the oldest ones are dropped for the UDP messages.
+    assert_eq!(tcp_msgs(&server), vec!["TCP 2"]);
+    assert_eq!(server.recv::<UdpMsg>().count(), 3);
+    assert_eq!(server.dropped_msgs_from(1), 2);
+
+    // Taking a message makes room for one more.
+    assert!(server.take::<UdpMsg>().is_some());
+    for i in 3..5 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 2);
+    assert_eq!(tcp_msgs(&server), vec!["TCP 3", "TCP 4"]);
+    assert_eq!(server.recv::<UdpMsg>().count(), 2);
+    assert_eq!(server.dropped_msgs_from(1), 3);
+}
+
+/// Creates a server with two connected clients, with CIds 1 and 2.
+fn two_clients(config: Config) -> (Client, Client, Server) {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (first, mut server) = create_client_server_pair_with(config);
+    let second = Client::new(
+        server.listen_addr(),
+        get_table_parts(),
+        config,
+        Connection::new("Jane"),
+    );
+    while 0 == server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted)) {}
+    let (second, _) = second.block::<Response>().unwrap();
+    (first, second, server)
+}
+
+/// Sends `msgs` from `client`, and receives them on `server`, in order.
+fn send_all<T: Any + Send + Sync>(client: &Client, server: &mut Server, msgs: &[T]) {
+    for msg in msgs {
+        client.send(msg).unwrap();
+    }
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), msgs.len() as u32);
+}
+
+#[test]
+fn both_limits_drop_oldest() {
+    let config = Config {
+        max_buffered_per_mid: Some(3),
+        max_buffered_per_cid: Some(2),
+        overflow: OverflowPolicy::DropOldest,
+        ..Config::default()
+    };
+    let (first, second, mut server) = two_clients(config);
+
+    send_all(&first, &mut server, &[TcpMsg::new("First 0")]);
+    send_all(
+        &second,
+        &mut server,
+        &[TcpMsg::new("Second 0"), TcpMsg::new("Second 1")],
+    );
+
+    // Both limits are hit, so the second client's own oldest message is dropped, not the
+    // first client's.
+    send_all(&second, &mut server, &[TcpMsg::new("Second 2")]);
+    assert_eq!(tcp_msgs(&server), vec!["First 0", "Second 1", "Second 2"]);
+    assert_eq!(server.dropped_msgs_from(1), 0);
+    assert_eq!(server.dropped_msgs_from(2), 1);
+}
+
+#[test]
+fn both_limits_other_type() {
+    let config = Config {
+        max_buffered_per_mid: Some(2),
+        max_buffered_per_cid: Some(2),
+        overflow: OverflowPolicy::DropOldest,
+        ..Config::default()
+    };
+    let (first, second, mut server) = two_clients(config);
+
+    send_all(
+        &first,
+        &mut server,
+        &[UdpMsg::new("First 0"), UdpMsg::new("First 1")],
+    );
+    send_all(
+        &second,
+        &mut server,
+        &[TcpMsg::new("Second 0"), TcpMsg::new("Second 1")],
+    );
+
+    // The second client has no UDP message to drop, so it drops its oldest TCP message for its
+    // own limit, and the oldest UDP message is dropped for the limit of the type.
+    send_all(&second, &mut server, &[UdpMsg::new("Second 2")]);
+    let udp_msgs: Vec<_> = server.recv::<UdpMsg>().map(|msg| msg.msg.clone()).collect();
+    assert_eq!(udp_msgs, vec!["First 1", "Second 2"]);
+    assert_eq!(tcp_msgs(&server), vec!["Second 1"]);
+    assert_eq!(server.dropped_msgs_from(1), 1);
+    assert_eq!(server.dropped_msgs_from(2), 1);
+}
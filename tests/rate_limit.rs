@@ -0,0 +1,101 @@
+//! Tests the receive rate limits.
+use crate::helper::test_messages::{get_table_parts, TcpMsg};
+use crate::helper::{create_client_server_pair_from, create_client_server_pair_with};
+use carrier_pigeon::net::{Config, KickReason};
+use carrier_pigeon::rate_limit::{LimitKind, RateLimit, Violation, ViolationAction};
+use carrier_pigeon::{Client, Server};
+use simple_logger::SimpleLogger;
+use std::any::TypeId;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod helper;
+
+/// Sends 5 TCP messages from the client to the server, and receives them.
+fn spam(client: &Client, server: &mut Server) -> u32 {
+    for i in 0..5 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs()
+}
+
+/// Creates a client and server pair where [`TcpMsg`]s are limited to a burst of 2.
+fn create_limited_pair() -> (Client, Server) {
+    let mut parts = get_table_parts();
+    let mid = parts.tid_map[&TypeId::of::<TcpMsg>()];
+    parts.options[mid].recv_rate_limit = Some(RateLimit::new(1, 2));
+    create_client_server_pair_from(parts, Config::default())
+}
+
+#[test]
+fn connection_limit() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        recv_rate_limit: Some(RateLimit::new(1, 3)),
+        ..Config::default()
+    };
+    let (client, mut server) = create_client_server_pair_with(config);
+
+    // Without a hook, the messages over the limit are dropped.
+    assert_eq!(spam(&client, &mut server), 5);
+    let msgs: Vec<_> = server.recv::<TcpMsg>().map(|msg| msg.msg.clone()).collect();
+    assert_eq!(msgs, vec!["TCP 0", "TCP 1", "TCP 2"]);
+}
+
+#[test]
+fn type_limit_warn() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (client, mut server) = create_limited_pair();
+
+    let violations = Arc::new(Mutex::new(vec![]));
+    let hook_violations = violations.clone();
+    server.set_violation_hook(move |violation: Violation| {
+        hook_violations.lock().unwrap().push(violation);
+        ViolationAction::Warn
+    });
+
+    assert_eq!(spam(&client, &mut server), 5);
+    assert_eq!(server.recv::<TcpMsg>().count(), 5);
+
+    let violations = violations.lock().unwrap();
+    assert_eq!(violations.len(), 3);
+    assert!(violations
+        .iter()
+        .all(|v| v.cid == 1 && v.kind == LimitKind::MsgType));
+}
+
+#[test]
+fn type_limit_disconnect() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (mut client, mut server) = create_limited_pair();
+    server.set_violation_hook(|_| ViolationAction::Disconnect);
+
+    assert_eq!(spam(&client, &mut server), 3);
+    assert_eq!(server.recv::<TcpMsg>().count(), 2);
+
+    let mut statuses = vec![];
+    server.handle_disconnects(|cid, status| statuses.push((cid, status)));
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].0, 1);
+    assert_eq!(statuses[0].1.kicked(), Some(KickReason::RateLimit));
+
+    // Give the disconnect enough time to arrive.
+    std::thread::sleep(Duration::from_millis(100));
+    client.recv_msgs();
+    assert!(!client.open());
+}
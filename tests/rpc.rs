@@ -0,0 +1,58 @@
+//! Tests remote procedure calls.
+use crate::helper::create_client_server_pair_with;
+use crate::helper::test_messages::{Ping, Pong};
+use carrier_pigeon::net::Config;
+use carrier_pigeon::rpc::CallStatus;
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+#[test]
+fn call() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        call_timeout: Duration::from_millis(300),
+        ..Config::default()
+    };
+    let (mut client, mut server) = create_client_server_pair_with(config);
+
+    let first = client.call(Ping(1)).unwrap();
+    let second = client.call(Ping(2)).unwrap();
+    assert_ne!(first.id(), second.id());
+    assert_eq!(first.poll(&client), CallStatus::Pending);
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+
+    server.recv_msgs();
+    let answered = server.handle_calls::<Ping, Pong>(|cid, ping| {
+        assert_eq!(cid, 1);
+        Pong(ping.0 * 10)
+    });
+    assert_eq!(answered, 2);
+
+    // Give the server enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+
+    client.recv_msgs();
+    assert_eq!(second.poll(&client), CallStatus::Done(Pong(20)));
+    // The response is only yielded once.
+    assert_eq!(second.poll(&client), CallStatus::Pending);
+
+    // Poll the first call a frame late.
+    client.clear_msgs();
+    client.recv_msgs();
+    assert_eq!(first.poll(&client), CallStatus::Done(Pong(10)));
+
+    // Never answered.
+    let third = client.call(Ping(3)).unwrap();
+    std::thread::sleep(Duration::from_millis(400));
+    client.clear_msgs();
+    client.recv_msgs();
+    assert_eq!(third.poll(&client), CallStatus::TimedOut);
+}
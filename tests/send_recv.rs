@@ -1,6 +1,8 @@
 //! Simple send/receive tests.
-use crate::helper::create_client_server_pair;
-use crate::helper::test_messages::{TcpMsg, UdpMsg};
+use crate::helper::test_messages::{Ping, TcpMsg, UdpMsg};
+use crate::helper::{create_client_server_pair, create_client_server_pair_with};
+use carrier_pigeon::net::Config;
+use carrier_pigeon::rpc::RpcRequest;
 use simple_logger::SimpleLogger;
 use std::time::Duration;
 
@@ -95,3 +97,206 @@ fn send_recv() {
         assert!(udp_msgs.contains(&&UdpMsg::new(msg)));
     }
 }
+
+#[test]
+fn send_recv_batched() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        batch_udp: true,
+        ..Config::default()
+    };
+    let (mut client, mut server) = create_client_server_pair_with(config);
+
+    // Send 50 udp messages. These are queued until `send_queued` is called.
+    for i in 0..50 {
+        client
+            .send(&UdpMsg::new(format!("Test UDP Msg {}", i)))
+            .unwrap();
+    }
+    assert_eq!(client.bandwidth_stats().queued_msgs, 50);
+    assert_eq!(client.send_queued().unwrap(), 50);
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(server.recv_msgs(), 50);
+
+    // Despite UDP being unreliable, we are sending the messages through localhost
+    // so none should get lost.
+    let udp_msgs: Vec<_> = server.recv::<UdpMsg>().map(|m| m.m).collect();
+    for i in 0..50 {
+        let msg = format!("Test UDP Msg {}", i);
+        assert!(udp_msgs.contains(&&UdpMsg::new(msg)));
+    }
+
+    // SERVER TO CLIENT
+    for i in 0..50 {
+        server
+            .send_to(1, &UdpMsg::new(format!("Test UDP message {}", i)))
+            .unwrap();
+    }
+    assert_eq!(server.bandwidth_stats(1).unwrap().queued_msgs, 50);
+    assert_eq!(server.send_queued().unwrap(), 50);
+
+    // Give the server enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(client.recv_msgs(), 50);
+}
+
+#[test]
+fn send_recv_tick() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let config = Config {
+        tick_header: true,
+        ..Config::default()
+    };
+    let (mut client, mut server) = create_client_server_pair_with(config);
+
+    client.set_tick(7);
+    client.send(&TcpMsg::new("Test TCP Msg")).unwrap();
+    client.send(&UdpMsg::new("Test UDP Msg")).unwrap();
+    server.set_tick(12);
+    server.send_to(1, &TcpMsg::new("Test TCP Msg")).unwrap();
+
+    // Give the messages enough time to arrive.
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(server.recv_msgs(), 2);
+    assert_eq!(server.recv::<TcpMsg>().next().unwrap().tick, Some(7));
+    assert_eq!(server.recv::<UdpMsg>().next().unwrap().tick, Some(7));
+
+    assert_eq!(client.recv_msgs(), 1);
+    assert_eq!(client.recv::<TcpMsg>().next().unwrap().tick, Some(12));
+}
+
+#[test]
+fn recv_all_ordered() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (client, mut server) = create_client_server_pair();
+
+    // Interleave two message types sent over TCP, so the arrival order is known.
+    for i in 0..3 {
+        client.send(&TcpMsg::new(format!("TCP {}", i))).unwrap();
+        client.call(Ping(i)).unwrap();
+    }
+
+    // Give the client enough time to send the messages.
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.recv_msgs(), 6);
+
+    let msgs: Vec<_> = server.recv_all_ordered().collect();
+    assert_eq!(msgs.len(), 6);
+    assert!(msgs.windows(2).all(|w| w[0].seq < w[1].seq));
+    for (i, pair) in msgs.chunks(2).enumerate() {
+        let tcp = pair[0].downcast::<TcpMsg>().unwrap();
+        assert_eq!(tcp.cid, 1);
+        assert_eq!(tcp.msg, format!("TCP {}", i));
+        assert!(pair[1].is::<RpcRequest<Ping>>());
+        assert!(pair[1].downcast::<TcpMsg>().is_none());
+        assert_ne!(pair[0].mid, pair[1].mid);
+    }
+}
+
+#[test]
+fn drain_take() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (mut client, mut server) = create_client_server_pair();
+
+    for i in 0..3 {
+        server
+            .send_to(1, &TcpMsg::new(format!("TCP {}", i)))
+            .unwrap();
+    }
+    client.send(&TcpMsg::new("Test TCP Msg")).unwrap();
+
+    // Give the messages enough time to arrive.
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(client.recv_msgs(), 3);
+    let first = client.take::<TcpMsg>().unwrap();
+    assert_eq!(first.cid, 0);
+    assert_eq!(first.into_inner(), TcpMsg::new("TCP 0"));
+
+    let rest: Vec<_> = client.drain::<TcpMsg>().map(|msg| msg.m).collect();
+    assert_eq!(rest, vec![TcpMsg::new("TCP 1"), TcpMsg::new("TCP 2")]);
+    assert_eq!(client.recv::<TcpMsg>().count(), 0);
+    assert!(client.take::<TcpMsg>().is_none());
+
+    assert_eq!(server.recv_msgs(), 1);
+    let msgs: Vec<_> = server.drain::<TcpMsg>().collect();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].cid, 1);
+    assert_eq!(msgs[0].msg, "Test TCP Msg");
+    assert_eq!(server.recv::<TcpMsg>().count(), 0);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn send_recv_compressed() {
+    use crate::helper::create_client_server_pair_from;
+    use crate::helper::test_messages::{Connection, Disconnect, Response};
+    use carrier_pigeon::{MsgOptions, MsgTable, Transport};
+
+    let options = MsgOptions {
+        compress: true,
+        ..MsgOptions::default()
+    };
+    let mut table = MsgTable::new();
+    table
+        .register_with::<TcpMsg>(Transport::TCP, options)
+        .unwrap();
+    table
+        .register_with::<UdpMsg>(Transport::UDP, options)
+        .unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let (mut client, mut server) = create_client_server_pair_from(parts, Config::default());
+    let cid = server.cids().next().unwrap();
+
+    // Long enough to be over the compress threshold, and easy to compress.
+    let long = "compress me ".repeat(100);
+
+    client.send(&TcpMsg::new(long.clone())).unwrap();
+    client.send(&UdpMsg::new(long.clone())).unwrap();
+    server.send_to(cid, &TcpMsg::new(long.clone())).unwrap();
+    server.send_to(cid, &UdpMsg::new(long.clone())).unwrap();
+
+    std::thread::sleep(Duration::from_millis(100));
+
+    assert_eq!(server.recv_msgs(), 2);
+    assert_eq!(client.recv_msgs(), 2);
+
+    assert_eq!(
+        server.recv::<TcpMsg>().next().unwrap().m,
+        &TcpMsg::new(long.clone())
+    );
+    assert_eq!(
+        server.recv::<UdpMsg>().next().unwrap().m,
+        &UdpMsg::new(long.clone())
+    );
+    assert_eq!(
+        client.recv::<TcpMsg>().next().unwrap().m,
+        &TcpMsg::new(long.clone())
+    );
+    assert_eq!(
+        client.recv::<UdpMsg>().next().unwrap().m,
+        &UdpMsg::new(long)
+    );
+}
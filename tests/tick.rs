@@ -0,0 +1,62 @@
+//! Tests the [`TickRunner`].
+use crate::helper::create_client_server_pair;
+use crate::helper::test_messages::{Connection, Response, TcpMsg};
+use carrier_pigeon::net::CId;
+use carrier_pigeon::tick::{ServerHandler, Tick, TickRunner};
+use carrier_pigeon::Server;
+use simple_logger::SimpleLogger;
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+mod helper;
+
+#[derive(Default)]
+struct Handler {
+    ticks: Vec<Tick>,
+    msgs: Vec<String>,
+}
+
+impl ServerHandler for Handler {
+    type Connection = Connection;
+    type Response = Response;
+
+    fn on_connect(&mut self, _cid: CId, _con_msg: Connection) -> (bool, Response) {
+        (true, Response::Accepted)
+    }
+
+    fn on_messages(&mut self, server: &mut Server, _tick: Tick) {
+        self.msgs
+            .extend(server.recv::<TcpMsg>().map(|msg| msg.msg.clone()));
+    }
+
+    fn on_tick(&mut self, _server: &mut Server, tick: Tick) -> ControlFlow<()> {
+        self.ticks.push(tick);
+        if tick == 2 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+}
+
+#[test]
+fn run_server() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let (client, mut server) = create_client_server_pair();
+
+    client.send(&TcpMsg::new("Hello")).unwrap();
+    // Give the client enough time to send the message.
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut runner = TickRunner::new(100);
+    let mut handler = Handler::default();
+    runner.run_server(&mut server, &mut handler);
+
+    assert_eq!(runner.tick(), 3);
+    assert_eq!(handler.ticks, vec![0, 1, 2]);
+    assert_eq!(handler.msgs, vec!["Hello".to_owned()]);
+}
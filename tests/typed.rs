@@ -0,0 +1,148 @@
+//! Tests the [`TypedParts`] and the handling of the wrong connection, response and disconnect
+//! types.
+use crate::helper::test_messages::{Connection, Disconnect, Response, TcpMsg, UdpMsg};
+use crate::helper::ADDR_LOCAL;
+use carrier_pigeon::net::Config;
+use carrier_pigeon::{Client, Error, MsgTable, Server, Transport, TypedParts};
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+/// Builds a table with the test messages, remembering the connection, response and disconnect
+/// types.
+fn typed_parts() -> TypedParts<Connection, Response, Disconnect> {
+    let mut table = MsgTable::new();
+    table.register::<TcpMsg>(Transport::TCP).unwrap();
+    table.register::<UdpMsg>(Transport::UDP).unwrap();
+    table
+        .build_typed::<Connection, Response, Disconnect>()
+        .unwrap()
+}
+
+#[test]
+fn typed() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let parts = typed_parts();
+    let mut server = Server::listen(ADDR_LOCAL, parts.clone(), Config::default()).unwrap();
+    server.set_full_response(Response::rejected("Full"));
+
+    let client = Client::connect(
+        server.listen_addr(),
+        parts,
+        Config::default(),
+        Connection::new("John"),
+    );
+    while 0
+        == server.handle_new_cons(|_cid, con_msg| {
+            assert_eq!(con_msg, Connection::new("John"));
+            (true, Response::Accepted)
+        })
+    {}
+    let (mut client, resp) = client.block().unwrap();
+    assert_eq!(resp, Response::Accepted);
+
+    // The client disconnects.
+    client.disconnect(&Disconnect::new("Bye")).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+    let mut disconnects = vec![];
+    server.handle_disconnects(|cid, status| {
+        disconnects.push((cid, status.disconnected().cloned()));
+    });
+    assert_eq!(disconnects, vec![(1, Some(Disconnect::new("Bye")))]);
+}
+
+#[test]
+fn typed_server_disconnect() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let parts = typed_parts();
+    let mut server = Server::listen(ADDR_LOCAL, parts.clone(), Config::default()).unwrap();
+    let client = Client::connect(
+        server.listen_addr(),
+        parts,
+        Config::default(),
+        Connection::new("John"),
+    );
+    while 0 == server.handle_new_cons(|_cid, _con_msg| (true, Response::Accepted)) {}
+    let (mut client, _resp) = client.block().unwrap();
+
+    let cid = server.cids().next().unwrap();
+    server.disconnect(&Disconnect::new("Bye"), cid).unwrap();
+
+    std::thread::sleep(Duration::from_millis(100));
+    client.recv_msgs();
+    assert_eq!(
+        client.status().disconnected(),
+        Some(&Disconnect::new("Bye"))
+    );
+}
+
+#[test]
+fn wrong_types() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    // Without the types, the wrong types are rejected at runtime instead of panicking.
+    let mut server = Server::new(ADDR_LOCAL, typed_parts().erase(), Config::default()).unwrap();
+    assert!(matches!(
+        server.set_full_response(TcpMsg::new("Not the response")),
+        Err(Error::WrongType(_))
+    ));
+    let client = Client::new(
+        server.listen_addr(),
+        typed_parts().erase(),
+        Config::default(),
+        Connection::new("John"),
+    );
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(
+        server.handle_new_cons(|_cid, _con_msg: TcpMsg| (true, Response::Accepted)),
+        0
+    );
+
+    while 0 == server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted)) {}
+    let (mut client, _resp) = client.block::<Response>().unwrap();
+
+    let cid = server.cids().next().unwrap();
+    assert!(matches!(
+        server.disconnect(&TcpMsg::new("Not the disconnect"), cid),
+        Err(Error::WrongType(_))
+    ));
+    server.disconnect(&Disconnect::new("Bye"), cid).unwrap();
+
+    std::thread::sleep(Duration::from_millis(100));
+    client.recv_msgs();
+    assert!(client.status().disconnected::<TcpMsg>().is_none());
+    assert!(client.status().disconnected::<Disconnect>().is_some());
+}
+
+#[test]
+fn wrong_response_type() {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let parts = typed_parts();
+    let mut server = Server::new(ADDR_LOCAL, parts.clone().erase(), Config::default()).unwrap();
+    let client = Client::new(
+        server.listen_addr(),
+        parts.erase(),
+        Config::default(),
+        Connection::new("John"),
+    );
+
+    while 0 == server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted)) {}
+    assert!(matches!(client.block::<TcpMsg>(), Err(Error::WrongType(_))));
+}
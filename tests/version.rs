@@ -0,0 +1,225 @@
+//! Tests versioned messages between peers with different versions.
+use crate::helper::test_messages::{Connection, Disconnect, Response};
+use crate::helper::ADDR_LOCAL;
+use carrier_pigeon::net::Config;
+use carrier_pigeon::version::{Version, Versioned};
+use carrier_pigeon::{
+    Client, Error, MsgOptions, MsgRegError, MsgTable, MsgTableParts, Result, Server, Transport,
+};
+use serde::{Deserialize, Serialize};
+use simple_logger::SimpleLogger;
+use std::time::Duration;
+
+mod helper;
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct ChatV1 {
+    text: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+struct Chat {
+    text: String,
+    color: u32,
+}
+
+/// Builds the parts of a peer with version 1 of the chat message.
+fn v1_parts() -> MsgTableParts {
+    let mut table = MsgTable::new();
+    table
+        .register_versioned::<ChatV1>(Transport::TCP, MsgOptions::default(), Versioned::new(1))
+        .unwrap();
+    table.build::<Connection, Response, Disconnect>().unwrap()
+}
+
+/// Builds the parts of a peer with version `version` of the chat message, which can convert
+/// from and to version 1.
+fn new_parts(version: Version) -> MsgTableParts {
+    let mut table = MsgTable::new();
+    table
+        .register_versioned::<Chat>(
+            Transport::TCP,
+            MsgOptions::default(),
+            Versioned::new(version)
+                .upgrade_from(1, |old: ChatV1| Chat {
+                    text: old.text,
+                    color: 0,
+                })
+                .downgrade_to(1, |new: &Chat| ChatV1 {
+                    text: new.text.clone(),
+                }),
+        )
+        .unwrap();
+    table.build::<Connection, Response, Disconnect>().unwrap()
+}
+
+/// Connects a client with `client_parts` to a server with `server_parts`.
+fn connect(server_parts: MsgTableParts, client_parts: MsgTableParts) -> Result<(Client, Server)> {
+    // Create a simple logger
+    let _ = SimpleLogger::new()
+        .with_level(log::LevelFilter::Trace)
+        .init();
+
+    let mut server = Server::new(ADDR_LOCAL, server_parts, Config::default()).unwrap();
+    let client = Client::new(
+        server.listen_addr(),
+        client_parts,
+        Config::default(),
+        Connection::new("John"),
+    );
+
+    while !client.done() {
+        server.handle_new_cons(|_cid, _con_msg: Connection| (true, Response::Accepted));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let (client, _resp) = client.block::<Response>()?;
+    Ok((client, server))
+}
+
+#[test]
+fn old_client() {
+    let (mut client, mut server) = connect(new_parts(2), v1_parts()).unwrap();
+    let cid = server.cids().next().unwrap();
+    assert_eq!(client.versions()[3], 1);
+    assert_eq!(server.versions(cid).unwrap()[3], 1);
+
+    client
+        .send(&ChatV1 {
+            text: "Hi".to_owned(),
+        })
+        .unwrap();
+    server
+        .send_to(
+            cid,
+            &Chat {
+                text: "Hello".to_owned(),
+                color: 7,
+            },
+        )
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+    client.recv_msgs();
+
+    let msgs: Vec<_> = server.recv::<Chat>().map(|m| m.m.clone()).collect();
+    assert_eq!(
+        msgs,
+        vec![Chat {
+            text: "Hi".to_owned(),
+            color: 0,
+        }]
+    );
+    let msgs: Vec<_> = client.recv::<ChatV1>().map(|m| m.m.clone()).collect();
+    assert_eq!(
+        msgs,
+        vec![ChatV1 {
+            text: "Hello".to_owned(),
+        }]
+    );
+}
+
+#[test]
+fn old_server() {
+    let (client, mut server) = connect(v1_parts(), new_parts(2)).unwrap();
+    assert_eq!(client.versions()[3], 1);
+
+    client
+        .send(&Chat {
+            text: "Hi".to_owned(),
+            color: 7,
+        })
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    server.recv_msgs();
+
+    let msgs: Vec<_> = server.recv::<ChatV1>().map(|m| m.m.clone()).collect();
+    assert_eq!(
+        msgs,
+        vec![ChatV1 {
+            text: "Hi".to_owned(),
+        }]
+    );
+}
+
+#[test]
+fn same_version() {
+    let (client, server) = connect(new_parts(2), new_parts(2)).unwrap();
+    let cid = server.cids().next().unwrap();
+    assert_eq!(client.versions()[3], 2);
+    assert_eq!(server.versions(cid).unwrap()[3], 2);
+}
+
+#[test]
+fn incompatible() {
+    // Version 3 has no conversions from version 1.
+    let mut table = MsgTable::new();
+    table
+        .register_versioned::<Chat>(Transport::TCP, MsgOptions::default(), Versioned::new(3))
+        .unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let result = connect(parts, v1_parts());
+    assert!(matches!(
+        result,
+        Err(Error::IncompatibleVersion { version: 1, .. })
+    ));
+}
+
+#[test]
+fn one_way() {
+    // Version 1 can only be received, not sent, so it is not supported.
+    let mut table = MsgTable::new();
+    table
+        .register_versioned::<Chat>(
+            Transport::TCP,
+            MsgOptions::default(),
+            Versioned::new(2).upgrade_from(1, |old: ChatV1| Chat {
+                text: old.text,
+                color: 0,
+            }),
+        )
+        .unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+
+    let result = connect(parts, v1_parts());
+    assert!(matches!(
+        result,
+        Err(Error::IncompatibleVersion { version: 1, .. })
+    ));
+}
+
+#[test]
+fn not_older() {
+    let mut table = MsgTable::new();
+    let result = table.register_versioned::<Chat>(
+        Transport::TCP,
+        MsgOptions::default(),
+        Versioned::new(2).downgrade_to(2, |new: &Chat| ChatV1 {
+            text: new.text.clone(),
+        }),
+    );
+    assert_eq!(
+        result.err(),
+        Some(MsgRegError::VersionNotOlder {
+            version: 2,
+            current: 2
+        })
+    );
+    assert!(!table.is_registered::<Chat>());
+}
+
+#[test]
+fn versioned_options() {
+    let mut table = MsgTable::new();
+    let options = MsgOptions::new(5, Some(Duration::from_millis(100)));
+    let handle = table
+        .register_versioned::<Chat>(Transport::UDP, options, Versioned::new(2))
+        .unwrap();
+    let parts = table.build::<Connection, Response, Disconnect>().unwrap();
+    assert_eq!(parts.handle::<Chat>(), Some(handle));
+    assert_eq!(parts.options[handle.mid()].priority, 5);
+    assert_eq!(
+        parts.options[handle.mid()].ttl,
+        Some(Duration::from_millis(100))
+    );
+}